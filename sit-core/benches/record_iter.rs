@@ -0,0 +1,118 @@
+//! Compares record iteration against the previous (quadratic) algorithm
+//!
+//! Run with `cargo bench --bench record_iter`. The size of the repository
+//! can be changed with `SIT_BENCH_RECORDS` (number of records, 20000 by
+//! default) and `SIT_BENCH_GENERATIONS` (100 by default) environment variables.
+
+extern crate sit_core;
+extern crate tempdir;
+extern crate walkdir;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use sit_core::{Repository, Record};
+use sit_core::record::{RecordContainer, RecordOwningContainer};
+use sit_core::encoding::Encoding;
+use tempdir::TempDir;
+
+fn env_or(name: &str, default: usize) -> usize {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// Previous record iteration algorithm, kept here for comparison
+///
+/// Every generation re-partitions all remaining records, re-reading their
+/// `.prev` directories and looking parents up linearly.
+fn quadratic_generations(path: &Path, depth: usize, encoding: &Encoding) -> Vec<Vec<PathBuf>> {
+    let mut dir: Vec<_> = walkdir::WalkDir::new(path).min_depth(depth).max_depth(depth)
+        .into_iter().filter_map(Result::ok).collect();
+    let mut parents: Vec<String> = vec![];
+    let mut generations = vec![];
+    loop {
+        let (filtered, rest): (Vec<_>, Vec<_>) = dir.into_iter().partition(|e| {
+            if !e.path().is_dir() {
+                return false;
+            }
+            let dot_prev = e.path().join(".prev");
+            !dot_prev.is_dir() || match fs::read_dir(dot_prev) {
+                Err(_) => false,
+                Ok(links) => links.filter_map(Result::ok)
+                    .filter(|l| path.join(split_path(l.file_name().to_str().unwrap())).is_dir())
+                    .all(|l| parents.iter().any(|p| p.as_str() == l.file_name().to_str().unwrap())),
+            }
+        });
+        dir = rest;
+        if filtered.is_empty() {
+            break;
+        }
+        parents.extend(filtered.iter().map(|e| String::from(e.file_name().to_str().unwrap()))
+            .filter(|name| encoding.decode(name.as_bytes()).is_ok()));
+        generations.push(filtered.into_iter().map(|e| e.path().to_path_buf()).collect());
+    }
+    generations
+}
+
+fn split_path(s: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    let chars: Vec<char> = s.chars().collect();
+    for chunk in chars.chunks(2) {
+        path.push(chunk.iter().collect::<String>());
+    }
+    path
+}
+
+fn time<T, F: FnMut() -> T>(mut f: F) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+fn main() {
+    let records = env_or("SIT_BENCH_RECORDS", 20000);
+    let generations = env_or("SIT_BENCH_GENERATIONS", 100).max(1);
+    let width = (records / generations).max(1);
+
+    let tmp = TempDir::new("sit").unwrap();
+    let repo = Repository::new(tmp.path().join(".sit")).unwrap().with_integrity_check(false);
+
+    print!("Creating {} records in {} generations... ", width * generations, generations);
+    let (_, elapsed) = time(|| {
+        let mut previous: Vec<String> = vec![];
+        for generation in 0..generations {
+            let mut current = vec![];
+            for i in 0..width {
+                let content = format!("{}/{}", generation, i);
+                let mut files = vec![(String::from("test"), content.into_bytes())];
+                if !previous.is_empty() {
+                    // link to a couple of records of the previous generation
+                    files.push((format!(".prev/{}", previous[i % previous.len()]), vec![]));
+                    files.push((format!(".prev/{}", previous[(i * 7 + 3) % previous.len()]), vec![]));
+                    files.dedup();
+                }
+                let record = repo.new_record(files.iter().map(|(n, c)| (n.as_str(), &c[..])), false).unwrap();
+                current.push(record.encoded_hash());
+            }
+            previous = current;
+        }
+    });
+    println!("{:?}", elapsed);
+
+    let (result, elapsed) = time(|| repo.record_iter().unwrap().map(|g| g.len()).collect::<Vec<_>>());
+    println!("Record iteration: {:?}", elapsed);
+    assert_eq!(result.len(), generations);
+
+    let config = repo.config();
+    let encoding = config.encoding();
+    // every two characters of the encoded hash (including padding)
+    // make one level of directories
+    let depth = (encoding.encode_len(config.hashing_algorithm().len()) + 1) / 2;
+    let (quadratic, elapsed_quadratic) = time(|| quadratic_generations(repo.records_path(), depth, encoding));
+    println!("Previous record iteration: {:?}", elapsed_quadratic);
+    assert_eq!(quadratic.iter().map(|g| g.len()).collect::<Vec<_>>(), result);
+
+    let nanos = |d: Duration| d.as_secs() as f64 * 1e9 + d.subsec_nanos() as f64;
+    println!("Speedup: {:.1}x", nanos(elapsed_quadratic) / nanos(elapsed));
+}
@@ -0,0 +1,306 @@
+//! Bundles carry records between repositories that can't reach each other
+//!
+//! A bundle is a single file consisting of:
+//!
+//! * a magic line (`SITBUNDLE1`)
+//! * a line with the length of the header
+//! * JSON header with the hashing algorithm and encoding used to create the
+//!   records, and the list of records (parents first) along with locations of
+//!   their files in the archive
+//! * an archive with concatenated contents of all files of bundled records
+//!
+//! Bundles are applied with [`Repository::apply_bundle`].
+//!
+//! [`Repository::apply_bundle`]: ../repository/struct.Repository.html#method.apply_bundle
+
+use std::fs;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use tempdir::TempDir;
+
+use crate::encoding::Encoding;
+use crate::hash::HashingAlgorithm;
+use crate::pack::PackEntry;
+use crate::record::Record;
+use crate::repository::Error;
+
+const MAGIC: &[u8] = b"SITBUNDLE1\n";
+
+/// Bundle header
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleHeader {
+    hashing_algorithm: HashingAlgorithm,
+    encoding: Encoding,
+    records: Vec<BundleEntry>,
+}
+
+/// Record's files in the archive
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleEntry {
+    hash: String,
+    files: Vec<PackEntry>,
+}
+
+/// Writes a new bundle
+pub struct BundleWriter {
+    tempdir: TempDir,
+    archive: io::BufWriter<fs::File>,
+    header: BundleHeader,
+    offset: u64,
+}
+
+impl BundleWriter {
+    /// Starts a new bundle of records created with given hashing algorithm and encoding
+    pub fn new(hashing_algorithm: &HashingAlgorithm, encoding: &Encoding) -> Result<Self, Error> {
+        let tempdir = TempDir::new("sit")?;
+        let archive = io::BufWriter::new(fs::File::create(tempdir.path().join("archive"))?);
+        Ok(BundleWriter {
+            tempdir,
+            archive,
+            header: BundleHeader {
+                hashing_algorithm: hashing_algorithm.clone(),
+                encoding: encoding.clone(),
+                records: vec![],
+            },
+            offset: 0,
+        })
+    }
+
+    /// Adds a record to the bundle
+    ///
+    /// Records should be added parents first.
+    pub fn add<R: Record>(&mut self, record: &R) -> Result<(), Error> {
+        let mut files = vec![];
+        for (name, mut reader) in record.file_iter() {
+            let length = io::copy(&mut reader, &mut self.archive)?;
+            files.push(PackEntry { name: name.as_ref().into(), offset: self.offset, length });
+            self.offset += length;
+        }
+        self.header.records.push(BundleEntry { hash: record.encoded_hash().as_ref().into(), files });
+        Ok(())
+    }
+
+    /// Returns the number of records added to the bundle
+    pub fn len(&self) -> usize {
+        self.header.records.len()
+    }
+
+    /// Returns true if nothing was added to the bundle
+    pub fn is_empty(&self) -> bool {
+        self.header.records.is_empty()
+    }
+
+    /// Finishes the bundle, writing it out
+    pub fn finish<W: Write>(self, mut writer: W) -> Result<(), Error> {
+        let BundleWriter { tempdir, archive, header, .. } = self;
+        archive.into_inner().map_err(|e| Error::OtherError(format!("{}", e)))?;
+        let header = serde_json::to_vec(&header)?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(format!("{}\n", header.len()).as_bytes())?;
+        writer.write_all(&header)?;
+        io::copy(&mut fs::File::open(tempdir.path().join("archive"))?, &mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Bundle of records
+#[derive(Debug)]
+pub struct Bundle {
+    hashing_algorithm: HashingAlgorithm,
+    encoding: Encoding,
+    records: Vec<BundleRecord>,
+}
+
+impl Bundle {
+    /// Opens a bundle
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        let mut reader = io::BufReader::new(fs::File::open(&path)?);
+        let mut magic = vec![0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::OtherError(format!("{} is not a bundle", path.display())));
+        }
+        let mut line = vec![];
+        reader.read_until(b'\n', &mut line)?;
+        let length: u64 = std::str::from_utf8(&line).ok()
+            .and_then(|l| l.trim().parse().ok())
+            .ok_or_else(|| Error::OtherError(format!("{} has an invalid header", path.display())))?;
+        let header: BundleHeader = serde_json::from_reader(reader.take(length))?;
+        let data_offset = (MAGIC.len() + line.len()) as u64 + length;
+
+        let BundleHeader { hashing_algorithm, encoding, records } = header;
+        let path = Arc::new(path);
+        let records = records.into_iter()
+            .map(|entry| Ok(BundleRecord {
+                hash: encoding.decode(entry.hash.as_bytes())?,
+                encoded_hash: entry.hash,
+                path: path.clone(),
+                data_offset,
+                files: entry.files,
+            }))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Bundle { hashing_algorithm, encoding, records })
+    }
+
+    /// Returns hashing algorithm records were created with
+    pub fn hashing_algorithm(&self) -> &HashingAlgorithm {
+        &self.hashing_algorithm
+    }
+
+    /// Returns encoding records were created with
+    pub fn encoding(&self) -> &Encoding {
+        &self.encoding
+    }
+
+    /// Returns bundled records (parents first)
+    pub fn records(&self) -> impl Iterator<Item = BundleRecord> + '_ {
+        self.records.iter().cloned()
+    }
+}
+
+/// Record residing in a [`Bundle`]
+///
+/// [`Bundle`]: struct.Bundle.html
+#[derive(Debug, Clone)]
+pub struct BundleRecord {
+    hash: Vec<u8>,
+    encoded_hash: String,
+    path: Arc<PathBuf>,
+    data_offset: u64,
+    files: Vec<PackEntry>,
+}
+
+impl BundleRecord {
+    /// Returns path to the bundle
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+}
+
+impl PartialEq for BundleRecord {
+    fn eq(&self, other: &BundleRecord) -> bool {
+        self.hash == other.hash
+    }
+}
+
+impl Record for BundleRecord {
+    type Read = io::Take<fs::File>;
+    type Str = String;
+    type Hash = Vec<u8>;
+    type Iter = BundleRecordFileIterator;
+
+    fn hash(&self) -> Self::Hash {
+        self.hash.clone()
+    }
+
+    fn encoded_hash(&self) -> Self::Str {
+        self.encoded_hash.clone()
+    }
+
+    fn file_iter(&self) -> Self::Iter {
+        BundleRecordFileIterator {
+            record: self.clone(),
+            index: 0,
+        }
+    }
+
+    #[cfg(feature = "deprecated-item-api")]
+    fn item_id(&self) -> Self::Str {
+        String::new()
+    }
+}
+
+/// An iterator over files in [`BundleRecord`]
+///
+/// Files that can't be read are skipped (and therefore the record
+/// will fail the integrity check).
+///
+/// [`BundleRecord`]: struct.BundleRecord.html
+pub struct BundleRecordFileIterator {
+    record: BundleRecord,
+    index: usize,
+}
+
+impl Iterator for BundleRecordFileIterator {
+    type Item = (String, io::Take<fs::File>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = self.record.files.get(self.index)?;
+            self.index += 1;
+            let file = fs::File::open(self.record.path.as_path())
+                .and_then(|mut f| f.seek(SeekFrom::Start(self.record.data_offset + entry.offset)).map(|_| f));
+            if let Ok(file) = file {
+                return Some((entry.name.clone(), file.take(entry.length)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Repository;
+    use crate::repository::Config;
+    use crate::record::{RecordContainer, RecordOwningContainer};
+
+    fn bundle<RC: RecordContainer>(container: &RC, config: &Config) -> PathBuf {
+        let path = TempDir::new("sit").unwrap().into_path().join("bundle");
+        let mut writer = BundleWriter::new(config.hashing_algorithm(), config.encoding()).unwrap();
+        for record in container.record_iter().unwrap().flat_map(|records| records) {
+            writer.add(&record).unwrap();
+        }
+        writer.finish(fs::File::create(&path).unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn create_and_apply() {
+        let repo = Repository::in_memory(Config::default());
+        let record1 = repo.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        let record2 = repo.new_record(vec![("test", &[2u8][..]), ("test2", &[3u8][..])].into_iter(), true).unwrap();
+        let path = bundle(&repo, repo.config());
+
+        let bundle = Bundle::open(&path).unwrap();
+        let records: Vec<_> = bundle.records().collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].encoded_hash(), record1.encoded_hash());
+        assert_eq!(records[1].encoded_hash(), record2.encoded_hash());
+        assert!(records[1].integrity_intact(repo.config().hashing_algorithm()));
+
+        let target = Repository::in_memory(Config::default());
+        let report = target.apply_bundle(&bundle).unwrap();
+        assert_eq!(report.imported, vec![record1.encoded_hash(), record2.encoded_hash()]);
+        assert_eq!(target.record(record2.encoded_hash()).unwrap(), record2);
+        assert_eq!(target.apply_bundle(&bundle).unwrap().present.len(), 2);
+    }
+
+    #[test]
+    fn corrupted() {
+        let repo = Repository::in_memory(Config::default());
+        let record = repo.new_record(vec![("test", &b"hello"[..])].into_iter(), false).unwrap();
+        let path = bundle(&repo, repo.config());
+        let mut contents = fs::read(&path).unwrap();
+        let len = contents.len();
+        contents[len - 1] = b'!';
+        fs::write(&path, contents).unwrap();
+
+        let target = Repository::in_memory(Config::default());
+        let report = target.apply_bundle(&Bundle::open(&path).unwrap()).unwrap();
+        assert_eq!(report.invalid, vec![record.encoded_hash()]);
+        assert!(target.record(record.encoded_hash()).is_none());
+    }
+
+    #[test]
+    fn not_a_bundle() {
+        let path = TempDir::new("sit").unwrap().into_path().join("bundle");
+        fs::write(&path, b"SITBUNDLE0\n").unwrap();
+        assert!(Bundle::open(&path).is_err());
+    }
+}
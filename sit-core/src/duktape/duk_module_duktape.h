@@ -10,8 +10,10 @@ extern "C" {
 /* Maximum length of CommonJS module identifier to resolve.  Length includes
  * both current module ID, requested (possibly relative) module ID, and a
  * slash in between.
+ *
+ * sit: module IDs are (absolute) file paths, so the limit is raised from 256.
  */
-#define  DUK_COMMONJS_MODULE_ID_LIMIT  256
+#define  DUK_COMMONJS_MODULE_ID_LIMIT  4096
 
 extern void duk_module_duktape_init(duk_context *ctx);
 
@@ -3,14 +3,39 @@
 use serde_derive::{Deserialize, Serialize};
 use data_encoding_macro::*;
 
+use data_encoding::{self, DecodeError, DecodeKind};
+
 /// Available encodings
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Every encoding produces identifiers of the same length for inputs
+/// of the same length, which is what allows record directories to be
+/// laid out with a fixed depth.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Encoding {
     /// [Base32] encoding
     ///
     /// [Base32]: https://en.wikipedia.org/wiki/Base32
     #[serde(rename = "base32")]
     Base32,
+    /// Lowercase [Base32] encoding without padding
+    ///
+    /// [Base32]: https://en.wikipedia.org/wiki/Base32
+    #[serde(rename = "base32lower")]
+    Base32Lower,
+    /// Lowercase [hexadecimal] encoding
+    ///
+    /// [hexadecimal]: https://en.wikipedia.org/wiki/Hexadecimal
+    #[serde(rename = "hex")]
+    Hex,
+    /// [Base58] encoding (Bitcoin alphabet)
+    ///
+    /// Unlike the conventional Base58, leading zero bytes are not encoded
+    /// separately: the value is always encoded with the number of digits
+    /// necessary to represent any input of the same length.
+    ///
+    /// [Base58]: https://en.wikipedia.org/wiki/Base58
+    #[serde(rename = "base58")]
+    Base58,
 }
 
 
@@ -20,16 +45,50 @@ impl Default for Encoding {
     }
 }
 
-use data_encoding;
-
-use std::ops::Deref;
+impl Encoding {
+    /// Encodes input
+    pub fn encode(&self, input: &[u8]) -> String {
+        match self {
+            &Encoding::Base32 => BASE32_DASHPAD.encode(input),
+            &Encoding::Base32Lower => BASE32_LOWER_NOPAD.encode(input),
+            &Encoding::Hex => data_encoding::HEXLOWER.encode(input),
+            &Encoding::Base58 => base58_encode(input),
+        }
+    }
 
-impl Deref for Encoding {
-    type Target = data_encoding::Encoding;
+    /// Decodes input
+    pub fn decode(&self, input: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        match self {
+            &Encoding::Base32 => BASE32_DASHPAD.decode(input),
+            &Encoding::Base32Lower => BASE32_LOWER_NOPAD.decode(input),
+            &Encoding::Hex => data_encoding::HEXLOWER.decode(input),
+            &Encoding::Base58 => base58_decode(input),
+        }
+    }
 
-    fn deref(&self) -> &Self::Target {
+    /// Returns the length of encoded input of a given length
+    pub fn encode_len(&self, len: usize) -> usize {
         match self {
-            &Encoding::Base32 => &BASE32_DASHPAD,
+            &Encoding::Base32 => BASE32_DASHPAD.encode_len(len),
+            &Encoding::Base32Lower => BASE32_LOWER_NOPAD.encode_len(len),
+            &Encoding::Hex => data_encoding::HEXLOWER.encode_len(len),
+            &Encoding::Base58 => base58_len(len),
+        }
+    }
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = String;
+
+    /// Parses encoding names as they appear in the configuration
+    /// (`base32`, `base32lower`, `hex` and `base58`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base32" => Ok(Encoding::Base32),
+            "base32lower" => Ok(Encoding::Base32Lower),
+            "hex" => Ok(Encoding::Hex),
+            "base58" => Ok(Encoding::Base58),
+            _ => Err(format!("unknown encoding {}", s)),
         }
     }
 }
@@ -39,3 +98,101 @@ const BASE32_DASHPAD: data_encoding::Encoding = new_encoding!{
     padding: '-',
 };
 
+const BASE32_LOWER_NOPAD: data_encoding::Encoding = new_encoding!{
+    symbols: "abcdefghijklmnopqrstuvwxyz234567",
+};
+
+const BASE58_SYMBOLS: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Returns the number of Base58 digits necessary to represent any input of a given length
+fn base58_len(len: usize) -> usize {
+    // 58^n is never a power of two, so the result is never exact
+    (len as f64 * 8.0 / 58f64.log2()).ceil() as usize
+}
+
+fn base58_encode(input: &[u8]) -> String {
+    let mut digits = vec![0u8; base58_len(input.len())];
+    for &byte in input {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut().rev() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+    }
+    digits.into_iter().map(|d| BASE58_SYMBOLS[d as usize] as char).collect()
+}
+
+fn base58_decode(input: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let len = (0..).take_while(|&n| base58_len(n) <= input.len()).last().unwrap_or(0);
+    if base58_len(len) != input.len() {
+        return Err(DecodeError { position: 0, kind: DecodeKind::Length });
+    }
+    let mut bytes = vec![0u8; len];
+    for (position, symbol) in input.iter().enumerate() {
+        let mut carry = BASE58_SYMBOLS.iter().position(|s| s == symbol)
+            .ok_or(DecodeError { position, kind: DecodeKind::Symbol })? as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        if carry != 0 {
+            // the value doesn't fit into the number of bytes implied by input's length
+            return Err(DecodeError { position, kind: DecodeKind::Symbol });
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODINGS: &[Encoding] = &[Encoding::Base32, Encoding::Base32Lower, Encoding::Hex, Encoding::Base58];
+
+    #[test]
+    fn roundtrip() {
+        for encoding in ENCODINGS {
+            for input in &[&[][..], &[0][..], &[0, 0, 1][..], &[255; 20][..], &[0; 32][..], &b"testthat"[..]] {
+                let encoded = encoding.encode(input);
+                assert_eq!(encoded.len(), encoding.encode_len(input.len()));
+                assert_eq!(encoding.decode(encoded.as_bytes()).unwrap(), input.to_vec());
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_length() {
+        for encoding in ENCODINGS {
+            assert_eq!(encoding.encode(&[0; 20]).len(), encoding.encode(&[255; 20]).len());
+        }
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(Encoding::Base32.encode(b"test"), "ORSXG5A-");
+        assert_eq!(Encoding::Base32Lower.encode(b"test"), "orsxg5a");
+        assert_eq!(Encoding::Hex.encode(b"test"), "74657374");
+        assert_eq!(Encoding::Base58.encode(b"test"), "3yZe7d");
+        assert_eq!(Encoding::Base58.encode(&[0, 0, 1]), "11112");
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!("base32lower".parse(), Ok(Encoding::Base32Lower));
+        assert_eq!("base58".parse(), Ok(Encoding::Base58));
+        assert!("base64".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(Encoding::Base32Lower.decode(b"ORSXG5A").is_err());
+        assert!(Encoding::Hex.decode(b"7465737").is_err());
+        assert!(Encoding::Base58.decode(b"3yZe70").is_err());
+        // doesn't fit into 4 bytes
+        assert!(Encoding::Base58.decode(b"zzzzzz").is_err());
+        // no input length encodes into 4 digits
+        assert!(Encoding::Base58.decode(b"1111").is_err());
+    }
+}
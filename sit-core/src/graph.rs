@@ -0,0 +1,296 @@
+//! Record graph
+//!
+//! Records form a directed acyclic graph through their `.prev/` links.
+//! [`RecordGraph`] allows to query this graph. Typically, it is obtained
+//! through [`RecordContainer::graph`].
+//!
+//! [`RecordGraph`]: struct.RecordGraph.html
+//! [`RecordContainer::graph`]: ../record/trait.RecordContainer.html#method.graph
+
+use std::collections::HashMap;
+
+use crate::Record;
+
+/// Graph of records
+///
+/// Records are kept in the order they were iterated over, generation
+/// by generation, and all queries return records in this order.
+#[derive(Debug)]
+pub struct RecordGraph<R: Record> {
+    records: Vec<R>,
+    index: HashMap<String, usize>,
+    parents: Vec<Vec<usize>>,
+    children: Vec<Vec<usize>>,
+    heads: Vec<usize>,
+}
+
+impl<R: Record> RecordGraph<R> {
+    /// Builds a graph out of generations of records (as returned by [`RecordContainer::record_iter`])
+    ///
+    /// Links to records that are not in the graph are ignored.
+    ///
+    /// [`RecordContainer::record_iter`]: ../record/trait.RecordContainer.html#tymethod.record_iter
+    pub fn new<G: IntoIterator<Item = R>, I: IntoIterator<Item = G>>(generations: I) -> Self {
+        let mut records = vec![];
+        let mut heads = vec![];
+        for generation in generations {
+            heads = vec![];
+            for record in generation {
+                heads.push(records.len());
+                records.push(record);
+            }
+        }
+        let index: HashMap<String, usize> = records.iter().enumerate()
+            .map(|(i, r)| (String::from(r.encoded_hash().as_ref()), i))
+            .collect();
+        let parents: Vec<Vec<usize>> = records.iter()
+            .map(|r| {
+                let mut parents: Vec<_> = r.parents().iter().filter_map(|p| index.get(p).cloned()).collect();
+                parents.sort();
+                parents.dedup();
+                parents
+            })
+            .collect();
+        let mut children = vec![vec![]; records.len()];
+        for (i, parents) in parents.iter().enumerate() {
+            for &parent in parents {
+                children[parent].push(i);
+            }
+        }
+        RecordGraph { records, index, parents, children, heads }
+    }
+
+    /// Returns all records
+    pub fn records(&self) -> &[R] {
+        &self.records
+    }
+
+    /// Finds a record by its encoded hash
+    pub fn record<S: AsRef<str>>(&self, hash: S) -> Option<&R> {
+        self.index.get(hash.as_ref()).map(|&i| &self.records[i])
+    }
+
+    /// Returns record's parents
+    pub fn parents<S: AsRef<str>>(&self, hash: S) -> Vec<&R> {
+        self.get(self.parent_indices(hash))
+    }
+
+    /// Returns record's children
+    pub fn children<S: AsRef<str>>(&self, hash: S) -> Vec<&R> {
+        self.get(self.child_indices(hash))
+    }
+
+    /// Returns the last generation of records (the ones new records get linked to)
+    pub fn heads(&self) -> Vec<&R> {
+        self.get(self.heads.clone())
+    }
+
+    /// Returns all records the record descends from
+    pub fn ancestors<S: AsRef<str>>(&self, hash: S) -> Vec<&R> {
+        self.get(self.ancestor_indices(hash))
+    }
+
+    /// Returns all records that descend from the record
+    pub fn descendants<S: AsRef<str>>(&self, hash: S) -> Vec<&R> {
+        self.get(self.descendant_indices(hash))
+    }
+
+    /// Consumes the graph, returning its records in the same order
+    pub fn into_records(self) -> Vec<R> {
+        self.records
+    }
+
+    /// Consumes the graph, returning records selected by `f` (as indices in ascending order)
+    pub(crate) fn select<F: FnOnce(&Self) -> Vec<usize>>(self, f: F) -> Vec<R> {
+        let mut indices = f(&self).into_iter().peekable();
+        self.records.into_iter().enumerate()
+            .filter_map(|(i, r)| if indices.peek() == Some(&i) {
+                indices.next();
+                Some(r)
+            } else {
+                None
+            })
+            .collect()
+    }
+
+    pub(crate) fn parent_indices<S: AsRef<str>>(&self, hash: S) -> Vec<usize> {
+        self.index.get(hash.as_ref()).map(|&i| self.parents[i].clone()).unwrap_or(vec![])
+    }
+
+    pub(crate) fn child_indices<S: AsRef<str>>(&self, hash: S) -> Vec<usize> {
+        self.index.get(hash.as_ref()).map(|&i| self.children[i].clone()).unwrap_or(vec![])
+    }
+
+    pub(crate) fn ancestor_indices<S: AsRef<str>>(&self, hash: S) -> Vec<usize> {
+        self.reachable(hash, &self.parents)
+    }
+
+    pub(crate) fn descendant_indices<S: AsRef<str>>(&self, hash: S) -> Vec<usize> {
+        self.reachable(hash, &self.children)
+    }
+
+    fn get(&self, indices: Vec<usize>) -> Vec<&R> {
+        indices.into_iter().map(|i| &self.records[i]).collect()
+    }
+
+    fn reachable<S: AsRef<str>>(&self, hash: S, edges: &[Vec<usize>]) -> Vec<usize> {
+        let start = match self.index.get(hash.as_ref()) {
+            None => return vec![],
+            Some(&i) => i,
+        };
+        let mut visited = vec![false; self.records.len()];
+        let mut pending = edges[start].clone();
+        while let Some(i) = pending.pop() {
+            if !visited[i] {
+                visited[i] = true;
+                pending.extend(edges[i].iter().cloned());
+            }
+        }
+        visited.iter().enumerate().filter(|&(_, v)| *v).map(|(i, _)| i).collect()
+    }
+}
+
+/// Orders a graph (described by indices of nodes) generation by generation
+///
+/// Every generation consists of nodes whose parents have all been returned in
+/// preceding generations. Nodes within a generation are ordered by their index.
+///
+/// The graph is built once, so iterating through all generations takes time
+/// proportional to the number of nodes and links (plus sorting every generation).
+#[derive(Debug)]
+pub(crate) struct Generations {
+    /// Children of every node
+    children: Vec<Vec<usize>>,
+    /// Number of parents that haven't been returned yet
+    pending: Vec<usize>,
+    /// Next generation
+    ready: Vec<usize>,
+}
+
+impl Generations {
+    /// Creates an iterator over generations of a graph
+    ///
+    /// `parents` contains indices of every node's parents, or `None` if the
+    /// node can never be ordered (such as when some of its links can't be read);
+    /// such nodes and their descendants are never returned.
+    pub(crate) fn new(parents: Vec<Option<Vec<usize>>>) -> Self {
+        let mut children = vec![vec![]; parents.len()];
+        let mut pending = vec![0; parents.len()];
+        let mut ready = vec![];
+        for (node, parents) in parents.into_iter().enumerate() {
+            match parents {
+                None => pending[node] = usize::max_value(),
+                Some(mut parents) => {
+                    parents.sort();
+                    parents.dedup();
+                    pending[node] = parents.len();
+                    if parents.is_empty() {
+                        ready.push(node);
+                    }
+                    for parent in parents {
+                        children[parent].push(node);
+                    }
+                }
+            }
+        }
+        Generations { children, pending, ready }
+    }
+}
+
+impl Iterator for Generations {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ready.is_empty() {
+            return None;
+        }
+        let generation = std::mem::replace(&mut self.ready, vec![]);
+        for &node in generation.iter() {
+            for &child in self.children[node].iter() {
+                let pending = &mut self.pending[child];
+                if *pending != usize::max_value() {
+                    *pending -= 1;
+                    if *pending == 0 {
+                        self.ready.push(child);
+                    }
+                }
+            }
+        }
+        self.ready.sort();
+        Some(generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Repository;
+    use crate::repository::Config;
+    use crate::record::{RecordContainer, RecordOwningContainer};
+
+    #[test]
+    fn record_graph() {
+        let repo = Repository::in_memory(Config::default());
+        let record1 = repo.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        let record2 = repo.new_record(vec![("test", &[2u8][..])].into_iter(), false).unwrap();
+        let record3 = repo.new_record(vec![("test", &[3u8][..])].into_iter(), true).unwrap();
+        let record4 = repo.new_record(vec![("test", &[4u8][..])].into_iter(), false).unwrap();
+        let record5 = repo.new_record(vec![("test", &[5u8][..])].into_iter(), true).unwrap();
+        let graph = repo.graph().unwrap();
+
+        assert_eq!(graph.records().len(), 5);
+        assert_eq!(graph.record(record1.encoded_hash()), Some(&record1));
+        assert_eq!(graph.record("missing"), None);
+
+        let mut parents = graph.parents(record3.encoded_hash());
+        parents.sort_by_key(|r| r.encoded_hash());
+        let mut expected = vec![&record1, &record2];
+        expected.sort_by_key(|r| r.encoded_hash());
+        assert_eq!(parents, expected);
+        assert_eq!(graph.parents(record5.encoded_hash()), vec![&record3]);
+        assert!(graph.parents(record1.encoded_hash()).is_empty());
+
+        assert_eq!(graph.children(record1.encoded_hash()), vec![&record3]);
+        assert_eq!(graph.children(record3.encoded_hash()), vec![&record5]);
+        assert!(graph.children(record4.encoded_hash()).is_empty());
+
+        assert_eq!(graph.heads(), vec![&record5]);
+
+        let ancestors = graph.ancestors(record5.encoded_hash());
+        assert_eq!(ancestors.len(), 3);
+        assert_eq!(ancestors[2], &record3);
+        assert!(graph.ancestors(record4.encoded_hash()).is_empty());
+
+        assert_eq!(graph.descendants(record2.encoded_hash()), vec![&record3, &record5]);
+        assert!(graph.descendants("missing").is_empty());
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(Generations::new(vec![]).next(), None);
+    }
+
+    #[test]
+    fn ordering() {
+        let generations: Vec<_> = Generations::new(vec![
+            Some(vec![3]),
+            Some(vec![]),
+            Some(vec![0, 1]),
+            Some(vec![]),
+            Some(vec![1, 1]),
+        ]).collect();
+        assert_eq!(generations, vec![vec![1, 3], vec![0, 4], vec![2]]);
+    }
+
+    #[test]
+    fn unordered_nodes() {
+        let generations: Vec<_> = Generations::new(vec![
+            Some(vec![]),
+            None,
+            Some(vec![1]),
+            Some(vec![0]),
+            Some(vec![4]),
+        ]).collect();
+        assert_eq!(generations, vec![vec![0], vec![3]]);
+    }
+}
@@ -3,7 +3,7 @@ use serde_derive::{Deserialize, Serialize};
 
 /// Enumerates known hashing algorithm. Its content depends on features
 /// enabled during build-time
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum HashingAlgorithm {
     #[cfg(feature = "blake2")]
     #[serde(rename = "blake2b")]
@@ -20,6 +20,18 @@ pub enum HashingAlgorithm {
     ///
     /// [SHA-1]: https://en.wikipedia.org/wiki/SHA-1
     SHA1,
+    #[cfg(feature = "sha2")]
+    #[serde(rename = "sha256")]
+    /// [SHA-256] algorithm
+    ///
+    /// [SHA-256]: https://en.wikipedia.org/wiki/SHA-2
+    SHA256,
+    #[cfg(feature = "blake3")]
+    #[serde(rename = "blake3")]
+    /// [BLAKE3] algorithm
+    ///
+    /// [BLAKE3]: https://github.com/BLAKE3-team/BLAKE3
+    Blake3,
 }
 
 impl Default for HashingAlgorithm {
@@ -44,6 +56,10 @@ impl Default for HashingAlgorithm {
 use blake2;
 #[cfg(feature = "sha-1")]
 use sha1;
+#[cfg(feature = "sha2")]
+use sha2;
+#[cfg(feature = "blake3")]
+use blake3;
 
 
 use digest::{FixedOutput, VariableOutput, Input};
@@ -98,6 +114,24 @@ impl<T: VariableOutput + Input> Hasher for VariableOutputHasher<T> {
 }
 
 
+/// Wraps BLAKE3 (which doesn't implement `digest` traits)
+#[cfg(feature = "blake3")]
+struct Blake3Hasher(blake3::Hasher);
+
+#[cfg(feature = "blake3")]
+impl Hasher for Blake3Hasher {
+    fn process(&mut self, input: &[u8]) {
+        self.0.update(input);
+    }
+
+    fn result(self) -> Vec<u8> {
+        self.0.finalize().as_bytes().to_vec()
+    }
+
+    fn result_box(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().as_bytes().to_vec()
+    }
+}
 
 impl HashingAlgorithm {
 
@@ -110,6 +144,10 @@ impl HashingAlgorithm {
             &HashingAlgorithm::Blake2b { size } => Box::new(VariableOutputHasher(blake2::Blake2b::new(size).unwrap())),
             #[cfg(feature = "sha-1")]
             &HashingAlgorithm::SHA1 => Box::new(FixedOutputHasher(sha1::Sha1::default())),
+            #[cfg(feature = "sha2")]
+            &HashingAlgorithm::SHA256 => Box::new(FixedOutputHasher(sha2::Sha256::default())),
+            #[cfg(feature = "blake3")]
+            &HashingAlgorithm::Blake3 => Box::new(Blake3Hasher(blake3::Hasher::new())),
         }
     }
 
@@ -120,11 +158,40 @@ impl HashingAlgorithm {
             &HashingAlgorithm::Blake2b { size } => size,
             #[cfg(feature = "sha-1")]
             &HashingAlgorithm::SHA1 => 20,
+            #[cfg(feature = "sha2")]
+            &HashingAlgorithm::SHA256 => 32,
+            #[cfg(feature = "blake3")]
+            &HashingAlgorithm::Blake3 => 32,
         }
     }
 
 }
 
+impl std::str::FromStr for HashingAlgorithm {
+    type Err = String;
+
+    /// Parses `blake2b` (160 bits), `blake2b-<bits>`, `sha1`, `sha256` and `blake3`
+    /// (algorithms that were not enabled at build time are rejected)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            #[cfg(feature = "blake2")]
+            "blake2b" => Ok(HashingAlgorithm::Blake2b { size: 20 }),
+            #[cfg(feature = "blake2")]
+            _ if s.starts_with("blake2b-") => match s[8..].parse::<usize>() {
+                Ok(bits) if bits % 8 == 0 && bits >= 8 && bits <= 512 => Ok(HashingAlgorithm::Blake2b { size: bits / 8 }),
+                _ => Err(format!("invalid BLAKE2b digest size in {}", s)),
+            },
+            #[cfg(feature = "sha-1")]
+            "sha1" => Ok(HashingAlgorithm::SHA1),
+            #[cfg(feature = "sha2")]
+            "sha256" => Ok(HashingAlgorithm::SHA256),
+            #[cfg(feature = "blake3")]
+            "blake3" => Ok(HashingAlgorithm::Blake3),
+            _ => Err(format!("unknown hashing algorithm {}", s)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -155,4 +222,42 @@ mod tests {
         // 294863232e30c5580ee9410b7c35a2c6d3b6ceb3
         assert_eq!(hasher.result_box(), vec![41, 72, 99, 35, 46, 48, 197, 88, 14, 233, 65, 11, 124, 53, 162, 198, 211, 182, 206, 179]);
     }
+
+    #[test]
+    fn from_str() {
+        #[cfg(feature = "blake2")] {
+            assert_eq!("blake2b".parse(), Ok(HashingAlgorithm::Blake2b { size: 20 }));
+            assert_eq!("blake2b-256".parse(), Ok(HashingAlgorithm::Blake2b { size: 32 }));
+            assert!("blake2b-255".parse::<HashingAlgorithm>().is_err());
+        }
+        #[cfg(feature = "sha-1")]
+        assert_eq!("sha1".parse(), Ok(HashingAlgorithm::SHA1));
+        assert!("md5".parse::<HashingAlgorithm>().is_err());
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn sha256() {
+        let algo = HashingAlgorithm::SHA256;
+        let mut hasher = algo.hasher();
+        hasher.process(b"test");
+        hasher.process(b"that");
+        // $ sha256sum <test file>
+        // # returns
+        // e559e608c40cabcb68b3d840ba275e060544f0ae31297a5966dcb07ce7b12310
+        assert_eq!(hasher.result_box(), vec![229, 89, 230, 8, 196, 12, 171, 203, 104, 179, 216, 64, 186, 39, 94, 6,
+                                             5, 68, 240, 174, 49, 41, 122, 89, 102, 220, 176, 124, 231, 177, 35, 16]);
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn blake3() {
+        let algo = HashingAlgorithm::Blake3;
+        let mut hasher = algo.hasher();
+        hasher.process(b"test");
+        hasher.process(b"that");
+        let result = hasher.result_box();
+        assert_eq!(result.len(), algo.len());
+        assert_eq!(result, blake3::hash(b"testthat").as_bytes().to_vec());
+    }
 }
@@ -20,6 +20,12 @@ pub use crate::item::Item;
 pub mod record;
 pub use crate::record::Record;
 pub use crate::repository::{Repository, Error as RepositoryError};
+pub mod store;
+pub use crate::store::RecordStore;
+pub mod pack;
+pub mod bundle;
+pub mod graph;
+pub mod redaction;
 pub mod reducers;
 pub use crate::reducers::Reducer;
 #[cfg(feature = "duktape")]
@@ -0,0 +1,322 @@
+//! Packs keep many records in a single archive
+//!
+//! Every record stored as a directory requires a file per entry, which
+//! quickly adds up to millions of small files in bigger repositories.
+//! A pack consists of two files under `packs/`:
+//!
+//! * `<name>.pack` with concatenated contents of all files of packed records
+//! * `<name>.idx` (JSON) describing where every record's file is located in the archive
+//!
+//! Packs are never modified once written, new records are added as loose
+//! directories and can be packed again into a new pack. Pack's name is the
+//! encoded hash of its archive and its index, and an existing pack is never
+//! overwritten.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use tempdir::TempDir;
+
+use crate::encoding::Encoding;
+use crate::hash::{Hasher, HashingAlgorithm};
+use crate::repository::Error;
+
+/// Pack archive file extension
+pub const PACK_EXTENSION: &str = "pack";
+/// Pack index file extension
+pub const INDEX_EXTENSION: &str = "idx";
+
+/// Location of a record's file inside of a pack archive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PackEntry {
+    /// File name
+    pub name: String,
+    /// Offset in the archive
+    pub offset: u64,
+    /// Length of the file
+    pub length: u64,
+}
+
+/// Pack index
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PackIndex {
+    /// Files of every record (keyed by encoded record hash)
+    pub records: BTreeMap<String, Vec<PackEntry>>,
+}
+
+/// Record residing in a pack
+#[derive(Debug)]
+pub struct PackedRecord {
+    pack: PathBuf,
+    entries: Vec<PackEntry>,
+}
+
+impl PackedRecord {
+    /// Returns path to the pack archive
+    pub fn pack(&self) -> &Path {
+        self.pack.as_path()
+    }
+
+    /// Returns record's files locations
+    pub fn entries(&self) -> &[PackEntry] {
+        &self.entries
+    }
+
+    /// Returns encoded hashes of records referenced in `.prev/`
+    pub fn parents(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter()
+            .filter(|e| e.name.starts_with(".prev/"))
+            .map(|e| &e.name[6..])
+    }
+
+    /// Opens a file described by the entry for reading
+    pub fn open(&self, entry: &PackEntry) -> io::Result<io::Take<fs::File>> {
+        let mut f = fs::File::open(&self.pack)?;
+        f.seek(SeekFrom::Start(entry.offset))?;
+        Ok(f.take(entry.length))
+    }
+}
+
+/// Reads indices of all packs in `path`
+///
+/// Packs are sorted by their name, and if a record is present in more than
+/// one pack, only its first occurrence is returned.
+pub fn read_packs<P: AsRef<Path>>(path: P) -> Result<Vec<(String, Arc<PackedRecord>)>, Error> {
+    read_indices(index_paths(path)?)
+}
+
+/// Lists (sorted) indices of complete packs in `path`
+fn index_paths<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Error> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut indices: Vec<_> = fs::read_dir(path)?.filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(INDEX_EXTENSION))
+        .filter(|p| p.with_extension(PACK_EXTENSION).is_file())
+        .collect();
+    indices.sort();
+    Ok(indices)
+}
+
+fn read_indices(indices: Vec<PathBuf>) -> Result<Vec<(String, Arc<PackedRecord>)>, Error> {
+    let mut result: Vec<(String, Arc<PackedRecord>)> = vec![];
+    let mut seen = HashSet::new();
+    for index_path in indices {
+        let index: PackIndex = serde_json::from_reader(io::BufReader::new(fs::File::open(&index_path)?))?;
+        let pack = index_path.with_extension(PACK_EXTENSION);
+        for (name, entries) in index.records {
+            if seen.insert(name.clone()) {
+                result.push((name, Arc::new(PackedRecord { pack: pack.clone(), entries })));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Indices of packs in a directory, kept in memory between lookups
+///
+/// Packs are never modified once written, so indices are only read again
+/// when packs are added or removed.
+#[derive(Debug, Default)]
+pub struct PackCache {
+    indices: Vec<PathBuf>,
+    records: Vec<(String, Arc<PackedRecord>)>,
+    lookup: HashMap<String, Arc<PackedRecord>>,
+}
+
+impl PackCache {
+    /// Returns records of all packs in `path` (see [`read_packs`])
+    ///
+    /// [`read_packs`]: fn.read_packs.html
+    pub fn records<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<(String, Arc<PackedRecord>)>, Error> {
+        self.refresh(path)?;
+        Ok(self.records.clone())
+    }
+
+    /// Returns a packed record by its encoded hash
+    pub fn record<P: AsRef<Path>, S: AsRef<str>>(&mut self, path: P, name: S) -> Result<Option<Arc<PackedRecord>>, Error> {
+        self.refresh(path)?;
+        Ok(self.lookup.get(name.as_ref()).cloned())
+    }
+
+    fn refresh<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let indices = index_paths(path)?;
+        if indices != self.indices {
+            self.records = read_indices(indices.clone())?;
+            self.lookup = self.records.iter().cloned().collect();
+            self.indices = indices;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a new pack
+pub struct PackWriter {
+    tempdir: TempDir,
+    archive: io::BufWriter<fs::File>,
+    hasher: Box<dyn Hasher>,
+    index: PackIndex,
+    offset: u64,
+}
+
+impl PackWriter {
+    /// Starts a new pack (`path` is the directory where packs reside)
+    pub fn new<P: AsRef<Path>>(path: P, hashing_algorithm: &HashingAlgorithm) -> Result<Self, Error> {
+        fs::create_dir_all(path.as_ref())?;
+        let tempdir = TempDir::new_in(path.as_ref(), "sit")?;
+        let archive = io::BufWriter::new(fs::File::create(tempdir.path().join(PACK_EXTENSION))?);
+        Ok(PackWriter {
+            tempdir,
+            archive,
+            hasher: hashing_algorithm.hasher(),
+            index: PackIndex::default(),
+            offset: 0,
+        })
+    }
+
+    /// Adds a record file to the pack
+    pub fn add<S: AsRef<str>, N: Into<String>, R: Read>(&mut self, record: S, name: N, mut reader: R) -> Result<(), Error> {
+        let mut buf = vec![0; 4096];
+        let mut length = 0;
+        loop {
+            let bytes_read = reader.read(&mut buf)?;
+            if bytes_read == 0 {
+                break;
+            }
+            self.archive.write_all(&buf[0..bytes_read])?;
+            self.hasher.process(&buf[0..bytes_read]);
+            length += bytes_read as u64;
+        }
+        self.index.records.entry(record.as_ref().into()).or_insert(vec![])
+            .push(PackEntry { name: name.into(), offset: self.offset, length });
+        self.offset += length;
+        Ok(())
+    }
+
+    /// Returns true if nothing was added to the pack
+    pub fn is_empty(&self) -> bool {
+        self.index.records.is_empty()
+    }
+
+    /// Finishes writing the pack and returns a path to the archive
+    ///
+    /// The index is written after the archive has been put in place,
+    /// so an interrupted pack is never picked up. Fails if the same
+    /// pack already exists.
+    pub fn finish(self, encoding: &Encoding) -> Result<PathBuf, Error> {
+        let PackWriter { tempdir, archive, mut hasher, index, .. } = self;
+        let archive = archive.into_inner().map_err(|e| Error::OtherError(format!("{}", e)))?;
+        archive.sync_all()?;
+        drop(archive);
+
+        // identical archives can describe different records
+        hasher.process(&serde_json::to_vec(&index)?);
+        let name = encoding.encode(&hasher.result_box());
+        let mut dir = tempdir.path().to_path_buf();
+        dir.pop();
+        let pack_path = dir.join(&name).with_extension(PACK_EXTENSION);
+        if pack_path.exists() || pack_path.with_extension(INDEX_EXTENSION).exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                      format!("pack {} already exists", name)).into());
+        }
+        fs::rename(tempdir.path().join(PACK_EXTENSION), &pack_path)?;
+
+        let index_tmp = tempdir.path().join(INDEX_EXTENSION);
+        let index_file = fs::File::create(&index_tmp)?;
+        serde_json::to_writer(&index_file, &index)?;
+        index_file.sync_all()?;
+        drop(index_file);
+        fs::rename(&index_tmp, dir.join(&name).with_extension(INDEX_EXTENSION))?;
+
+        Ok(pack_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn write_and_read() {
+        let tmp = TempDir::new("sit").unwrap().into_path();
+        let mut writer = PackWriter::new(&tmp, &HashingAlgorithm::default()).unwrap();
+        assert!(writer.is_empty());
+        writer.add("A", "file1", &b"hello"[..]).unwrap();
+        writer.add("A", ".prev/B", &b""[..]).unwrap();
+        writer.add("B", "file2", &b"world"[..]).unwrap();
+        let pack = writer.finish(&Encoding::default()).unwrap();
+        assert!(pack.is_file());
+        assert!(pack.with_extension(INDEX_EXTENSION).is_file());
+
+        let packs = read_packs(&tmp).unwrap();
+        assert_eq!(packs.len(), 2);
+        let (name, record) = packs.iter().find(|(name, _)| name == "A").unwrap();
+        assert_eq!(name, "A");
+        assert_eq!(record.parents().collect::<Vec<_>>(), vec!["B"]);
+        let mut s = String::new();
+        record.open(&record.entries()[0]).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello");
+        let (_, record) = packs.iter().find(|(name, _)| name == "B").unwrap();
+        let mut s = String::new();
+        record.open(&record.entries()[0]).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "world");
+    }
+
+    #[test]
+    fn packing_twice() {
+        let tmp = TempDir::new("sit").unwrap().into_path();
+        let mut writer = PackWriter::new(&tmp, &HashingAlgorithm::default()).unwrap();
+        writer.add("A", "file", &b"hello"[..]).unwrap();
+        let pack = writer.finish(&Encoding::default()).unwrap();
+        // same archive, different records
+        let mut writer = PackWriter::new(&tmp, &HashingAlgorithm::default()).unwrap();
+        writer.add("B", "file", &b"hello"[..]).unwrap();
+        assert_ne!(writer.finish(&Encoding::default()).unwrap(), pack);
+        // same pack
+        let mut writer = PackWriter::new(&tmp, &HashingAlgorithm::default()).unwrap();
+        writer.add("A", "file", &b"hello"[..]).unwrap();
+        assert_matches!(writer.finish(&Encoding::default()), Err(Error::IoError(_)));
+
+        let packs = read_packs(&tmp).unwrap();
+        assert_eq!(packs.len(), 2);
+        assert!(packs.iter().any(|(name, _)| name == "A"));
+        assert!(packs.iter().any(|(name, _)| name == "B"));
+    }
+
+    #[test]
+    fn incomplete_pack_ignored() {
+        let tmp = TempDir::new("sit").unwrap().into_path();
+        fs::File::create(tmp.join("X").with_extension(INDEX_EXTENSION)).unwrap();
+        assert!(read_packs(&tmp).unwrap().is_empty());
+    }
+
+    #[test]
+    fn cache() {
+        let tmp = TempDir::new("sit").unwrap().into_path();
+        let mut cache = PackCache::default();
+        assert!(cache.records(&tmp).unwrap().is_empty());
+        assert!(cache.record(&tmp, "A").unwrap().is_none());
+
+        let mut writer = PackWriter::new(&tmp, &HashingAlgorithm::default()).unwrap();
+        writer.add("A", "file1", &b"hello"[..]).unwrap();
+        writer.finish(&Encoding::default()).unwrap();
+        // new packs are picked up
+        assert_eq!(cache.records(&tmp).unwrap().len(), 1);
+        assert_eq!(cache.record(&tmp, "A").unwrap().unwrap().entries()[0].name, "file1");
+        assert!(cache.record(&tmp, "B").unwrap().is_none());
+
+        let mut writer = PackWriter::new(&tmp, &HashingAlgorithm::default()).unwrap();
+        writer.add("B", "file2", &b"world"[..]).unwrap();
+        writer.finish(&Encoding::default()).unwrap();
+        assert_eq!(cache.records(&tmp).unwrap().len(), 2);
+        assert!(cache.record(&tmp, "B").unwrap().is_some());
+    }
+}
@@ -4,6 +4,8 @@ use std::io::{self, Read};
 use crate::hash::{Hasher, HashingAlgorithm};
 use std::path::PathBuf;
 use derive_error::Error;
+use crate::graph::RecordGraph;
+use crate::redaction::{RedactedRecordContainer, Redactions};
 
 /// Record's file
 ///
@@ -285,6 +287,15 @@ pub trait Record {
    /// Returns an iterator over files in the record
    fn file_iter(&self) -> Self::Iter;
 
+   /// Returns encoded hashes of records this record links to as its parents
+   /// (through `.prev/<hash>` files)
+   fn parents(&self) -> Vec<String> {
+       self.file_iter()
+           .filter(|(name, _)| name.as_ref().starts_with(".prev/"))
+           .map(|(name, _)| String::from(&name.as_ref()[6..]))
+           .collect()
+   }
+
    /// Returns true if the integrity of the record is intact
    fn integrity_intact(&self, hashing_algorithm: &HashingAlgorithm) -> bool {
        let mut hasher = hashing_algorithm.hasher();
@@ -313,6 +324,41 @@ pub trait RecordContainer {
     /// Iterates through the tree of records
     fn record_iter(&self) -> Result<Self::Iter, Self::Error>;
 
+    /// Returns a graph of all records
+    fn graph(&self) -> Result<RecordGraph<Self::Record>, Self::Error> {
+        Ok(RecordGraph::new(self.record_iter()?))
+    }
+
+    /// Returns the last generation of records
+    ///
+    /// These are the dangling records [`RecordOwningContainer::new_record`]
+    /// links new records to.
+    ///
+    /// [`RecordOwningContainer::new_record`]: trait.RecordOwningContainer.html#tymethod.new_record
+    fn heads(&self) -> Result<Vec<Self::Record>, Self::Error> {
+        Ok(self.record_iter()?.last().map(|records| records.into_iter().collect()).unwrap_or(vec![]))
+    }
+
+    /// Returns parents of a record
+    fn parents<S: AsRef<str>>(&self, hash: S) -> Result<Vec<Self::Record>, Self::Error> {
+        Ok(self.graph()?.select(|graph| graph.parent_indices(hash)))
+    }
+
+    /// Returns children of a record
+    fn children<S: AsRef<str>>(&self, hash: S) -> Result<Vec<Self::Record>, Self::Error> {
+        Ok(self.graph()?.select(|graph| graph.child_indices(hash)))
+    }
+
+    /// Returns all records a record descends from, in the order of iteration
+    fn ancestors<S: AsRef<str>>(&self, hash: S) -> Result<Vec<Self::Record>, Self::Error> {
+        Ok(self.graph()?.select(|graph| graph.ancestor_indices(hash)))
+    }
+
+    /// Returns all records that descend from a record, in the order of iteration
+    fn descendants<S: AsRef<str>>(&self, hash: S) -> Result<Vec<Self::Record>, Self::Error> {
+        Ok(self.graph()?.select(|graph| graph.descendant_indices(hash)))
+    }
+
     fn fixed_roots<S: Into<String>, I: IntoIterator<Item = S>>(&self, roots: I) -> 
         FixedRootsRecordContainer<Self> where Self: Sized {
         FixedRootsRecordContainer {
@@ -320,6 +366,17 @@ pub trait RecordContainer {
             roots: roots.into_iter().map(|s| s.into()).collect(),
         }
     }
+
+    /// Returns a view of the container in which files hidden by redaction
+    /// records are presented as removed
+    ///
+    /// See [`redaction`] module for details.
+    ///
+    /// [`redaction`]: ../redaction/index.html
+    fn redacted(&self) -> Result<RedactedRecordContainer<Self>, Self::Error> where Self: Sized {
+        let records: Vec<Self::Record> = self.record_iter()?.flat_map(|records| records).collect();
+        Ok(RedactedRecordContainer::new(self, Redactions::new(records.iter())))
+    }
 }
 
 pub struct FixedRootsRecordContainer<'a, RC: RecordContainer + 'a> {
@@ -359,8 +416,7 @@ impl<RC: RecordContainer> Iterator for FixedRootsRecordIterator<RC> {
                 let records: Vec<_> = value.into_iter()
                     .filter(|record| 
                             self.roots.iter().any(|root| root == record.encoded_hash().as_ref()) ||
-                            record.file_iter().filter(|&(ref name, _)| name.as_ref().starts_with(".prev/"))
-                            .any(|(name, _)| self.known.iter().any(|known| known == &name.as_ref()[6..])))
+                            record.parents().iter().any(|parent| self.known.iter().any(|known| known == parent)))
                     .collect();
                 for r in records.iter() {
                     self.known.push(r.encoded_hash().as_ref().into());
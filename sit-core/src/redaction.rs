@@ -0,0 +1,307 @@
+//! Redactions hide files of records without rewriting history
+//!
+//! Records are immutable and removing them (or their files) would change
+//! hashes of all of their descendants. Instead, a *redaction* record can be
+//! added. It has a `.type/Redaction` file and, for every record it redacts,
+//! a `.redacts/<encoded hash>` file listing names of redacted files, one per line.
+//!
+//! [`RecordContainer::redacted`] returns a view of a container in which
+//! redacted files are presented as removed. Everything built upon that view
+//! (such as reducers and [`RecordExt::serde_serialize`]) doesn't see them.
+//! Links to previous records (`.prev/`) can't be redacted, so the graph
+//! of records stays intact.
+//!
+//! Redacted files are still present in the repository, so redactions
+//! hide data from views, they don't remove it.
+//!
+//! [`RecordContainer::redacted`]: ../record/trait.RecordContainer.html#method.redacted
+//! [`RecordExt::serde_serialize`]: ../record/trait.RecordExt.html#method.serde_serialize
+
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Serialize, Serializer};
+
+use crate::hash::HashingAlgorithm;
+use crate::path::HasPath;
+use crate::record::{RecordContainer, RecordContainerReduction, RecordExt};
+use crate::Record;
+
+/// Type of redaction records
+pub const REDACTION_TYPE: &str = "Redaction";
+/// Prefix of files listing redacted files of a record
+pub const REDACTS_PREFIX: &str = ".redacts/";
+
+/// Returns files of a redaction record that redacts `files` of record `hash`
+pub fn redaction_files<S: AsRef<str>, F: AsRef<str>, I: IntoIterator<Item = F>>(hash: S, files: I) -> Vec<(String, Vec<u8>)> {
+    let mut list = String::new();
+    for file in files {
+        list.push_str(file.as_ref());
+        list.push('\n');
+    }
+    vec![
+        (format!(".type/{}", REDACTION_TYPE), vec![]),
+        (format!("{}{}", REDACTS_PREFIX, hash.as_ref()), list.into_bytes()),
+    ]
+}
+
+/// Files redacted by redaction records
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Redactions(HashMap<String, Arc<HashSet<String>>>);
+
+impl Redactions {
+    /// Collects redactions from records
+    pub fn new<'a, R: Record + 'a, I: IntoIterator<Item = &'a R>>(records: I) -> Self {
+        let mut redactions: HashMap<String, HashSet<String>> = HashMap::new();
+        for record in records.into_iter().filter(|r| r.has_type(REDACTION_TYPE)) {
+            for (name, mut reader) in record.file_iter() {
+                let name = name.as_ref();
+                if !name.starts_with(REDACTS_PREFIX) {
+                    continue;
+                }
+                let mut list = String::new();
+                if reader.read_to_string(&mut list).is_err() {
+                    continue;
+                }
+                redactions.entry(name[REDACTS_PREFIX.len()..].into()).or_insert_with(HashSet::new)
+                    .extend(list.lines().map(str::trim)
+                            .filter(|f| !f.is_empty() && !f.starts_with(".prev/"))
+                            .map(String::from));
+            }
+        }
+        Redactions(redactions.into_iter().map(|(k, v)| (k, Arc::new(v))).collect())
+    }
+
+    /// Returns true if there are no redactions
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns names of redacted files of a record
+    pub fn redacted_files<S: AsRef<str>>(&self, hash: S) -> Option<&HashSet<String>> {
+        self.0.get(hash.as_ref()).map(|files| &**files)
+    }
+
+    /// Returns a string that uniquely identifies this set of redactions
+    ///
+    /// Useful for invalidating anything derived from redacted records
+    /// (such as reduction cache entries) when redactions change.
+    pub fn fingerprint(&self) -> String {
+        let mut hashes: Vec<_> = self.0.keys().collect();
+        hashes.sort();
+        hashes.into_iter().map(|hash| {
+            let mut files: Vec<_> = self.0[hash].iter().map(String::as_str).collect();
+            files.sort();
+            format!("{}:{}", hash, files.join(","))
+        }).collect::<Vec<_>>().join(";")
+    }
+
+    fn wrap<R: Record>(&self, record: R) -> RedactedRecord<R> {
+        let redacted = self.0.get(record.encoded_hash().as_ref()).cloned();
+        RedactedRecord { record, redacted }
+    }
+}
+
+/// View of a container in which redacted files are presented as removed
+///
+/// Obtained through [`RecordContainer::redacted`].
+///
+/// [`RecordContainer::redacted`]: ../record/trait.RecordContainer.html#method.redacted
+pub struct RedactedRecordContainer<'a, RC: RecordContainer + 'a> {
+    container: &'a RC,
+    redactions: Redactions,
+}
+
+impl<'a, RC: RecordContainer + 'a> RedactedRecordContainer<'a, RC> {
+    /// Creates a view of a container with given redactions
+    ///
+    /// Useful when redaction records reside outside of the container
+    /// (for example, when the container is a subset of a repository).
+    pub fn new(container: &'a RC, redactions: Redactions) -> Self {
+        RedactedRecordContainer { container, redactions }
+    }
+
+    /// Returns redactions in effect
+    pub fn redactions(&self) -> &Redactions {
+        &self.redactions
+    }
+}
+
+impl<'a, RC: RecordContainer + 'a> RecordContainer for RedactedRecordContainer<'a, RC> {
+    type Error = RC::Error;
+    type Record = RedactedRecord<RC::Record>;
+    type Records = Vec<RedactedRecord<RC::Record>>;
+    type Iter = RedactedRecordIterator<RC>;
+
+    fn record_iter(&self) -> Result<Self::Iter, Self::Error> {
+        Ok(RedactedRecordIterator {
+            iter: self.container.record_iter()?,
+            redactions: self.redactions.clone(),
+        })
+    }
+}
+
+impl<'a, RC: RecordContainer + 'a> RecordContainerReduction for RedactedRecordContainer<'a, RC> {}
+
+/// An iterator over generations of [`RedactedRecord`]s
+///
+/// [`RedactedRecord`]: struct.RedactedRecord.html
+pub struct RedactedRecordIterator<RC: RecordContainer> {
+    iter: RC::Iter,
+    redactions: Redactions,
+}
+
+impl<RC: RecordContainer> Iterator for RedactedRecordIterator<RC> {
+    type Item = Vec<RedactedRecord<RC::Record>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let records = self.iter.next()?;
+        Some(records.into_iter().map(|r| self.redactions.wrap(r)).collect())
+    }
+}
+
+/// Record with redacted files removed
+#[derive(Debug, Clone)]
+pub struct RedactedRecord<R: Record> {
+    record: R,
+    redacted: Option<Arc<HashSet<String>>>,
+}
+
+impl<R: Record> RedactedRecord<R> {
+    /// Returns the underlying record (with all of its files)
+    pub fn record(&self) -> &R {
+        &self.record
+    }
+
+    /// Returns sorted names of redacted files
+    pub fn redacted_files(&self) -> Vec<String> {
+        let mut files: Vec<_> = self.redacted.iter().flat_map(|f| f.iter().cloned()).collect();
+        files.sort();
+        files
+    }
+}
+
+impl<R: Record + PartialEq> PartialEq for RedactedRecord<R> {
+    fn eq(&self, other: &RedactedRecord<R>) -> bool {
+        self.record == other.record
+    }
+}
+
+impl<R: Record + HasPath> HasPath for RedactedRecord<R> {
+    fn path(&self) -> &Path {
+        self.record.path()
+    }
+}
+
+impl<R: Record> Serialize for RedactedRecord<R> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        self.serde_serialize(serializer)
+    }
+}
+
+impl<R: Record> Record for RedactedRecord<R> {
+    type Read = R::Read;
+    type Str = R::Str;
+    type Hash = R::Hash;
+    type Iter = RedactedFileIterator<R::Iter>;
+
+    fn hash(&self) -> Self::Hash {
+        self.record.hash()
+    }
+
+    fn encoded_hash(&self) -> Self::Str {
+        self.record.encoded_hash()
+    }
+
+    #[cfg(feature = "deprecated-item-api")]
+    fn item_id(&self) -> Self::Str {
+        self.record.item_id()
+    }
+
+    fn file_iter(&self) -> Self::Iter {
+        RedactedFileIterator {
+            iter: self.record.file_iter(),
+            redacted: self.redacted.clone(),
+        }
+    }
+
+    /// Checks the integrity of the underlying record (redacted files are
+    /// still a part of it)
+    fn integrity_intact(&self, hashing_algorithm: &HashingAlgorithm) -> bool {
+        self.record.integrity_intact(hashing_algorithm)
+    }
+}
+
+/// An iterator over files of [`RedactedRecord`] that skips redacted files
+///
+/// [`RedactedRecord`]: struct.RedactedRecord.html
+pub struct RedactedFileIterator<I> {
+    iter: I,
+    redacted: Option<Arc<HashSet<String>>>,
+}
+
+impl<S: AsRef<str>, Rd, I: Iterator<Item = (S, Rd)>> Iterator for RedactedFileIterator<I> {
+    type Item = (S, Rd);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (name, reader) = self.iter.next()?;
+            match self.redacted {
+                Some(ref redacted) if redacted.contains(name.as_ref()) => continue,
+                _ => return Some((name, reader)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Repository;
+    use crate::repository::Config;
+    use crate::record::RecordOwningContainer;
+
+    #[test]
+    fn redaction() {
+        let repo = Repository::in_memory(Config::default());
+        let record = repo.new_record(vec![("text", &b"hello"[..]), ("secret", &b"password"[..])].into_iter(), false).unwrap();
+        repo.new_record(vec![("text", &b"bye"[..])].into_iter(), true).unwrap();
+        let files = redaction_files(record.encoded_hash(), vec!["secret", ".prev/whatever"]);
+        let redaction = repo.new_record(files.iter().map(|(n, c)| (n.as_str(), &c[..])), true).unwrap();
+
+        let redacted = repo.redacted().unwrap();
+        assert_eq!(redacted.redactions().redacted_files(record.encoded_hash()).unwrap().len(), 1);
+        let records: Vec<_> = redacted.record_iter().unwrap().flat_map(|r| r).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].encoded_hash(), record.encoded_hash());
+        let names: Vec<_> = records[0].file_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["text"]);
+        assert_eq!(records[0].redacted_files(), vec!["secret"]);
+        assert!(records[0].integrity_intact(repo.config().hashing_algorithm()));
+        assert!(records[0].record().file_iter().any(|(name, _)| name == "secret"));
+        // the graph stays intact
+        assert_eq!(records[1].parents(), vec![record.encoded_hash()]);
+        assert_eq!(records[2].encoded_hash(), redaction.encoded_hash());
+        assert!(records[1].redacted_files().is_empty());
+
+        let json = serde_json::to_value(&records[0]).unwrap();
+        assert!(json["files"].get("secret").is_none());
+        assert_eq!(json["files"]["text"], "hello");
+    }
+
+    #[test]
+    fn fingerprint() {
+        let repo = Repository::in_memory(Config::default());
+        let record = repo.new_record(vec![("text", &b"hello"[..])].into_iter(), false).unwrap();
+        let empty = repo.redacted().unwrap().redactions().fingerprint();
+        assert!(repo.redacted().unwrap().redactions().is_empty());
+        let files = redaction_files(record.encoded_hash(), vec!["text"]);
+        repo.new_record(files.iter().map(|(n, c)| (n.as_str(), &c[..])), true).unwrap();
+        let redactions = repo.redacted().unwrap().redactions().clone();
+        assert!(!redactions.is_empty());
+        assert_ne!(redactions.fingerprint(), empty);
+        assert_eq!(redactions.fingerprint(), format!("{}:text", record.encoded_hash()));
+    }
+}
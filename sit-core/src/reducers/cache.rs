@@ -0,0 +1,350 @@
+//! Persistent reduction cache
+//!
+//! Reducing a container requires folding every one of its records, which
+//! gets slow as records accumulate. [`ReductionCache`] stores the reduced
+//! state along with DAG heads (records that were the last ones processed)
+//! so that the next reduction only needs to fold over records that were
+//! added since.
+//!
+//! Reducers' own states (such as `this` of JavaScript reducers) are stored
+//! in the entry, too, so that reduction resumes exactly where it stopped.
+//!
+//! Every cache entry is keyed by the hash of reducer sources, the scope of
+//! reduction (such as fixed roots), the initial state and reducers' initial
+//! own states, so changing any of these invalidates the entry automatically.
+//! An entry is also discarded if any of its heads is gone, or if new records
+//! were added to the generations that have already been folded (which would
+//! change reduction order).
+//!
+//! [`ReductionCache`]: struct.ReductionCache.html
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{self, Map as JsonMap, Value as JsonValue};
+use tempdir::TempDir;
+
+use crate::encoding::Encoding;
+use crate::hash::HashingAlgorithm;
+use crate::record::{RecordContainer, ReductionError};
+use crate::reducers::Reducer;
+use crate::Record;
+
+/// Cache entry
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Encoded hash of reducer sources
+    reducers: String,
+    /// Encoded hashes of the last processed records
+    heads: Vec<String>,
+    /// Reduced state
+    state: JsonMap<String, JsonValue>,
+    /// Reducer's own state
+    #[serde(default)]
+    reducer: JsonValue,
+}
+
+/// Reduction cache
+#[derive(Debug, Clone)]
+pub struct ReductionCache {
+    path: PathBuf,
+    hashing_algorithm: HashingAlgorithm,
+    encoding: Encoding,
+}
+
+impl ReductionCache {
+    /// Creates a cache residing in `path`. The directory is created on demand.
+    pub fn new<P: Into<PathBuf>>(path: P, hashing_algorithm: HashingAlgorithm, encoding: Encoding) -> Self {
+        ReductionCache {
+            path: path.into(),
+            hashing_algorithm,
+            encoding,
+        }
+    }
+
+    /// Returns path to the cache
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// Removes all cache entries
+    pub fn clear(&self) -> io::Result<()> {
+        if self.path.is_dir() {
+            fs::remove_dir_all(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// Reduces container with a given [`Reducer`] and state, using and updating the cache
+    ///
+    /// `reducers` is a hash of reducer sources and `scope` distinguishes different
+    /// reductions made with the same reducers (for example, reductions with fixed roots).
+    ///
+    /// Failing to read or write the cache never fails the reduction. States the reducer
+    /// deems not [cacheable] are returned, but not saved.
+    ///
+    /// [`Reducer`]: ../trait.Reducer.html
+    /// [cacheable]: ../trait.Reducer.html#method.cacheable
+    pub fn reduce_with_reducer_and_state<RC, R>(&self, container: &RC, reducer: &mut R, reducers: &[u8], scope: &str,
+                                                state: JsonMap<String, JsonValue>) -> Result<JsonMap<String, JsonValue>, ReductionError<RC::Error>>
+        where RC: RecordContainer, R: Reducer<State = JsonMap<String, JsonValue>, Item = RC::Record> {
+        let reducers = self.encoding.encode(reducers);
+        let own_state = reducer.own_state();
+        let name = self.entry_name(&reducers, scope, &state, &own_state);
+        let generations: Vec<Vec<RC::Record>> = container.record_iter()?
+            .map(|records| records.into_iter().collect())
+            .collect();
+        let parents: HashMap<String, Vec<String>> = generations.iter().flat_map(|v| v)
+            .map(|r| (r.encoded_hash().as_ref().into(), r.parents()))
+            .collect();
+
+        let resumed = match self.load(&name) {
+            Some(entry) if entry.reducers == reducers => processed_records(&generations, &parents, &entry.heads)
+                .map(|processed| (entry, processed)),
+            _ => None,
+        };
+        let (state, processed) = match resumed {
+            Some((entry, processed)) => {
+                if reducer.restore_own_state(&entry.reducer) {
+                    (entry.state, processed)
+                } else {
+                    // start over, the way it would have started without the cache
+                    reducer.restore_own_state(&own_state);
+                    (state, HashSet::new())
+                }
+            },
+            None => (state, HashSet::new()),
+        };
+
+        let state = generations.iter().fold(state, |acc, recs|
+            recs.iter()
+                .filter(|r| !processed.contains(r.encoded_hash().as_ref()))
+                .fold(acc, |acc, rec| reducer.reduce(acc, rec)));
+
+        let linked: HashSet<&str> = parents.values().flat_map(|v| v).map(String::as_str).collect();
+        let mut heads: Vec<String> = parents.keys().filter(|h| !linked.contains(h.as_str())).cloned().collect();
+        heads.sort();
+        let entry = CacheEntry { reducers, heads, state, reducer: reducer.own_state() };
+        if reducer.cacheable() {
+            let _ = self.save(&name, &entry);
+        }
+        Ok(entry.state)
+    }
+
+    fn entry_name(&self, reducers: &str, scope: &str, state: &JsonMap<String, JsonValue>, own_state: &JsonValue) -> String {
+        let mut hasher = self.hashing_algorithm.hasher();
+        hasher.process(reducers.as_bytes());
+        hasher.process(&[0]);
+        hasher.process(scope.as_bytes());
+        hasher.process(&[0]);
+        hasher.process(serde_json::to_string(state).unwrap().as_bytes());
+        hasher.process(&[0]);
+        hasher.process(own_state.to_string().as_bytes());
+        self.encoding.encode(&hasher.result_box())
+    }
+
+    fn load(&self, name: &str) -> Option<CacheEntry> {
+        let file = fs::File::open(self.path.join(name)).ok()?;
+        serde_json::from_reader(io::BufReader::new(file)).ok()
+    }
+
+    fn save(&self, name: &str, entry: &CacheEntry) -> Result<(), io::Error> {
+        if !self.path.is_dir() {
+            fs::create_dir_all(&self.path)?;
+            // cache is local and should never be committed
+            let mut gitignore = fs::File::create(self.path.join(".gitignore"))?;
+            gitignore.write_all(b"*\n")?;
+        }
+        // write the entry to a temporary file first so that concurrent
+        // reductions never observe a partially written entry
+        let tempdir = TempDir::new_in(&self.path, "sit")?;
+        let tmp = tempdir.path().join(name);
+        {
+            let mut file = io::BufWriter::new(fs::File::create(&tmp)?);
+            serde_json::to_writer(&mut file, entry)?;
+            file.flush()?;
+        }
+        fs::rename(&tmp, self.path.join(name))
+    }
+}
+
+/// Returns a set of records that have been processed if `heads` were the last
+/// records processed, or `None` if the cached state can't be used anymore
+fn processed_records<R: Record>(generations: &[Vec<R>], parents: &HashMap<String, Vec<String>>, heads: &[String]) -> Option<HashSet<String>> {
+    if heads.iter().any(|h| !parents.contains_key(h)) {
+        return None;
+    }
+    let mut processed = HashSet::new();
+    let mut pending: Vec<&str> = heads.iter().map(String::as_str).collect();
+    while let Some(hash) = pending.pop() {
+        if let Some(p) = parents.get(hash) {
+            if processed.insert(String::from(hash)) {
+                pending.extend(p.iter().map(String::as_str));
+            }
+        }
+    }
+    // new records must not precede already processed ones
+    let first_new = generations.iter()
+        .position(|recs| recs.iter().any(|r| !processed.contains(r.encoded_hash().as_ref())));
+    let last_processed = generations.iter()
+        .rposition(|recs| recs.iter().any(|r| processed.contains(r.encoded_hash().as_ref())));
+    match (first_new, last_processed) {
+        (Some(first_new), Some(last_processed)) if first_new <= last_processed => None,
+        _ => Some(processed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Repository;
+    use crate::record::RecordOwningContainer;
+
+    /// Counts reduced records
+    struct Counter(usize);
+
+    impl Reducer for Counter {
+        type State = JsonMap<String, JsonValue>;
+        type Item = crate::repository::Record;
+
+        fn reduce(&mut self, mut state: Self::State, item: &Self::Item) -> Self::State {
+            self.0 += 1;
+            let records = state.entry("records").or_insert(JsonValue::Array(vec![]));
+            records.as_array_mut().unwrap().push(JsonValue::String(item.encoded_hash().as_ref().into()));
+            state
+        }
+    }
+
+    fn setup() -> (Repository<crate::repository::ModuleDirectory<PathBuf>>, ReductionCache) {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(&tmp).unwrap();
+        let cache = ReductionCache::new(tmp.join("cache"), repo.config().hashing_algorithm().clone(),
+                                        repo.config().encoding().clone());
+        (repo, cache)
+    }
+
+    fn reduce<RC: RecordContainer<Record = crate::repository::Record>>(cache: &ReductionCache, container: &RC, reducers: &[u8]) -> (usize, JsonMap<String, JsonValue>) {
+        let mut counter = Counter(0);
+        let state = cache.reduce_with_reducer_and_state(container, &mut counter, reducers, "", Default::default()).unwrap();
+        (counter.0, state)
+    }
+
+    #[test]
+    fn incremental() {
+        let (repo, cache) = setup();
+        repo.new_record(vec![("test", &[1u8][..])].into_iter(), true).unwrap();
+        repo.new_record(vec![("test", &[2u8][..])].into_iter(), true).unwrap();
+        let (count, state) = reduce(&cache, &repo, b"r");
+        assert_eq!(count, 2);
+        assert!(cache.path().join(".gitignore").is_file());
+        // nothing new
+        let (count, state_) = reduce(&cache, &repo, b"r");
+        assert_eq!(count, 0);
+        assert_eq!(state, state_);
+        // a new record
+        let record = repo.new_record(vec![("test", &[3u8][..])].into_iter(), true).unwrap();
+        let (count, state) = reduce(&cache, &repo, b"r");
+        assert_eq!(count, 1);
+        assert_eq!(state.get("records").unwrap().as_array().unwrap().len(), 3);
+        assert_eq!(state.get("records").unwrap().as_array().unwrap()[2], JsonValue::String(record.encoded_hash()));
+    }
+
+    #[test]
+    fn reducers_change() {
+        let (repo, cache) = setup();
+        repo.new_record(vec![("test", &[1u8][..])].into_iter(), true).unwrap();
+        assert_eq!(reduce(&cache, &repo, b"r").0, 1);
+        assert_eq!(reduce(&cache, &repo, b"r1").0, 1);
+        assert_eq!(reduce(&cache, &repo, b"r1").0, 0);
+    }
+
+    #[test]
+    fn scope_and_state() {
+        let (repo, cache) = setup();
+        repo.new_record(vec![("test", &[1u8][..])].into_iter(), true).unwrap();
+        assert_eq!(reduce(&cache, &repo, b"r").0, 1);
+        let mut counter = Counter(0);
+        cache.reduce_with_reducer_and_state(&repo, &mut counter, b"r", "scope", Default::default()).unwrap();
+        assert_eq!(counter.0, 1);
+        let mut state = JsonMap::new();
+        state.insert("a".into(), JsonValue::Bool(true));
+        let mut counter = Counter(0);
+        cache.reduce_with_reducer_and_state(&repo, &mut counter, b"r", "", state).unwrap();
+        assert_eq!(counter.0, 1);
+    }
+
+    #[test]
+    fn new_record_in_folded_generation() {
+        let (repo, cache) = setup();
+        repo.new_record(vec![("test", &[1u8][..])].into_iter(), true).unwrap();
+        repo.new_record(vec![("test", &[2u8][..])].into_iter(), true).unwrap();
+        assert_eq!(reduce(&cache, &repo, b"r").0, 2);
+        // a new root record would have been reduced before the second one
+        repo.new_record(vec![("test", &[3u8][..])].into_iter(), false).unwrap();
+        assert_eq!(reduce(&cache, &repo, b"r").0, 3);
+    }
+
+    #[test]
+    fn new_record_in_last_processed_generation() {
+        use crate::record::RecordContainerReduction;
+        let (repo, cache) = setup();
+        repo.new_record(vec![("test", &[1u8][..])].into_iter(), true).unwrap();
+        assert_eq!(reduce(&cache, &repo, b"r").0, 1);
+        // another root shares the generation with the processed one
+        repo.new_record(vec![("test", &[2u8][..])].into_iter(), false).unwrap();
+        let (count, state) = reduce(&cache, &repo, b"r");
+        assert_eq!(count, 2);
+        assert_eq!(state, repo.reduce_with_reducer_and_state(&mut Counter(0), Default::default()).unwrap());
+    }
+
+    #[test]
+    fn missing_head() {
+        let (repo, cache) = setup();
+        repo.new_record(vec![("test", &[1u8][..])].into_iter(), true).unwrap();
+        let record = repo.new_record(vec![("test", &[2u8][..])].into_iter(), true).unwrap();
+        assert_eq!(reduce(&cache, &repo, b"r").0, 2);
+        use crate::path::HasPath;
+        fs::remove_dir_all(record.path()).unwrap();
+        assert_eq!(reduce(&cache, &repo, b"r").0, 1);
+    }
+
+    /// Reduces nothing cacheable
+    struct Uncacheable(Counter);
+
+    impl Reducer for Uncacheable {
+        type State = JsonMap<String, JsonValue>;
+        type Item = crate::repository::Record;
+
+        fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State {
+            self.0.reduce(state, item)
+        }
+
+        fn cacheable(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn uncacheable() {
+        let (repo, cache) = setup();
+        repo.new_record(vec![("test", &[1u8][..])].into_iter(), true).unwrap();
+        let mut reducer = Uncacheable(Counter(0));
+        cache.reduce_with_reducer_and_state(&repo, &mut reducer, b"r", "", Default::default()).unwrap();
+        assert_eq!((reducer.0).0, 1);
+        assert!(!cache.path().is_dir());
+        assert_eq!(reduce(&cache, &repo, b"r").0, 1);
+    }
+
+    #[test]
+    fn clear() {
+        let (repo, cache) = setup();
+        repo.new_record(vec![("test", &[1u8][..])].into_iter(), true).unwrap();
+        assert_eq!(reduce(&cache, &repo, b"r").0, 1);
+        cache.clear().unwrap();
+        assert_eq!(reduce(&cache, &repo, b"r").0, 1);
+    }
+}
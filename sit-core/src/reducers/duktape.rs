@@ -10,10 +10,16 @@ use std::ffi::{CString, CStr, OsStr};
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::io;
+use std::os::raw::c_void;
+use std::time::{Duration, Instant};
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::sync::Arc;
 use crate::path::HasPath;
 use crate::RepositoryError;
 
 use derive_error::Error;
+use serde_derive::{Serialize, Deserialize};
 
 #[cfg(feature = "duktape-mmap")]
 use memmap;
@@ -25,6 +31,11 @@ pub trait SourceFiles {
     type Iter : Iterator<Item = PathBuf>;
 
     fn source_files(self) -> Result<Self::Iter, Error>;
+
+    /// Returns directories with libraries that reducers can `require` by name
+    fn library_paths(&self) -> Result<Vec<PathBuf>, Error> {
+        Ok(vec![])
+    }
 }
 
 impl<T> SourceFiles for T where T: IntoIterator<Item = PathBuf> {
@@ -35,7 +46,7 @@ impl<T> SourceFiles for T where T: IntoIterator<Item = PathBuf> {
     }
 }
 
-impl<'a, MI> SourceFiles for &'a crate::Repository<MI> where MI: crate::repository::ModuleIterator<PathBuf, crate::repository::Error> {
+impl<'a, MI, S> SourceFiles for &'a crate::Repository<MI, S> where MI: crate::repository::ModuleIterator<PathBuf, crate::repository::Error> {
 
     type Iter = std::vec::IntoIter<PathBuf>;
 
@@ -57,15 +68,605 @@ impl<'a, MI> SourceFiles for &'a crate::Repository<MI> where MI: crate::reposito
 
         Ok(files.into_iter())
     }
+
+    fn library_paths(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut paths = vec![];
+
+        if let Some(path) = library_path(self.path()) {
+            paths.push(path);
+        }
+
+        for module_name in self.module_iter()? {
+            let module_name = module_name?;
+            if let Some(path) = library_path(&self.modules_path().join(module_name)) {
+                paths.push(path);
+            }
+        }
+
+        Ok(paths)
+    }
+}
+
+/// Returns the library directory of a repository or a module (if it exists)
+///
+/// It is `lib`, unless `package.json` declares another directory
+/// (relative to it, without leaving it) as `directories.lib`.
+fn library_path(path: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+    let declared = fs::read(path.join("package.json")).ok()
+        .and_then(|package| serde_json::from_slice::<JsonValue>(&package).ok())
+        .and_then(|package| package.pointer("/directories/lib").and_then(JsonValue::as_str).map(PathBuf::from))
+        .filter(|lib| lib.components().all(|c| match c {
+            Component::Normal(_) | Component::CurDir => true,
+            _ => false,
+        }));
+    let lib = path.join(declared.unwrap_or_else(|| PathBuf::from("lib")));
+    if lib.is_dir() {
+        Some(lib)
+    } else {
+        None
+    }
+}
+
+/// Hashes reducer sources
+///
+/// Directories (including library ones) are hashed recursively, so changes
+/// to files that reducers `require` are taken into account, too. Useful for
+/// caching reductions.
+///
+/// File names are hashed relative to the directory containing the source,
+/// so the hash doesn't change when the repository is moved or cloned.
+pub fn source_files_hash<SF: SourceFiles>(source_files: SF, hashing_algorithm: &crate::hash::HashingAlgorithm) -> Result<Vec<u8>, Error> {
+    let mut hasher = hashing_algorithm.hasher();
+    let libraries = source_files.library_paths()?;
+    let mut files: Vec<_> = source_files.source_files()?.chain(libraries).collect();
+    files.sort();
+    let mut buf = vec![0; 4096];
+    for file in files {
+        let base = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        for entry in walkdir::WalkDir::new(&file).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+            let entry = entry.map_err(io::Error::from)?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let name = entry.path().strip_prefix(&base).unwrap_or(entry.path());
+            hasher.process(name.to_string_lossy().as_bytes());
+            hasher.process(&[0]);
+            let mut f = fs::File::open(entry.path())?;
+            loop {
+                let bytes_read = f.read(&mut buf)?;
+                if bytes_read == 0 {
+                    break;
+                }
+                hasher.process(&buf[0..bytes_read]);
+            }
+            hasher.process(&[0]);
+        }
+    }
+    Ok(hasher.result_box())
+}
+
+/// Execution limits for [`DuktapeReducer`]
+///
+/// Limits apply to every reducer invocation (per reducer, per record) and
+/// to evaluation of reducer modules. Violations are reported the same way as
+/// any other reducer error, in the state's `errors` array.
+///
+/// [`DuktapeReducer`]: struct.DuktapeReducer.html
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// Maximum execution time
+    pub timeout: Option<Duration>,
+    /// Maximum number of executed instructions
+    ///
+    /// Duktape checks limits every [`INTERRUPT_INTERVAL`] instructions, so
+    /// this limit is enforced with that granularity.
+    ///
+    /// [`INTERRUPT_INTERVAL`]: constant.INTERRUPT_INTERVAL.html
+    pub instructions: Option<u64>,
+    /// Maximum size of the heap, in bytes
+    ///
+    /// The heap holds compiled reducers and their states, too.
+    pub heap_size: Option<usize>,
+}
+
+/// What to do when a reducer fails (throws or returns an invalid value)
+///
+/// Either way, the error is reported in the state's `errors` array. Only the
+/// state is ever rolled back: changes reducers made to their `this` stay.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ErrorPolicy {
+    /// Discard changes made to the state by the failed record and stop reducing: subsequent
+    /// records are ignored (until [`reset_state`] is called)
+    ///
+    /// [`reset_state`]: struct.DuktapeReducer.html#method.reset_state
+    #[serde(rename = "stop")]
+    Stop,
+    /// Ignore the failed reducer for this record, apply the remaining ones
+    #[serde(rename = "skip-reducer")]
+    SkipReducer,
+    /// Discard changes made to the state by the failed record, continue with the next one
+    #[serde(rename = "skip-record")]
+    SkipRecord,
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy::SkipRecord
+    }
+}
+
+/// Severity of a reducer's log entry
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LogLevel {
+    /// `console.debug`
+    #[serde(rename = "debug")]
+    Debug,
+    /// `console.log`, `console.info` and `print`
+    #[serde(rename = "info")]
+    Info,
+    /// `console.warn`
+    #[serde(rename = "warn")]
+    Warn,
+    /// `console.error`
+    #[serde(rename = "error")]
+    Error,
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 4] = [LogLevel::Debug, LogLevel::Info, LogLevel::Warn, LogLevel::Error];
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        })
+    }
+}
+
+/// Output of `console` methods and `print` called by a reducer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub level: LogLevel,
+    /// Reducer's file (or the file being evaluated, if called from a module's body)
+    pub file: Option<PathBuf>,
+    /// Encoded hash of the record being reduced
+    pub record: Option<String>,
+    /// Arguments, separated by spaces (objects are serialized as JSON)
+    pub message: String,
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.level)?;
+        if let Some(ref file) = self.file {
+            write!(f, " {}", file.display())?;
+        }
+        if let Some(ref record) = self.record {
+            write!(f, " [{}]", record)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Receives reducers' log entries
+pub type Logger = Arc<dyn Fn(&LogEntry) + Send + Sync>;
+
+/// Default logger, prints entries to stderr
+pub fn stderr_logger() -> Logger {
+    Arc::new(|entry: &LogEntry| eprintln!("{}", entry))
+}
+
+/// Routes log entries of the heap, tagged with current reducer and record
+struct Console {
+    logger: Logger,
+    file: Option<PathBuf>,
+    record: Option<String>,
+}
+
+impl fmt::Debug for Console {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Console")
+            .field("file", &self.file)
+            .field("record", &self.record)
+            .finish()
+    }
+}
+
+/// Number of instructions Duktape executes between checks of [`Limits`]
+///
+/// [`Limits`]: struct.Limits.html
+pub const INTERRUPT_INTERVAL: u64 = 256 * 1024;
+
+/// Heap's user data, tracks usage against limits and routes console output
+#[derive(Debug)]
+struct Budget {
+    limits: Limits,
+    console: Console,
+    active: bool,
+    started: Instant,
+    interrupts: u64,
+    allocated: usize,
+    timed_out: Option<String>,
+    heap_exceeded: bool,
+}
+
+impl Budget {
+    fn new(limits: Limits, logger: Logger) -> *mut Budget {
+        Box::into_raw(Box::new(Budget {
+            limits,
+            console: Console {
+                logger,
+                file: None,
+                record: None,
+            },
+            active: false,
+            started: Instant::now(),
+            interrupts: 0,
+            allocated: 0,
+            timed_out: None,
+            heap_exceeded: false,
+        }))
+    }
+
+    /// Retrieves the budget of the heap
+    unsafe fn of(context: *mut duktape::duk_context) -> *mut Budget {
+        let mut funcs = duktape::duk_memory_functions {
+            alloc_func: None,
+            realloc_func: None,
+            free_func: None,
+            udata: ptr::null_mut(),
+        };
+        duktape::duk_get_memory_functions(context, &mut funcs);
+        funcs.udata as *mut Budget
+    }
+
+    /// Starts enforcing limits (only JavaScript code is limited, never the host)
+    fn start(&mut self) {
+        self.active = true;
+        self.started = Instant::now();
+        self.interrupts = 0;
+        self.timed_out = None;
+        self.heap_exceeded = false;
+    }
+
+    /// Stops enforcing limits, returns the violation (if any)
+    fn stop(&mut self) -> Option<String> {
+        self.active = false;
+        let heap_exceeded = if self.heap_exceeded {
+            self.limits.heap_size.map(|limit| format!("RangeError: heap size limit of {} bytes exceeded", limit))
+        } else {
+            None
+        };
+        self.heap_exceeded = false;
+        self.timed_out.take().or(heap_exceeded)
+    }
+
+    fn admit(&mut self, size: usize) -> bool {
+        match self.limits.heap_size {
+            Some(limit) if self.active && self.allocated + size > limit => {
+                self.heap_exceeded = true;
+                false
+            },
+            _ => true,
+        }
+    }
+
+    fn check(&mut self) -> bool {
+        if !self.active {
+            return false;
+        }
+        if self.timed_out.is_some() {
+            // has to keep signalling until the error bubbles out
+            return true;
+        }
+        self.interrupts += 1;
+        if let Some(limit) = self.limits.instructions {
+            if self.interrupts * INTERRUPT_INTERVAL > limit {
+                self.timed_out = Some(format!("RangeError: instruction limit of {} exceeded", limit));
+            }
+        }
+        if let Some(timeout) = self.limits.timeout {
+            if self.timed_out.is_none() && self.started.elapsed() > timeout {
+                let ms = timeout.as_secs() * 1000 + u64::from(timeout.subsec_millis());
+                self.timed_out = Some(format!("RangeError: execution time limit of {}ms exceeded", ms));
+            }
+        }
+        self.timed_out.is_some()
+    }
+}
+
+/// Called by Duktape periodically during execution
+/// (see `DUK_USE_EXEC_TIMEOUT_CHECK` in `duk_config.h`)
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn sit_duktape_exec_timeout_check(udata: *mut c_void) -> duktape::duk_bool_t {
+    if udata.is_null() {
+        return 0;
+    }
+    (*(udata as *mut Budget)).check() as duktape::duk_bool_t
+}
+
+// Allocations are prefixed with their size, so that the heap size can be tracked
+const ALLOC_HEADER: usize = 16;
+
+unsafe extern "C" fn heap_alloc(udata: *mut c_void, size: duktape::duk_size_t) -> *mut c_void {
+    let budget = &mut *(udata as *mut Budget);
+    if size == 0 || !budget.admit(size) {
+        return ptr::null_mut();
+    }
+    let base = alloc::alloc(Layout::from_size_align_unchecked(size + ALLOC_HEADER, ALLOC_HEADER));
+    if base.is_null() {
+        return ptr::null_mut();
+    }
+    *(base as *mut usize) = size;
+    budget.allocated += size;
+    base.add(ALLOC_HEADER) as *mut c_void
+}
+
+unsafe extern "C" fn heap_realloc(udata: *mut c_void, pointer: *mut c_void, size: duktape::duk_size_t) -> *mut c_void {
+    if pointer.is_null() {
+        return heap_alloc(udata, size);
+    }
+    if size == 0 {
+        heap_free(udata, pointer);
+        return ptr::null_mut();
+    }
+    let budget = &mut *(udata as *mut Budget);
+    let base = (pointer as *mut u8).sub(ALLOC_HEADER);
+    let old_size = *(base as *const usize);
+    if size > old_size && !budget.admit(size - old_size) {
+        return ptr::null_mut();
+    }
+    let base = alloc::realloc(base, Layout::from_size_align_unchecked(old_size + ALLOC_HEADER, ALLOC_HEADER), size + ALLOC_HEADER);
+    if base.is_null() {
+        return ptr::null_mut();
+    }
+    *(base as *mut usize) = size;
+    budget.allocated = budget.allocated - old_size + size;
+    base.add(ALLOC_HEADER) as *mut c_void
+}
+
+unsafe extern "C" fn heap_free(udata: *mut c_void, pointer: *mut c_void) {
+    if pointer.is_null() {
+        return;
+    }
+    let budget = &mut *(udata as *mut Budget);
+    let base = (pointer as *mut u8).sub(ALLOC_HEADER);
+    let size = *(base as *const usize);
+    budget.allocated -= size;
+    alloc::dealloc(base, Layout::from_size_align_unchecked(size + ALLOC_HEADER, ALLOC_HEADER));
+}
+
+// not exported by the bindings
+const DUK_VARARGS: duktape::duk_idx_t = -1;
+
+fn decode_string(s: &CStr) -> String {
+    #[cfg(feature = "cesu8")] {
+        if let Ok(s) = cesu8::from_cesu8(s.to_bytes()) {
+            return s.into_owned();
+        }
+    }
+    s.to_string_lossy().into_owned()
+}
+
+/// Implements `console` methods and `print`, log level is the function's magic
+unsafe extern "C" fn console_log(ctx: *mut duktape::duk_context) -> duktape::duk_ret_t {
+    let level = LogLevel::ALL[duktape::duk_get_current_magic(ctx) as usize];
+    let stack_prop = CString::new("stack").unwrap();
+    let mut message = String::new();
+    for i in 0..duktape::duk_get_top(ctx) {
+        if i > 0 {
+            message.push(' ');
+        }
+        if duktape::duk_is_string(ctx, i) == 1 {
+            message.push_str(&decode_string(CStr::from_ptr(duktape::duk_get_string(ctx, i))));
+        } else if duktape::duk_is_object(ctx, i) == 1 && duktape::duk_is_function(ctx, i) != 1 {
+            // errors are best represented by their stack traces
+            duktape::duk_get_prop_string(ctx, i, stack_prop.as_ptr());
+            if duktape::duk_is_string(ctx, -1) == 1 {
+                message.push_str(&decode_string(CStr::from_ptr(duktape::duk_get_string(ctx, -1))));
+                duktape::duk_pop(ctx);
+            } else {
+                duktape::duk_pop(ctx);
+                let json = duktape::duk_json_encode(ctx, i);
+                if json.is_null() {
+                    message.push_str("undefined");
+                } else {
+                    message.push_str(&decode_string(CStr::from_ptr(json)));
+                }
+            }
+        } else {
+            message.push_str(&decode_string(CStr::from_ptr(duktape::duk_safe_to_lstring(ctx, i, ptr::null_mut()))));
+        }
+    }
+    let console = &(*Budget::of(ctx)).console;
+    (console.logger)(&LogEntry {
+        level,
+        file: console.file.clone(),
+        record: console.record.clone(),
+        message,
+    });
+    0
+}
+
+/// Defines `console` and `print` globals
+unsafe fn install_console(context: *mut duktape::duk_context) {
+    duktape::duk_push_object(context);
+    for (name, level) in &[("debug", LogLevel::Debug), ("log", LogLevel::Info), ("info", LogLevel::Info),
+                           ("warn", LogLevel::Warn), ("error", LogLevel::Error)] {
+        let name = CString::new(*name).unwrap();
+        duktape::duk_push_c_function(context, Some(console_log), DUK_VARARGS);
+        duktape::duk_set_magic(context, -1, *level as duktape::duk_int_t);
+        duktape::duk_put_prop_string(context, -2, name.as_ptr());
+    }
+    let console = CString::new("console").unwrap();
+    duktape::duk_put_global_string(context, console.as_ptr());
+    let print = CString::new("print").unwrap();
+    duktape::duk_push_c_function(context, Some(console_log), DUK_VARARGS);
+    duktape::duk_set_magic(context, -1, LogLevel::Info as duktape::duk_int_t);
+    duktape::duk_put_global_string(context, print.as_ptr());
+}
+
+/// JSON-encodes the value on top of the stack (to be called with `duk_safe_call`,
+/// as encoding throws on circular references)
+unsafe extern "C" fn json_encode(ctx: *mut duktape::duk_context, _udata: *mut c_void) -> duktape::duk_ret_t {
+    duktape::duk_json_encode(ctx, -1);
+    1
+}
+
+/// Pushes a string, converting it to CESU-8 if necessary
+unsafe fn push_string(ctx: *mut duktape::duk_context, s: &str) {
+    #[cfg(feature = "cesu8")]
+    let s = cesu8::to_cesu8(s);
+    #[cfg(not(feature = "cesu8"))]
+    let s = s.as_bytes();
+    duktape::duk_push_lstring(ctx, s.as_ptr() as *const _, s.len());
+}
+
+// global stash key of the prototype of records passed to reducers
+const RECORD_PROTOTYPE: &str = "recordPrototype";
+
+// lazily computed record properties (the index is getter's magic)
+const RECORD_PROPERTIES: [(&str, Option<&str>); 3] = [("types", Some(".type/")), ("parents", Some(".prev/")), ("timestamp", None)];
+
+/// Returns content of `this.files[name]`, where name is on top of the stack (and is consumed)
+unsafe fn record_file(ctx: *mut duktape::duk_context) -> Option<Vec<u8>> {
+    let files_prop = CString::new("files").unwrap();
+    duktape::duk_push_this(ctx);
+    duktape::duk_get_prop_string(ctx, -1, files_prop.as_ptr());
+    duktape::duk_remove(ctx, -2);
+    // name files
+    duktape::duk_swap_top(ctx, -2);
+    // files name
+    duktape::duk_get_prop(ctx, -2);
+    // files file
+    let content = if duktape::duk_is_buffer_data(ctx, -1) == 1 {
+        let mut sz = 0;
+        let data = duktape::duk_get_buffer_data(ctx, -1, &mut sz);
+        // the buffer might not outlive popping it (if it came from a getter),
+        // so it has to be copied
+        Some(if sz == 0 { vec![] } else { std::slice::from_raw_parts(data as *const u8, sz).to_vec() })
+    } else {
+        None
+    };
+    duktape::duk_pop_2(ctx);
+    content
+}
+
+/// Implements `record.text(name)` and `record.json(name)` (magic is 1 for the latter)
+unsafe extern "C" fn record_file_content(ctx: *mut duktape::duk_context) -> duktape::duk_ret_t {
+    duktape::duk_require_string(ctx, 0);
+    duktape::duk_dup(ctx, 0);
+    match record_file(ctx) {
+        None => 0,
+        Some(content) => {
+            push_string(ctx, &String::from_utf8_lossy(&content));
+            if duktape::duk_get_current_magic(ctx) == 1 {
+                duktape::duk_json_decode(ctx, -1);
+            }
+            1
+        },
+    }
+}
+
+/// Implements getters of `RECORD_PROPERTIES`, caching the result in the record
+unsafe extern "C" fn record_property(ctx: *mut duktape::duk_context) -> duktape::duk_ret_t {
+    let (name, prefix) = RECORD_PROPERTIES[duktape::duk_get_current_magic(ctx) as usize];
+    match prefix {
+        Some(prefix) => {
+            // names of files with the prefix, without it
+            let files_prop = CString::new("files").unwrap();
+            let array = duktape::duk_push_array(ctx);
+            duktape::duk_push_this(ctx);
+            duktape::duk_get_prop_string(ctx, -1, files_prop.as_ptr());
+            duktape::duk_remove(ctx, -2);
+            duktape::duk_enum(ctx, -1, duktape::DUK_ENUM_OWN_PROPERTIES_ONLY);
+            let mut index = 0;
+            while duktape::duk_next(ctx, -1, 0) == 1 {
+                let file = decode_string(CStr::from_ptr(duktape::duk_get_string(ctx, -1)));
+                if file.starts_with(prefix) {
+                    push_string(ctx, &file[prefix.len()..]);
+                    duktape::duk_put_prop_index(ctx, array, index);
+                    index += 1;
+                }
+                duktape::duk_pop(ctx);
+            }
+            duktape::duk_pop_2(ctx);
+        },
+        None => {
+            push_string(ctx, ".timestamp");
+            match record_file(ctx) {
+                Some(content) => push_string(ctx, String::from_utf8_lossy(&content).trim()),
+                None => duktape::duk_push_undefined(ctx),
+            }
+        },
+    }
+    // shadow the getter with the value
+    duktape::duk_push_this(ctx);
+    push_string(ctx, name);
+    duktape::duk_dup(ctx, -3);
+    duktape::duk_def_prop(ctx, -3, duktape::DUK_DEFPROP_HAVE_VALUE | duktape::DUK_DEFPROP_CLEAR_WRITABLE |
+                          duktape::DUK_DEFPROP_CLEAR_ENUMERABLE | duktape::DUK_DEFPROP_SET_CONFIGURABLE);
+    duktape::duk_pop(ctx);
+    1
+}
+
+/// Creates the prototype of records passed to reducers
+unsafe fn install_record_prototype(context: *mut duktape::duk_context) {
+    duktape::duk_push_global_stash(context);
+    duktape::duk_push_object(context);
+    for (magic, name) in ["text", "json"].iter().enumerate() {
+        push_string(context, name);
+        duktape::duk_push_c_function(context, Some(record_file_content), 1);
+        duktape::duk_set_magic(context, -1, magic as duktape::duk_int_t);
+        duktape::duk_def_prop(context, -3, duktape::DUK_DEFPROP_HAVE_VALUE | duktape::DUK_DEFPROP_SET_WRITABLE |
+                              duktape::DUK_DEFPROP_CLEAR_ENUMERABLE | duktape::DUK_DEFPROP_SET_CONFIGURABLE);
+    }
+    for (magic, (name, _)) in RECORD_PROPERTIES.iter().enumerate() {
+        push_string(context, name);
+        duktape::duk_push_c_function(context, Some(record_property), 0);
+        duktape::duk_set_magic(context, -1, magic as duktape::duk_int_t);
+        duktape::duk_def_prop(context, -3, duktape::DUK_DEFPROP_HAVE_GETTER |
+                              duktape::DUK_DEFPROP_CLEAR_ENUMERABLE | duktape::DUK_DEFPROP_SET_CONFIGURABLE);
+    }
+    let prototype = CString::new(RECORD_PROTOTYPE).unwrap();
+    duktape::duk_put_prop_string(context, -2, prototype.as_ptr());
+    duktape::duk_pop(context);
+}
+
+unsafe fn create_heap(limits: Limits, logger: Logger) -> (*mut duktape::duk_context, *mut Budget) {
+    let budget = Budget::new(limits, logger);
+    let context = duktape::duk_create_heap(Some(heap_alloc), Some(heap_realloc), Some(heap_free),
+                                           budget as *mut c_void, Some(fatal_handler));
+    if context.is_null() {
+        drop(Box::from_raw(budget));
+        panic!("can't create Duktape heap");
+    }
+    install_console(context);
+    install_record_prototype(context);
+    (context, budget)
 }
 
 #[derive(Debug)]
 pub struct DuktapeReducer<R: Record> {
     context: *mut duktape::duk_context,
+    budget: *mut Budget,
+    error_policy: ErrorPolicy,
+    stopped: bool,
+    // execution limits were exceeded since the last reset
+    limits_exceeded: bool,
     reducers: i32,
     filenames: Vec<PathBuf>,
     phantom_data: PhantomData<R>,
     functions: Vec<Vec<u8>>,
+    // directories modules can be loaded from
+    directories: Vec<PathBuf>,
+    libraries: Vec<PathBuf>,
 }
 
 unsafe impl<R: Record> Send for DuktapeReducer<R> {}
@@ -89,6 +690,8 @@ impl<R: Record> Drop for DuktapeReducer<R> {
     fn drop(&mut self) {
         unsafe {
             duktape::duk_destroy_heap(self.context);
+            // the heap is freed through the budget, so it goes last
+            drop(Box::from_raw(self.budget));
         }
     }
 }
@@ -117,10 +720,13 @@ impl<R: Record> DuktapeReducer<R> {
         duktape::duk_require_function(context, -2);
         duktape::duk_require_object(context, -3);
         // module f module
+        let budget = Budget::of(context);
+        (*budget).start();
         let res = duktape::duk_pcall(context,1);
+        let exceeded = (*budget).stop();
         if res as u32 == duktape::DUK_EXEC_ERROR {
             let err_str = CStr::from_ptr(duktape::duk_to_string(context, -1));
-            let error = err_str.to_str().unwrap().into();
+            let error = exceeded.unwrap_or_else(|| err_str.to_str().unwrap().into());
             return Err(Error::ExecutionError { error });
         }
         // module retval
@@ -135,22 +741,37 @@ impl<R: Record> DuktapeReducer<R> {
 
 
     #[cfg(feature = "duktape-require")]
-    unsafe extern "C" fn mod_search(context: *mut duktape::duk_hthread) -> duktape::duk_ret_t {
-        // module id
-        let id = CStr::from_ptr(duktape::duk_get_string(context, 0));
-        let str_paths_prop = CString::new("paths").unwrap();
+    unsafe fn duktape_paths(context: *mut duktape::duk_hthread, prop: &str) -> Vec<PathBuf> {
+        let str_prop = CString::new(prop).unwrap();
         let str_duktape = CString::new("Duktape").unwrap();
         duktape::duk_get_global_string(context, str_duktape.as_ptr());
-        duktape::duk_get_prop_string(context, -1,str_paths_prop.as_ptr());
-        // allowed paths
-        let paths_length = duktape::duk_get_length(context, -1);
+        duktape::duk_get_prop_string(context, -1, str_prop.as_ptr());
+        let length = duktape::duk_get_length(context, -1);
         let mut paths = vec![];
-        for _i in 0..paths_length {
-            duktape::duk_get_prop_index(context, -1, _i as u32);
-            paths.push(CStr::from_ptr(duktape::duk_get_string(context, -1)).to_str().unwrap());
+        for i in 0..length {
+            duktape::duk_get_prop_index(context, -1, i as u32);
+            paths.push(PathBuf::from(decode_string(CStr::from_ptr(duktape::duk_get_string(context, -1)))));
             duktape::duk_pop(context);
         }
         duktape::duk_pop_2(context);
+        paths
+    }
+
+    /// Finds a module
+    ///
+    /// Modules are identified by their paths (see `module_id`), so that relative
+    /// `require` calls in modules are resolved by Duktape. Other identifiers (coming
+    /// from reducers themselves or naming packages) are searched for relative to the
+    /// calling file, in `node_modules` directories from the calling file's directory
+    /// up to its reducers directory and in library directories. Once found, such
+    /// identifiers are redirected to the module's path.
+    #[cfg(feature = "duktape-require")]
+    unsafe extern "C" fn mod_search(context: *mut duktape::duk_hthread) -> duktape::duk_ret_t {
+        // module id
+        let id = decode_string(CStr::from_ptr(duktape::duk_get_string(context, 0)));
+        // allowed paths
+        let paths = DuktapeReducer::<R>::duktape_paths(context, "paths");
+        let libraries = DuktapeReducer::<R>::duktape_paths(context, "libraries");
         // figure out calling function's filename
         let filename = {
             let filename_prop = CString::new("fileName").unwrap();
@@ -166,7 +787,7 @@ impl<R: Record> DuktapeReducer<R> {
                 if 1 == duktape::duk_get_prop_string(context, -1, function_prop.as_ptr()) {
                     duktape::duk_remove(context, -2);
                     if 1 == duktape::duk_get_prop_string(context, -1, filename_prop.as_ptr()) {
-                        let filename = CStr::from_ptr(duktape::duk_get_string(context, -1)).to_owned().into_string().unwrap();
+                        let filename = decode_string(CStr::from_ptr(duktape::duk_get_string(context, -1)));
                         duktape::duk_pop_2(context);
                         break filename;
                     }
@@ -174,76 +795,159 @@ impl<R: Record> DuktapeReducer<R> {
                 duktape::duk_pop(context);
             }
         };
-        // find matching allowed path
-        let path = match paths.into_iter().find(|path| filename.starts_with(path)) {
-            None => {
-                let err = CString::new(format!("matching path not found for {}", filename)).unwrap();
-                duktape::duk_error_raw(context, duktape::DUK_ERR_ERROR as i32, ptr::null_mut(), 0,err.as_ptr());
-                return duktape::DUK_RET_ERROR;
+        let caller = PathBuf::from(&filename);
+        let allowed = |file: &Path| paths.iter().any(|path| is_within(file, path));
+
+        let module_path = module_path(&id);
+        let locations = if paths.iter().any(|path| module_path.starts_with(path)) {
+            vec![module_path]
+        } else {
+            // find matching allowed path
+            let prefix = match paths.iter().find(|path| caller.starts_with(path)) {
+                None => {
+                    let err = CString::new(format!("matching path not found for {}", filename)).unwrap();
+                    duktape::duk_error_raw(context, duktape::DUK_ERR_ERROR as i32, ptr::null_mut(), 0,err.as_ptr());
+                    return duktape::DUK_RET_ERROR;
+                }
+                Some(path) => path,
+            };
+            let dir = caller.parent().unwrap_or(prefix);
+            let mut locations = vec![dir.join(&id)];
+            for dir in dir.ancestors().take_while(|dir| dir.starts_with(prefix)) {
+                locations.push(dir.join("node_modules").join(&id));
             }
-            Some(path) => path,
+            locations.extend(libraries.iter().map(|library| library.join(&id)));
+            locations
         };
-        // find the module
-        let prefix = PathBuf::from(path);
-        let mut mod_path = prefix.join(filename);
-        mod_path.pop();
-        mod_path.push(PathBuf::from(id.to_str().unwrap()));
-        if mod_path.is_file() && mod_path.strip_prefix(&prefix).is_ok() {
-            let mut f = fs::File::open(mod_path).unwrap();
-            let mut s = String::new();
-            f.read_to_string(&mut s).unwrap();
-            let src = CString::new(s).unwrap();
-            duktape::duk_push_string(context, src.as_ptr());
-            return 1;
-        } else {
-            let err = CString::new(format!("module not found: {:?}", id)).unwrap();
-            duktape::duk_error_raw(context, duktape::DUK_ERR_ERROR as i32, ptr::null_mut(), 0,err.as_ptr());
-            return duktape::DUK_RET_ERROR;
+
+        let file = locations.iter().map(PathBuf::as_path).filter_map(resolve_module).find(|file| allowed(file));
+        match file {
+            Some(file) => {
+                let file_id = module_id(&file);
+                let filename_prop = CString::new("filename").unwrap();
+                push_string(context, file.to_str().unwrap());
+                duktape::duk_put_prop_string(context, 3, filename_prop.as_ptr());
+                if file_id == id {
+                    let mut s = String::new();
+                    fs::File::open(&file).and_then(|mut f| f.read_to_string(&mut s)).unwrap();
+                    push_string(context, &s);
+                } else {
+                    // the module is loaded under its own identifier, the redirect is not retained
+                    // as the same identifier may refer to a different module elsewhere
+                    push_string(context, &format!("module.exports = require({}); delete Duktape.modLoaded[module.id];",
+                                                  JsonValue::String(file_id)));
+                }
+                1
+            },
+            None => {
+                let tried: Vec<_> = locations.iter().map(|location| location.to_string_lossy().into_owned()).collect();
+                let err = CString::new(format!("module not found: {:?} (tried {})", id, tried.join(", "))).unwrap();
+                duktape::duk_error_raw(context, duktape::DUK_ERR_ERROR as i32, ptr::null_mut(), 0,err.as_ptr());
+                duktape::DUK_RET_ERROR
+            },
         }
     }
 }
 
-impl<R: Record> DuktapeReducer<R> {
-    pub fn new<SF: SourceFiles>(source_files: SF) -> Result<Self, Error> {
-        let context = unsafe {
-            duktape::duk_create_heap(None, None, None,ptr::null_mut(), Some(fatal_handler))
-        };
-        #[cfg(feature = "duktape-require")]
-        let str_duktape = CString::new("Duktape").unwrap();
-        #[cfg(feature = "duktape-require")]
-        unsafe {
-            let str_mod_search = CString::new("modSearch").unwrap();
-            duktape::duk_module_duktape_init(context);
-            duktape::duk_get_global_string(context, str_duktape.as_ptr());
-            // function
-            duktape::duk_push_c_function(context, Some(DuktapeReducer::<R>::mod_search), 4);
-            duktape::duk_put_prop_string(context, -2, str_mod_search.as_ptr());
-            duktape::duk_pop(context);
+/// Converts a path to a module identifier (paths are absolute, identifiers can't be)
+#[cfg(feature = "duktape-require")]
+fn module_id(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/").trim_start_matches('/').to_string()
+}
+
+/// Converts a module identifier back to a path
+#[cfg(feature = "duktape-require")]
+fn module_path(id: &str) -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(id)
+    } else {
+        PathBuf::from(format!("/{}", id))
+    }
+}
+
+/// Returns true if the file is within the directory (following symlinks and `..`)
+#[cfg(feature = "duktape-require")]
+fn is_within(file: &Path, dir: &Path) -> bool {
+    match (fs::canonicalize(file), fs::canonicalize(dir)) {
+        (Ok(file), Ok(dir)) => file.starts_with(dir),
+        _ => false,
+    }
+}
+
+/// Sets up `require`, allowing modules to be loaded from reducer and library directories
+#[cfg(feature = "duktape-require")]
+unsafe fn init_require<R: Record>(context: *mut duktape::duk_context, directories: &[PathBuf], libraries: &[PathBuf]) {
+    let str_duktape = CString::new("Duktape").unwrap();
+    let str_mod_search = CString::new("modSearch").unwrap();
+    duktape::duk_module_duktape_init(context);
+    duktape::duk_get_global_string(context, str_duktape.as_ptr());
+    // function
+    duktape::duk_push_c_function(context, Some(DuktapeReducer::<R>::mod_search), 4);
+    duktape::duk_put_prop_string(context, -2, str_mod_search.as_ptr());
+    // read-only path lists
+    let all: Vec<_> = directories.iter().chain(libraries).collect();
+    for (prop, paths) in &[("paths", all), ("libraries", libraries.iter().collect())] {
+        let str_prop = CString::new(*prop).unwrap();
+        duktape::duk_push_string(context, str_prop.as_ptr());
+        duktape::duk_push_array(context);
+        for (i, path) in paths.iter().enumerate() {
+            push_string(context, path.to_str().unwrap());
+            duktape::duk_put_prop_index(context, -2, i as u32);
         }
+        duktape::duk_def_prop(context, -3, duktape::DUK_DEFPROP_HAVE_VALUE);
+    }
+    duktape::duk_pop(context);
+}
 
-        #[cfg(feature = "duktape-require")]
-        let mut paths_counter = 0;
-        #[cfg(feature = "duktape-require")] 
-        let paths_array = {
-            let str_paths_prop = CString::new("paths").unwrap();
-            unsafe {
-                duktape::duk_get_global_string(context, str_duktape.as_ptr());
-                duktape::duk_push_string(context, str_paths_prop.as_ptr());
-                duktape::duk_push_array(context);
-                let ptr = duktape::duk_get_heapptr(context, -1);
-                duktape::duk_def_prop(context, -3, duktape::DUK_DEFPROP_HAVE_VALUE);
-                duktape::duk_pop(context);
-                ptr
-            }
-        };
-        
+/// Resolves a file, trying `.js` extension and `index.js` in a directory
+#[cfg(feature = "duktape-require")]
+fn resolve_file(path: &Path) -> Option<PathBuf> {
+    let mut js = path.as_os_str().to_owned();
+    js.push(".js");
+    vec![path.to_path_buf(), PathBuf::from(js), path.join("index.js")].into_iter().find(|path| path.is_file())
+}
 
-        #[cfg(feature = "duktape-require")]
-        let mut directories = vec![];
+/// Resolves a module like [`resolve_file`] does, also looking for `main` in `package.json`
+#[cfg(feature = "duktape-require")]
+fn resolve_module(path: &Path) -> Option<PathBuf> {
+    if path.is_dir() {
+        let main = fs::read(path.join("package.json")).ok()
+            .and_then(|package| serde_json::from_slice::<JsonValue>(&package).ok())
+            .and_then(|package| package.get("main").and_then(JsonValue::as_str).map(|main| path.join(main)));
+        if let Some(file) = main.as_ref().and_then(|main| resolve_file(main)) {
+            return Some(file);
+        }
+    }
+    resolve_file(path)
+}
+
+impl<R: Record> DuktapeReducer<R> {
+    pub fn new<SF: SourceFiles>(source_files: SF) -> Result<Self, Error> {
+        DuktapeReducer::with_limits(source_files, Limits::default())
+    }
 
+    /// Creates a reducer with execution limits
+    pub fn with_limits<SF: SourceFiles>(source_files: SF, limits: Limits) -> Result<Self, Error> {
+        let (context, budget) = unsafe { create_heap(limits, stderr_logger()) };
+        // from here on, the heap is owned by the reducer so that it is freed on errors
+        let mut reducer = DuktapeReducer {
+            context,
+            budget,
+            error_policy: ErrorPolicy::default(),
+            stopped: false,
+            limits_exceeded: false,
+            reducers: 0,
+            filenames: vec![],
+            functions: vec![],
+            directories: vec![],
+            libraries: vec![],
+            phantom_data: PhantomData,
+        };
         let mut reducers = 0;
         let mut filenames = vec![];
         let mut functions = vec![];
+        #[cfg(feature = "duktape-require")]
+        let libraries = source_files.library_paths()?;
         let files = source_files.source_files()?;
         // in test builds, we guarantee the order of files, but not in other builds as
         // it is not a great idea to rely on the order of these files
@@ -251,8 +955,12 @@ impl<R: Record> DuktapeReducer<R> {
         let mut files : Vec<_> = files.collect();
         #[cfg(test)]
         files.sort();
-        for file in files {
-            #[cfg(feature = "duktape-require")] {
+        #[cfg(not(test))]
+        let files : Vec<_> = files.collect();
+
+        #[cfg(feature = "duktape-require")] {
+            let mut directories = vec![];
+            for file in files.iter() {
                 let path = if !file.is_dir() {
                     file.parent().unwrap_or(Path::new("/")).to_path_buf()
                 } else {
@@ -260,14 +968,25 @@ impl<R: Record> DuktapeReducer<R> {
                 };
                 if !directories.iter().any(|d| d == &path) {
                     directories.push(path);
-                    let str_path = CString::new(directories.last().unwrap().to_str().unwrap()).unwrap();
-                    unsafe {
-                        duktape::duk_push_heapptr(context, paths_array);
-                        duktape::duk_push_string(context, str_path.as_ptr());
-                        duktape::duk_put_prop_index(context, -2, paths_counter);
-                        duktape::duk_pop(context);
-                    }
-                    paths_counter += 1;
+                }
+            }
+            unsafe { init_require::<R>(context, &directories, &libraries); }
+            reducer.directories = directories;
+            reducer.libraries = libraries;
+        }
+
+        for file in files {
+            #[cfg(feature = "native-reducers")] {
+                // dynamic libraries are loaded by NativeReducer
+                if file.is_file() && file.extension() == Some(OsStr::new(std::env::consts::DLL_EXTENSION)) {
+                    continue;
+                }
+            }
+
+            #[cfg(feature = "wasm-reducers")] {
+                // WebAssembly modules are loaded by WasmReducer
+                if file.is_file() && file.extension() == Some(OsStr::new("wasm")) {
+                    continue;
                 }
             }
 
@@ -291,13 +1010,68 @@ impl<R: Record> DuktapeReducer<R> {
             }
 
        }
-        Ok(DuktapeReducer {
-            context,
-            reducers,
-            filenames,
-            functions,
-            phantom_data: PhantomData,
-        })
+        reducer.reducers = reducers;
+        reducer.filenames = filenames;
+        reducer.functions = functions;
+        Ok(reducer)
+    }
+
+    /// Returns execution limits
+    pub fn limits(&self) -> &Limits {
+        unsafe { &(*self.budget).limits }
+    }
+
+    /// Changes execution limits
+    pub fn set_limits(&mut self, limits: Limits) {
+        unsafe { (*self.budget).limits = limits; }
+    }
+
+    /// Returns the error policy
+    pub fn error_policy(&self) -> ErrorPolicy {
+        self.error_policy
+    }
+
+    /// Changes the error policy
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+
+    /// Returns the logger receiving `console` output
+    pub fn logger(&self) -> Logger {
+        unsafe { (*self.budget).console.logger.clone() }
+    }
+
+    /// Changes the logger receiving `console` output ([`stderr_logger`] by default)
+    ///
+    /// Note that module bodies are evaluated when the reducer is created (or cloned),
+    /// so their output goes to the logger in effect at that time.
+    ///
+    /// [`stderr_logger`]: fn.stderr_logger.html
+    pub fn set_logger(&mut self, logger: Logger) {
+        unsafe { (*self.budget).console.logger = logger; }
+    }
+
+    /// Copies location details of the error on top of the stack
+    unsafe fn error_details(ctx: *mut duktape::duk_context, error: &mut Map<String, JsonValue>) {
+        if duktape::duk_is_object(ctx, -1) != 1 {
+            return;
+        }
+        // Duktape tracks line numbers, but not columns
+        let line_prop = CString::new("lineNumber").unwrap();
+        duktape::duk_get_prop_string(ctx, -1, line_prop.as_ptr());
+        if duktape::duk_is_number(ctx, -1) == 1 {
+            error.insert("line".into(), JsonValue::from(duktape::duk_get_int(ctx, -1)));
+        }
+        duktape::duk_pop(ctx);
+        for (prop, key) in &[("fileName", "source"), ("stack", "stack")] {
+            let prop = CString::new(*prop).unwrap();
+            duktape::duk_get_prop_string(ctx, -1, prop.as_ptr());
+            if duktape::duk_is_string(ctx, -1) == 1 {
+                let value = CStr::from_ptr(duktape::duk_get_string(ctx, -1)).to_string_lossy().into_owned();
+                error.insert((*key).into(), JsonValue::String(value));
+            }
+            duktape::duk_pop(ctx);
+        }
     }
 
     unsafe fn load_source(file: PathBuf, context: *mut duktape::duk_context) -> Result<Vec<u8>, Error> {
@@ -340,6 +1114,9 @@ impl<R: Record> DuktapeReducer<R> {
             ptr::copy_nonoverlapping(data, func.as_mut_ptr() as *mut _, sz);
             duktape::duk_pop(context);
             // load module
+            let console = &mut (*Budget::of(context)).console;
+            console.file = Some(file.clone());
+            console.record = None;
             DuktapeReducer::<R>::load_module(context)?;
             // If module.export is not function, bail
             if duktape::duk_is_function(context, -1) != 1 {
@@ -363,6 +1140,8 @@ impl<R: Record> DuktapeReducer<R> {
     /// multiple items, helps avoiding re-reading and re-compiling
     /// reducer functions every time.
     pub fn reset_state(&mut self) {
+        self.stopped = false;
+        self.limits_exceeded = false;
         for i in 0..self.reducers {
             unsafe {
                 duktape::duk_push_object(self.context);
@@ -377,9 +1156,9 @@ impl<R: Record> DuktapeReducer<R> {
 
 impl<R: Record> Clone for DuktapeReducer<R> {
     fn clone(&self) -> Self {
-        let context = unsafe {
-            duktape::duk_create_heap(None, None, None,ptr::null_mut(), Some(fatal_handler))
-        };
+        let (context, budget) = unsafe { create_heap(self.limits().clone(), self.logger()) };
+        #[cfg(feature = "duktape-require")]
+        unsafe { init_require::<R>(context, &self.directories, &self.libraries); }
 
         unsafe {
             for (i, func) in self.functions.iter().enumerate() {
@@ -388,6 +1167,7 @@ impl<R: Record> Clone for DuktapeReducer<R> {
                 duktape::duk_config_buffer(context, -1, func.as_ptr() as *mut _, func.len());
                 duktape::duk_load_function(context);
                 // obtain the module
+                (*budget).console.file = Some(self.filenames[i].clone());
                 DuktapeReducer::<R>::load_module(context).unwrap(); // since it's a clone we assume the first load went fine
                 // transfer state
                 duktape::duk_push_null(self.context);
@@ -401,9 +1181,15 @@ impl<R: Record> Clone for DuktapeReducer<R> {
         }
         DuktapeReducer {
             context,
+            budget,
+            error_policy: self.error_policy,
+            stopped: self.stopped,
+            limits_exceeded: self.limits_exceeded,
             reducers: self.reducers,
             filenames: self.filenames.clone(),
             functions: self.functions.clone(),
+            directories: self.directories.clone(),
+            libraries: self.libraries.clone(),
             phantom_data: PhantomData,
         }
     }
@@ -417,9 +1203,15 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
     fn reduce(&mut self, mut state: Self::State, item: &Self::Item) -> Self::State {
         use serde_json;
 
+        if self.stopped {
+            return state;
+        }
+        let mut errors = vec![];
+
         let json = serde_json::to_string(&JsonValue::Object(state.clone())).unwrap();
         unsafe {
             let ctx = self.context;
+            (*self.budget).console.record = Some(item.encoded_hash().as_ref().into());
 
             #[cfg(feature = "cesu8")]
             let json_cstring = CString::new(cesu8::to_cesu8(&json)).unwrap();
@@ -429,6 +1221,12 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
 
             // Item (record)
             duktape::duk_push_object(ctx);
+            // record.text(), record.types, etc. are inherited
+            let prototype = CString::new(RECORD_PROTOTYPE).unwrap();
+            duktape::duk_push_global_stash(ctx);
+            duktape::duk_get_prop_string(ctx, -1, prototype.as_ptr());
+            duktape::duk_remove(ctx, -2);
+            duktape::duk_set_prototype(ctx, -2);
             // item.hash
             let hash = CString::new(item.encoded_hash().as_ref()).unwrap();
             duktape::duk_push_string(ctx, hash.as_ptr());
@@ -438,11 +1236,10 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
             duktape::duk_push_object(ctx);
             #[cfg(feature = "duktape-mmap")]
             let mut mmaps = vec![];
-            for (name, reader) in item.file_iter() {
+            for (name, mut reader) in item.file_iter() {
                 let filename = CString::new(name.as_ref()).unwrap();
-                #[cfg(feature = "duktape-mmap")] {
-                    // avoid unused warning
-                    let _ = reader;
+                #[cfg(feature = "duktape-mmap")]
+                let mmapped = {
                     #[cfg(windows)] // replace slashes with backslashes
                     let name = name.as_ref().replace("/", "\\");
                     #[cfg(not(windows))]
@@ -450,10 +1247,14 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
 
                     let path = item.path().join(name);
 
-                    if fs::metadata(&path).unwrap().len() == 0 {
+                    // packed records don't have their files on the file system
+                    if !path.is_file() {
+                        false
+                    } else if fs::metadata(&path).unwrap().len() == 0 {
                         // if the file is empty, it can't be mmapped
                         // (also, no reason to do so anyway)
                         duktape::duk_push_buffer_raw(ctx, 0, duktape::DUK_BUF_MODE_FIXED);
+                        true
                     } else {
                         let file = fs::File::open(&path).unwrap();
                         let mmap = memmap::MmapOptions::new().map(&file).unwrap();
@@ -461,9 +1262,12 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
                         mmaps.push(mmap);
                         let mmap_ref = &mmaps[mmaps.len() - 1];
                         duktape::duk_config_buffer(ctx, -1, mmap_ref.as_ptr() as *mut _, mmap_ref.len());
+                        true
                     }
-                }
-                #[cfg(not(feature = "duktape-mmap"))] {
+                };
+                #[cfg(not(feature = "duktape-mmap"))]
+                let mmapped = false;
+                if !mmapped {
                     use std::io::Read;
                     // INEFFICIENT BUT WORKS FOR NOW {
                     let mut buf = vec![];
@@ -499,41 +1303,50 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
                 duktape::duk_require_object(ctx, -1);
 
                 // execute
+                (*self.budget).console.file = Some(self.filenames[i as usize].clone());
+                (*self.budget).start();
                 let res = duktape::duk_pcall_method(ctx,2);
+                let exceeded = (*self.budget).stop();
+                self.limits_exceeded |= exceeded.is_some();
+
+                let mut error = Map::new();
+                error.insert("file".into(), JsonValue::String(self.filenames[i as usize].to_str().unwrap().into()));
+                error.insert("record".into(), JsonValue::String(item.encoded_hash().as_ref().into()));
 
                // now, check for error
                 if res as u32 == duktape::DUK_EXEC_ERROR {
+                    // details have to be extracted before the error is converted to a string
+                    DuktapeReducer::<R>::error_details(ctx, &mut error);
                     let err = std::ffi::CStr::from_ptr(duktape::duk_safe_to_lstring(ctx, -1, ptr::null_mut()));
-                    {
-                        let arr = state.entry(String::from("errors")).or_insert(JsonValue::Array(vec![]));
-                        let mut error = Map::new();
-                        error.insert("file".into(), JsonValue::String(self.filenames[i as usize].to_str().unwrap().into()));
-                        error.insert("error".into(), JsonValue::String(err.to_str().unwrap().into()));
-                        arr.as_array_mut().unwrap().push(JsonValue::Object(error));
-                    }
-                    return state;
-                }
-
-                if duktape::duk_is_object(ctx, -1) == 1 {
+                    error.insert("error".into(), JsonValue::String(exceeded.unwrap_or_else(|| err.to_string_lossy().into())));
+                } else if duktape::duk_is_object(ctx, -1) == 1 {
                     // drop extra state
                     duktape::duk_swap_top(ctx, -2);
                     duktape::duk_pop(ctx);
                     // now it should be [item, current item state] again
+                    continue;
                 } else if duktape::duk_is_undefined(ctx, -1) == 1 {
                     // restore previous state
-                    duktape::duk_copy(ctx, -2, -1);
+                    duktape::duk_pop(ctx);
+                    continue;
                 } else {
                     let err = format!("TypeError: invalid return value {}, expected an object", std::ffi::CStr::from_ptr(duktape::duk_safe_to_lstring(ctx, -1, ptr::null_mut())).to_string_lossy());
-                    {
-                        let arr = state.entry(String::from("errors")).or_insert(JsonValue::Array(vec![]));
-                        let mut error = Map::new();
-                        error.insert("file".into(), JsonValue::String(self.filenames[i as usize].to_str().unwrap().into()));
-                        error.insert("error".into(), JsonValue::String(err));
-                        arr.as_array_mut().unwrap().push(JsonValue::Object(error));
-                    }
-                    return state;
+                    error.insert("error".into(), JsonValue::String(err));
                 }
 
+                // [item, previous item state, error or invalid value]
+                errors.push(JsonValue::Object(error));
+                match self.error_policy {
+                    ErrorPolicy::SkipReducer => {
+                        duktape::duk_pop(ctx);
+                    },
+                    ErrorPolicy::SkipRecord | ErrorPolicy::Stop => {
+                        duktape::duk_pop_3(ctx);
+                        self.stopped = self.error_policy == ErrorPolicy::Stop;
+                        push_errors(&mut state, errors);
+                        return state;
+                    },
+                }
             }
 
             // remove item
@@ -556,8 +1369,73 @@ impl<R: Record + HasPath> Reducer for DuktapeReducer<R> {
             // drop the json
             duktape::duk_pop(ctx);
 
-            map
+            let mut map = map;
+            push_errors(&mut map, errors);
+            map
+        }
+    }
+
+    /// States are not cacheable once execution limits were exceeded, as it
+    /// might not happen again (for example, if the system was busy), or once
+    /// reduction was stopped, as records added later must be ignored, too
+    fn cacheable(&self) -> bool {
+        !self.limits_exceeded && !self.stopped
+    }
+
+    fn reset_state(&mut self) {
+        DuktapeReducer::reset_state(self)
+    }
+
+    /// Returns an array of every reducer's `this`
+    ///
+    /// Only JSON values survive, the same way they do when the reducer is
+    /// cloned. If `this` can't be encoded at all (for example, if it has
+    /// circular references), `null` is returned, which can't be restored.
+    fn own_state(&self) -> JsonValue {
+        unsafe {
+            let ctx = self.context;
+            duktape::duk_push_array(ctx);
+            for i in 0..self.reducers {
+                duktape::duk_dup(ctx, i * 2 + 1);
+                duktape::duk_put_prop_index(ctx, -2, i as duktape::duk_uarridx_t);
+            }
+            let res = duktape::duk_safe_call(ctx, Some(json_encode), ptr::null_mut(), 1, 1);
+            let state = if res as u32 == duktape::DUK_EXEC_SUCCESS && duktape::duk_is_string(ctx, -1) == 1 {
+                serde_json::from_str(&decode_string(CStr::from_ptr(duktape::duk_get_string(ctx, -1)))).unwrap_or(JsonValue::Null)
+            } else {
+                JsonValue::Null
+            };
+            duktape::duk_pop(ctx);
+            state
+        }
+    }
+
+    fn restore_own_state(&mut self, state: &JsonValue) -> bool {
+        match state.as_array() {
+            Some(states) if states.len() == self.reducers as usize && states.iter().all(JsonValue::is_object) => (),
+            _ => return false,
+        }
+        unsafe {
+            let ctx = self.context;
+            push_string(ctx, &state.to_string());
+            duktape::duk_json_decode(ctx, -1);
+            for i in 0..self.reducers {
+                duktape::duk_get_prop_index(ctx, -1, i as duktape::duk_uarridx_t);
+                duktape::duk_replace(ctx, i * 2 + 1);
+            }
+            duktape::duk_pop(ctx);
         }
+        true
+    }
+}
+
+pub(crate) fn push_errors(state: &mut Map<String, JsonValue>, errors: Vec<JsonValue>) {
+    if errors.is_empty() {
+        return;
+    }
+    let arr = state.entry(String::from("errors")).or_insert(JsonValue::Array(vec![]));
+    if let Some(arr) = arr.as_array_mut() {
+        arr.extend(errors);
     }
 }
 
@@ -692,6 +1570,35 @@ mod tests {
         assert_eq!(state.get("hello").unwrap(), &JsonValue::Number(Number::from(3)));
     }
 
+    #[test]
+    fn cached_reducer_state() {
+        use crate::reducers::cache::ReductionCache;
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(&tmp).unwrap();
+        let cache = ReductionCache::new(tmp.join("cache"), repo.config().hashing_algorithm().clone(),
+                                        repo.config().encoding().clone());
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), "module.exports = function() { \
+          this.counter = (this.counter || 0) + 1; \
+          return {hello: this.counter}; \
+        }").unwrap();
+
+        repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let state = cache.reduce_with_reducer_and_state(&repo, &mut DuktapeReducer::new(&repo).unwrap(), b"r", "",
+                                                        Default::default()).unwrap();
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::from(2));
+
+        repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let cached = cache.reduce_with_reducer_and_state(&repo, &mut DuktapeReducer::new(&repo).unwrap(), b"r", "",
+                                                         Default::default()).unwrap();
+        let full = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        assert_eq!(cached, full);
+        assert_eq!(cached.get("hello").unwrap(), &JsonValue::from(3));
+    }
+
     #[test]
     fn multiple_reducers() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
@@ -1052,7 +1959,7 @@ mod tests {
         let err_str = "Error: module not found: \"index.js\"";
 
         assert_matches!(DuktapeReducer::<crate::repository::Record>::new(&repo),
-        Err(Error::ExecutionError { ref error }) if error == err_str);
+        Err(Error::ExecutionError { ref error }) if error.starts_with(err_str));
     }
 
 
@@ -1068,9 +1975,11 @@ mod tests {
         let mut f = fs::File::create(repo.path().join("reducers/reducer.js")).unwrap();
         f.write(b"module.exports = require(\"reducer/index.js\");").unwrap();
 
-        let err_str = "Error: module not found: \"reducer/index.js\"";
+        let err_str = format!("Error: module not found: \"reducer/index.js\" (tried {}, {})",
+                              repo.path().join("reducers/reducer/index.js").display(),
+                              repo.path().join("reducers/node_modules/reducer/index.js").display());
         assert_matches!(DuktapeReducer::<crate::repository::Record>::new(&repo),
-        Err(Error::ExecutionError { ref error }) if error == err_str);
+        Err(Error::ExecutionError { ref error }) if error == &err_str);
     }
 
 
@@ -1154,7 +2063,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         let err_str = "Error: module not found: \"reducer/index.js\"";
-        assert_matches!(err, Error::ExecutionError { ref error } if error == err_str);
+        assert_matches!(err, Error::ExecutionError { ref error } if error.starts_with(err_str));
     }
 
     #[cfg(feature = "duktape-require")]
@@ -1181,4 +2090,499 @@ mod tests {
         assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
     }
 
+    #[cfg(feature = "duktape-require")]
+    #[test]
+    fn require_node_modules() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        let package = repo.path().join("reducers/node_modules/pkg");
+        fs::create_dir_all(package.join("lib")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), "module.exports = require('pkg');").unwrap();
+        fs::write(package.join("package.json"), r#"{"name": "pkg", "main": "lib/main.js"}"#).unwrap();
+        // relative to the module, without an extension
+        fs::write(package.join("lib/main.js"), "module.exports = require('./helper');").unwrap();
+        fs::write(package.join("lib/helper.js"),
+                  "module.exports = function(state, record) { return {hello: record.hash}; }").unwrap();
+
+        let record = repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let mut reducer = DuktapeReducer::new(&repo).unwrap();
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
+
+        // clones can load modules, too
+        let state = repo.reduce_with_reducer(&mut reducer.clone()).unwrap();
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
+    }
+
+    #[test]
+    fn source_files_hash_relative() {
+        let tmp = TempDir::new("sit").unwrap().into_path();
+        let repo = Repository::new(tmp.join("1").join(".sit")).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), "module.exports = function(state) { return state; }").unwrap();
+        let algorithm = repo.config().hashing_algorithm().clone();
+        let hash = source_files_hash(&repo, &algorithm).unwrap();
+
+        // moving the repository doesn't change the hash
+        fs::rename(tmp.join("1"), tmp.join("2")).unwrap();
+        let repo = Repository::open(tmp.join("2").join(".sit")).unwrap();
+        assert_eq!(source_files_hash(&repo, &algorithm).unwrap(), hash);
+
+        // renaming a reducer does
+        fs::rename(repo.path().join("reducers/reducer.js"), repo.path().join("reducers/renamed.js")).unwrap();
+        assert_ne!(source_files_hash(&repo, &algorithm).unwrap(), hash);
+    }
+
+    #[cfg(feature = "duktape-require")]
+    #[test]
+    fn require_node_modules_per_module() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        // both modules have a package with the same name
+        for module in &["a", "b"] {
+            let reducers = repo.modules_path().join(module).join("reducers");
+            fs::create_dir_all(reducers.join("node_modules/pkg")).unwrap();
+            fs::write(reducers.join(format!("{}.js", module)),
+                      "var pkg = require('pkg'); module.exports = function(state) { return Object.assign(state, pkg); }").unwrap();
+            fs::write(reducers.join("node_modules/pkg/index.js"), format!("module.exports = {{{}: true}};", module)).unwrap();
+        }
+
+        repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        assert_eq!(state["a"], true);
+        assert_eq!(state["b"], true);
+    }
+
+    #[cfg(feature = "duktape-require")]
+    #[test]
+    fn require_library() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.modules_path().join("test/reducers")).unwrap();
+        fs::create_dir_all(repo.modules_path().join("library/lib/shared")).unwrap();
+        fs::write(repo.modules_path().join("test/reducers/reducer.js"), "module.exports = require('shared');").unwrap();
+        fs::write(repo.modules_path().join("library/lib/shared/index.js"),
+                  "module.exports = function(state, record) { return {hello: record.hash}; }").unwrap();
+
+        let record = repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
+    }
+
+    #[cfg(feature = "duktape-require")]
+    #[test]
+    fn require_declared_library() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.modules_path().join("test/reducers")).unwrap();
+        fs::create_dir_all(repo.modules_path().join("library/js/shared")).unwrap();
+        fs::create_dir_all(repo.modules_path().join("outside/lib")).unwrap();
+        fs::write(repo.modules_path().join("test/reducers/reducer.js"), "module.exports = require('shared');").unwrap();
+        fs::write(repo.modules_path().join("library/package.json"), r#"{"directories": {"lib": "js"}}"#).unwrap();
+        fs::write(repo.modules_path().join("library/js/shared/index.js"),
+                  "module.exports = function(state, record) { return {hello: record.hash}; }").unwrap();
+        // declared directories can't be outside of the module
+        fs::write(repo.modules_path().join("outside/package.json"), r#"{"directories": {"lib": "../library/js"}}"#).unwrap();
+
+        assert_eq!(repo.library_paths().unwrap(), vec![repo.modules_path().join("library/js"),
+                                                       repo.modules_path().join("outside/lib")]);
+        let record = repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::String(record.encoded_hash()));
+    }
+
+    #[cfg(feature = "duktape-require")]
+    #[test]
+    fn require_package_outside() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers/node_modules/pkg")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), "module.exports = require('pkg');").unwrap();
+        // main can't escape allowed directories
+        fs::write(repo.path().join("reducers/node_modules/pkg/package.json"), r#"{"main": "../../../outside.js"}"#).unwrap();
+        fs::write(repo.path().join("outside.js"), "module.exports = function() {};").unwrap();
+
+        assert_matches!(DuktapeReducer::<crate::repository::Record>::new(&repo),
+        Err(Error::ExecutionError { ref error }) if error.starts_with("Error: module not found: \"pkg\""));
+    }
+
+    #[test]
+    fn timeout() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), "module.exports = function(state) { while (true) {} }").unwrap();
+
+        repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let limits = Limits { timeout: Some(Duration::from_millis(100)), ..Default::default() };
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::with_limits(&repo, limits).unwrap()).unwrap();
+
+        assert_eq!(state["errors"][0]["error"], "RangeError: execution time limit of 100ms exceeded");
+    }
+
+    #[test]
+    fn exceeded_limits_not_cacheable() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), "module.exports = function(state) { while (true) {} }").unwrap();
+
+        repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let limits = Limits { instructions: Some(1_000_000), ..Default::default() };
+        let mut reducer = DuktapeReducer::with_limits(&repo, limits).unwrap();
+        assert!(reducer.cacheable());
+        repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert!(!reducer.cacheable());
+        assert!(!reducer.clone().cacheable());
+        reducer.reset_state();
+        assert!(reducer.cacheable());
+    }
+
+    #[test]
+    fn instruction_limit() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), "module.exports = function(state) { while (true) {} }").unwrap();
+
+        repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let limits = Limits { instructions: Some(1_000_000), ..Default::default() };
+        let mut reducer = DuktapeReducer::with_limits(&repo, limits.clone()).unwrap();
+        assert_eq!(reducer.limits(), &limits);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+
+        assert_eq!(state["errors"][0]["error"], "RangeError: instruction limit of 1000000 exceeded");
+    }
+
+    #[test]
+    fn within_limits() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"),
+                  "module.exports = function(state) { var a = []; for (var i = 0; i < 1000; i++) { a.push(i); }; return {sum: a.length}; }").unwrap();
+
+        repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let limits = Limits {
+            timeout: Some(Duration::from_secs(10)),
+            instructions: Some(100_000_000),
+            heap_size: Some(64 * 1024 * 1024),
+        };
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::with_limits(&repo, limits).unwrap()).unwrap();
+
+        assert_eq!(state.get("sum").unwrap(), &JsonValue::from(1000));
+        assert!(state.get("errors").is_none());
+    }
+
+    #[test]
+    fn heap_size_limit() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"),
+                  "module.exports = function(state) { var a = []; while (true) { a.push('' + a.length); } }").unwrap();
+
+        repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let mut reducer = DuktapeReducer::new(&repo).unwrap();
+        reducer.set_limits(Limits { heap_size: Some(4 * 1024 * 1024), ..Default::default() });
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+
+        assert_eq!(state["errors"][0]["error"], "RangeError: heap size limit of 4194304 bytes exceeded");
+    }
+
+    #[test]
+    fn module_timeout() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), "while (true) {}").unwrap();
+
+        let limits = Limits { timeout: Some(Duration::from_millis(100)), ..Default::default() };
+        let result = DuktapeReducer::<crate::repository::Record>::with_limits(&repo, limits);
+        assert_matches!(result.unwrap_err(), Error::ExecutionError { ref error } if error == "RangeError: execution time limit of 100ms exceeded");
+    }
+
+    #[test]
+    fn error_details() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), "module.exports = function(state) {\n  throw new Error('oops');\n}").unwrap();
+
+        let record = repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+
+        let error = &state["errors"][0];
+        assert_eq!(error["error"], "Error: oops");
+        assert_eq!(error["record"], JsonValue::String(record.encoded_hash()));
+        assert_eq!(error["line"], 2);
+        assert_eq!(error["source"], repo.path().join("reducers/reducer.js").to_str().unwrap());
+        assert!(error["stack"].as_str().unwrap().contains("oops"));
+    }
+
+    fn error_policy_repository() -> Repository<crate::repository::ModuleDirectory<PathBuf>> {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        // fails on the first record
+        fs::write(repo.path().join("reducers/1.js"),
+                  "module.exports = function(state) { if (!state.count) { throw new Error('first') }; }").unwrap();
+        fs::write(repo.path().join("reducers/2.js"),
+                  "module.exports = function(state) { return Object.assign(state, {count: (state.count || 0) + 1}); }").unwrap();
+        repo.new_record(vec![("text", &b"1"[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![("text", &b"2"[..])].into_iter(), true).unwrap();
+        repo
+    }
+
+    #[test]
+    fn error_policy_skip_record() {
+        let repo = error_policy_repository();
+        let mut reducer = DuktapeReducer::new(&repo).unwrap();
+        assert_eq!(reducer.error_policy(), ErrorPolicy::SkipRecord);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        // both records fail on the first reducer
+        assert!(state.get("count").is_none());
+        assert_eq!(state["errors"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn error_policy_skip_reducer() {
+        let repo = error_policy_repository();
+        let mut reducer = DuktapeReducer::new(&repo).unwrap();
+        reducer.set_error_policy(ErrorPolicy::SkipReducer);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        // first record fails on the first reducer, but the second one still applies
+        assert_eq!(state["count"], 2);
+        assert_eq!(state["errors"].as_array().unwrap().len(), 1);
+        assert_eq!(state["errors"][0]["error"], "Error: first");
+    }
+
+    #[test]
+    fn error_policy_stop() {
+        let repo = error_policy_repository();
+        let mut reducer = DuktapeReducer::new(&repo).unwrap();
+        reducer.set_error_policy(ErrorPolicy::Stop);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert!(state.get("count").is_none());
+        assert_eq!(state["errors"].as_array().unwrap().len(), 1);
+        assert!(!reducer.cacheable());
+        // resetting the state resumes reduction
+        reducer.reset_state();
+        assert!(reducer.cacheable());
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert_eq!(state["errors"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn error_policy_keeps_reducer_state() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/1.js"),
+                  "module.exports = function(state) { this.seen = (this.seen || 0) + 1; return Object.assign(state, {seen: this.seen}); }").unwrap();
+        // fails on the first record, after the first reducer has changed its `this`
+        fs::write(repo.path().join("reducers/2.js"),
+                  "module.exports = function(state) { if (state.seen == 1) { throw new Error('first') }; }").unwrap();
+        repo.new_record(vec![("text", &b"1"[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![("text", &b"2"[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        assert_eq!(state["seen"], 2);
+        assert_eq!(state["errors"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn undefined_result_keeps_record() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/1.js"), "module.exports = function(state, record) { }").unwrap();
+        fs::write(repo.path().join("reducers/2.js"), "module.exports = function(state, record) { return {hash: record.hash}; }").unwrap();
+
+        let record = repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+
+        assert_eq!(state.get("hash").unwrap(), &JsonValue::String(record.encoded_hash()));
+    }
+
+    #[test]
+    fn console() {
+        use std::sync::Mutex;
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), r#"
+        module.exports = function(state, record) {
+          console.log("record", record.hash, {a: 1}, [1, 2], 3, null);
+          console.warn("warning");
+          console.error(new Error("failure"));
+          print("printed");
+          return state;
+        }
+        "#).unwrap();
+        let record = repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+
+        let entries = Arc::new(Mutex::new(vec![]));
+        let mut reducer = DuktapeReducer::new(&repo).unwrap();
+        let entries_ = entries.clone();
+        reducer.set_logger(Arc::new(move |entry: &LogEntry| entries_.lock().unwrap().push(entry.clone())));
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        // output doesn't end up in the state
+        assert!(state.get("errors").is_none());
+
+        let entries = entries.lock().unwrap();
+        assert_eq!(entries.len(), 4);
+        let file = Some(repo.path().join("reducers/reducer.js"));
+        let hash = Some(record.encoded_hash());
+        assert!(entries.iter().all(|entry| entry.file == file && entry.record == hash));
+        assert_eq!(entries[0].level, LogLevel::Info);
+        assert_eq!(entries[0].message, format!("record {} {{\"a\":1}} [1,2] 3 null", record.encoded_hash()));
+        assert_eq!(entries[1].level, LogLevel::Warn);
+        assert_eq!(entries[1].message, "warning");
+        assert_eq!(entries[2].level, LogLevel::Error);
+        assert!(entries[2].message.contains("failure"));
+        assert_eq!(entries[3].level, LogLevel::Info);
+        assert_eq!(entries[3].message, "printed");
+    }
+
+    #[test]
+    fn console_clone() {
+        use std::sync::Mutex;
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), "module.exports = function(state) { console.debug('called'); }").unwrap();
+        repo.new_record(vec![("text", &b"Title"[..])].into_iter(), true).unwrap();
+
+        let entries = Arc::new(Mutex::new(vec![]));
+        let mut reducer = DuktapeReducer::new(&repo).unwrap();
+        let entries_ = entries.clone();
+        reducer.set_logger(Arc::new(move |entry: &LogEntry| entries_.lock().unwrap().push(entry.clone())));
+        // clones retain the logger
+        let mut reducer = reducer.clone();
+        repo.reduce_with_reducer(&mut reducer).unwrap();
+
+        let entries = entries.lock().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].level, LogLevel::Debug);
+        assert_eq!(entries[0].message, "called");
+    }
+
+    #[test]
+    fn log_entry_display() {
+        let entry = LogEntry {
+            level: LogLevel::Warn,
+            file: Some(PathBuf::from("reducer.js")),
+            record: Some("abc".into()),
+            message: "message".into(),
+        };
+        assert_eq!(entry.to_string(), "warn reducer.js [abc]: message");
+    }
+
+    #[test]
+    fn record_helpers() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), r#"
+        module.exports = function(state, record) {
+          return {
+            text: record.text("text"),
+            json: record.json("data.json"),
+            missing: record.text("missing"),
+            types: record.types.sort(),
+            parents: record.parents,
+            timestamp: record.timestamp,
+            cached: record.types === record.types,
+            keys: Object.keys(record),
+          };
+        }
+        "#).unwrap();
+        let first = repo.new_record(vec![("text", &b"first"[..])].into_iter(), false).unwrap();
+        let prev = format!(".prev/{}", first.encoded_hash());
+        repo.new_record(vec![("text", "Hello, мир 😀".as_bytes()), ("data.json", "{\"a\": [1, \"😀\"]}".as_bytes()),
+                             (".type/B", &b""[..]), (".type/A", &b""[..]), (prev.as_str(), &b""[..]),
+                             (".timestamp", &b"2018-01-01T00:00:00Z\n"[..])].into_iter(), false).unwrap();
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        assert_eq!(state["text"], "Hello, мир 😀");
+        assert_eq!(state["json"]["a"][0], 1);
+        assert_eq!(state["json"]["a"][1], "😀");
+        assert!(state.get("missing").is_none());
+        assert_eq!(state["types"], JsonValue::Array(vec!["A".into(), "B".into()]));
+        assert_eq!(state["parents"], JsonValue::Array(vec![first.encoded_hash().into()]));
+        assert_eq!(state["timestamp"], "2018-01-01T00:00:00Z");
+        assert_eq!(state["cached"], true);
+        // helpers are not enumerable
+        assert_eq!(state["keys"], JsonValue::Array(vec!["hash".into(), "files".into()]));
+    }
+
+    #[test]
+    fn record_helpers_getter_files() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        // buffers returned by getters are only referenced by the stack
+        fs::write(repo.path().join("reducers/reducer.js"), r#"
+        module.exports = function(state, record) {
+          record.files = { get text() { return new Uint8Array([104, 105]); } };
+          return {text: record.text("text")};
+        }
+        "#).unwrap();
+        repo.new_record(vec![("text", &b"first"[..])].into_iter(), false).unwrap();
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        assert_eq!(state["text"], "hi");
+    }
+
+    #[test]
+    fn record_helpers_invalid_json() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        use std::fs;
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"),
+                  "module.exports = function(state, record) { return {json: record.json('data.json'), types: record.types}; }").unwrap();
+        repo.new_record(vec![("data.json", &b"{"[..])].into_iter(), false).unwrap();
+        let state = repo.reduce_with_reducer(&mut DuktapeReducer::new(&repo).unwrap()).unwrap();
+        assert!(state["errors"][0]["error"].as_str().unwrap().starts_with("SyntaxError"));
+    }
+
 }
@@ -1,8 +1,10 @@
 //! Reducers process issues' records to present a digestable view
 //!
 
+use serde_json::Value as JsonValue;
+
 /// Generic reducer trait
-pub trait Reducer: Sized {
+pub trait Reducer {
     /// State type
     type State;
     /// Item type
@@ -10,14 +12,40 @@ pub trait Reducer: Sized {
 
     /// Takes current state, item and returns new state
     fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State;
+    /// Returns false if states reduced so far must not be cached
+    /// (for example, if they depend on how long the reduction took)
+    fn cacheable(&self) -> bool {
+        true
+    }
+    /// Returns reducer's own state kept across items (such as `this` of JavaScript
+    /// reducers), so that it can be cached along with the reduced state
+    ///
+    /// `null` means there is no such state.
+    fn own_state(&self) -> JsonValue {
+        JsonValue::Null
+    }
+    /// Restores reducer's own state previously returned by [`own_state`],
+    /// returns false if it can't be restored
+    ///
+    /// [`own_state`]: #method.own_state
+    fn restore_own_state(&mut self, state: &JsonValue) -> bool {
+        state.is_null()
+    }
+    /// Resets state kept across items (such as whether reduction was stopped),
+    /// so that the reducer can be reused for another item
+    fn reset_state(&mut self) {}
     /// Chains two reducers together sequentially
-    fn chain<R: Reducer<State=Self::State, Item=Self::Item>>(self, other: R) -> ChainedReducer<Self, R> {
+    fn chain<R: Reducer<State=Self::State, Item=Self::Item>>(self, other: R) -> ChainedReducer<Self, R> where Self: Sized {
        ChainedReducer::new(self, other)
     }
 }
 
 #[cfg(feature = "duktape-reducers")]
 pub mod duktape;
+pub mod native;
+#[cfg(feature = "wasm-reducers")]
+pub mod wasm;
+pub mod cache;
 
 /// Chained reducer (consists of two reducers)
 ///
@@ -40,6 +68,58 @@ impl<T, I, R1: Reducer<State=T, Item=I>, R2: Reducer<State=T, Item=I>> Reducer f
     fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State {
         self.1.reduce(self.0.reduce(state, item), item)
     }
+
+    fn cacheable(&self) -> bool {
+        self.0.cacheable() && self.1.cacheable()
+    }
+
+    fn reset_state(&mut self) {
+        self.0.reset_state();
+        self.1.reset_state();
+    }
+
+    fn own_state(&self) -> JsonValue {
+        match (self.0.own_state(), self.1.own_state()) {
+            (JsonValue::Null, JsonValue::Null) => JsonValue::Null,
+            (s1, s2) => JsonValue::Array(vec![s1, s2]),
+        }
+    }
+
+    fn restore_own_state(&mut self, state: &JsonValue) -> bool {
+        match state {
+            JsonValue::Null => self.0.restore_own_state(state) && self.1.restore_own_state(state),
+            JsonValue::Array(states) if states.len() == 2 =>
+                self.0.restore_own_state(&states[0]) && self.1.restore_own_state(&states[1]),
+            _ => false,
+        }
+    }
+}
+
+/// Allows borrowed reducers to be chained (and otherwise used as reducers)
+/// without giving up their ownership
+impl<'a, R: Reducer + ?Sized> Reducer for &'a mut R {
+    type State = R::State;
+    type Item = R::Item;
+
+    fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State {
+        (**self).reduce(state, item)
+    }
+
+    fn cacheable(&self) -> bool {
+        (**self).cacheable()
+    }
+
+    fn reset_state(&mut self) {
+        (**self).reset_state()
+    }
+
+    fn own_state(&self) -> JsonValue {
+        (**self).own_state()
+    }
+
+    fn restore_own_state(&mut self, state: &JsonValue) -> bool {
+        (**self).restore_own_state(state)
+    }
 }
 
 #[cfg(test)]
@@ -63,4 +143,11 @@ mod tests {
         assert_eq!(R(1).chain(R(2)).reduce(0, &0), 2);
     }
 
+    #[test]
+    fn borrowed_reducer() {
+        let (mut r1, mut r2) = (R(1), R(2));
+        assert_eq!((&mut r1).chain(&mut r2).reduce(0, &0), 2);
+        assert_eq!((&mut r2).chain(&mut r1).reduce(0, &0), 1);
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,653 @@
+//! Native (Rust) reducers
+//!
+//! Besides JavaScript reducers, records can be reduced by native code.
+//! There are two ways of supplying it:
+//!
+//! * embedders can register any [`Reducer`] in-process with
+//!   [`NativeReducer::register`]
+//! * modules can ship reducers compiled as dynamic libraries
+//!   (`reducers/*.so`, `reducers/*.dylib` or `reducers/*.dll`, depending on
+//!   the platform), loaded with [`NativeReducer::load`] from the same
+//!   [`SourceFiles`] JavaScript reducers are loaded from (requires
+//!   `native-reducers` feature)
+//!
+//! Dynamic libraries talk to the host over a small, stable C ABI: they
+//! export a `sit_reducer_v1` function returning a pointer to [`VTable`].
+//! States are passed as JSON, records as arrays of files. Plugins written
+//! in Rust don't need to deal with this directly, [`export_reducer!`] wraps
+//! any `Reducer<State = Map<String, JsonValue>, Item = NativeRecord>`:
+//!
+//! ```ignore
+//! struct Counter;
+//!
+//! impl Reducer for Counter {
+//!     type State = Map<String, JsonValue>;
+//!     type Item = NativeRecord;
+//!
+//!     fn reduce(&mut self, mut state: Self::State, _item: &Self::Item) -> Self::State {
+//!         let count = state.get("count").and_then(JsonValue::as_u64).unwrap_or(0);
+//!         state.insert("count".into(), count.into());
+//!         state
+//!     }
+//! }
+//!
+//! sit_core::export_reducer!(Counter);
+//! ```
+//!
+//! Native reducers are regular reducers, so they can be chained with
+//! JavaScript ones through [`ChainedReducer`].
+//!
+//! Note that dynamic libraries run with full privileges of the host process,
+//! unlike JavaScript reducers. This is why loading them is opt-in.
+//!
+//! [`Reducer`]: ../trait.Reducer.html
+//! [`ChainedReducer`]: ../struct.ChainedReducer.html
+//! [`SourceFiles`]: ../duktape/trait.SourceFiles.html
+//! [`NativeReducer::register`]: struct.NativeReducer.html#method.register
+//! [`NativeReducer::load`]: struct.NativeReducer.html#method.load
+//! [`VTable`]: struct.VTable.html
+//! [`export_reducer!`]: ../../macro.export_reducer.html
+
+use std::io::{Cursor, Read};
+use std::os::raw::c_void;
+use std::{mem, ptr, slice};
+
+use serde_json::{Map, Value as JsonValue};
+
+use super::Reducer;
+use crate::Record;
+
+/// Version of the ABI described by [`VTable`]
+///
+/// [`VTable`]: struct.VTable.html
+pub const ABI_VERSION: u32 = 1;
+
+/// Name of the function dynamic libraries export
+///
+/// It takes no arguments and returns `*const VTable`.
+pub const ENTRY_POINT: &[u8] = b"sit_reducer_v1\0";
+
+/// Boxed reducer over JSON states
+pub type BoxedReducer<R> = Box<dyn Reducer<State = Map<String, JsonValue>, Item = R> + Send>;
+
+/// Borrowed byte slice
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Slice {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl Slice {
+    fn new(bytes: &[u8]) -> Self {
+        Slice { ptr: bytes.as_ptr(), len: bytes.len() }
+    }
+
+    /// Returns the bytes this slice points to
+    ///
+    /// The caller must ensure the memory is valid for the lifetime `'a`
+    pub unsafe fn as_bytes<'a>(&self) -> &'a [u8] {
+        if self.ptr.is_null() {
+            &[]
+        } else {
+            slice::from_raw_parts(self.ptr, self.len)
+        }
+    }
+}
+
+/// Borrowed record file
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct File {
+    pub name: Slice,
+    pub content: Slice,
+}
+
+/// Borrowed record
+#[repr(C)]
+#[derive(Debug)]
+pub struct RecordRef {
+    pub hash: Slice,
+    pub encoded_hash: Slice,
+    pub files: *const File,
+    pub files_len: usize,
+}
+
+/// Byte buffer allocated by the plugin
+///
+/// It is always released by the plugin's `free_buffer`
+#[repr(C)]
+#[derive(Debug)]
+pub struct Buffer {
+    pub ptr: *mut u8,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl Buffer {
+    fn empty() -> Self {
+        Buffer { ptr: ptr::null_mut(), len: 0, capacity: 0 }
+    }
+
+    fn from_vec(mut vec: Vec<u8>) -> Self {
+        let buffer = Buffer { ptr: vec.as_mut_ptr(), len: vec.len(), capacity: vec.capacity() };
+        mem::forget(vec);
+        buffer
+    }
+}
+
+/// Functions exported by a dynamic library reducer
+///
+/// `reduce` takes a reducer instance, current state (JSON object), a record
+/// and a buffer to write its result to. On success, it returns `0` and
+/// writes the new state (JSON object), otherwise it returns a non-zero value
+/// and writes an error message.
+#[repr(C)]
+pub struct VTable {
+    pub abi_version: u32,
+    pub new: unsafe extern "C" fn() -> *mut c_void,
+    pub reduce: unsafe extern "C" fn(instance: *mut c_void, state: Slice, record: *const RecordRef, result: *mut Buffer) -> i32,
+    pub free_buffer: unsafe extern "C" fn(buffer: Buffer),
+    pub drop: unsafe extern "C" fn(instance: *mut c_void),
+}
+
+/// Record as seen by dynamic library reducers
+#[derive(Debug, Clone, PartialEq)]
+pub struct NativeRecord {
+    hash: Vec<u8>,
+    encoded_hash: String,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl NativeRecord {
+    /// Copies a borrowed record
+    pub unsafe fn from_raw(record: *const RecordRef) -> Self {
+        let record = &*record;
+        let files = if record.files.is_null() {
+            &[]
+        } else {
+            slice::from_raw_parts(record.files, record.files_len)
+        };
+        NativeRecord {
+            hash: record.hash.as_bytes().to_vec(),
+            encoded_hash: String::from_utf8_lossy(record.encoded_hash.as_bytes()).into(),
+            files: files.iter()
+                .map(|f| (String::from_utf8_lossy(f.name.as_bytes()).into(), f.content.as_bytes().to_vec()))
+                .collect(),
+        }
+    }
+}
+
+impl Record for NativeRecord {
+    type Read = Cursor<Vec<u8>>;
+    type Hash = Vec<u8>;
+    type Str = String;
+    type Iter = std::vec::IntoIter<(String, Cursor<Vec<u8>>)>;
+
+    fn hash(&self) -> Self::Hash {
+        self.hash.clone()
+    }
+
+    fn encoded_hash(&self) -> Self::Str {
+        self.encoded_hash.clone()
+    }
+
+    /// Items are not passed to dynamic libraries, always returns an empty string
+    #[cfg(feature = "deprecated-item-api")]
+    fn item_id(&self) -> Self::Str {
+        String::new()
+    }
+
+    fn file_iter(&self) -> Self::Iter {
+        self.files.iter()
+            .map(|(name, content)| (name.clone(), Cursor::new(content.clone())))
+            .collect::<Vec<_>>().into_iter()
+    }
+}
+
+/// Exports a reducer from a dynamic library
+///
+/// Takes an expression evaluating to a `Reducer<State = Map<String, JsonValue>, Item = NativeRecord> + Send`,
+/// it is evaluated every time the host instantiates the reducer.
+#[macro_export]
+macro_rules! export_reducer {
+    ($reducer: expr) => {
+        #[no_mangle]
+        pub extern "C" fn sit_reducer_v1() -> *const $crate::reducers::native::VTable {
+            unsafe extern "C" fn new() -> *mut ::std::os::raw::c_void {
+                $crate::reducers::native::plugin::new_instance($reducer)
+            }
+            static VTABLE: $crate::reducers::native::VTable = $crate::reducers::native::VTable {
+                abi_version: $crate::reducers::native::ABI_VERSION,
+                new,
+                reduce: $crate::reducers::native::plugin::reduce,
+                free_buffer: $crate::reducers::native::plugin::free_buffer,
+                drop: $crate::reducers::native::plugin::drop,
+            };
+            &VTABLE
+        }
+    };
+}
+
+/// Plugin side of the ABI, used by [`export_reducer!`]
+///
+/// [`export_reducer!`]: ../../../macro.export_reducer.html
+#[doc(hidden)]
+pub mod plugin {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+
+    pub fn new_instance<T>(reducer: T) -> *mut c_void
+        where T: Reducer<State = Map<String, JsonValue>, Item = NativeRecord> + Send + 'static {
+        let reducer: BoxedReducer<NativeRecord> = Box::new(reducer);
+        Box::into_raw(Box::new(reducer)) as *mut c_void
+    }
+
+    pub unsafe extern "C" fn reduce(instance: *mut c_void, state: Slice, record: *const RecordRef, result: *mut Buffer) -> i32 {
+        let reducer = &mut *(instance as *mut BoxedReducer<NativeRecord>);
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            let state: Map<String, JsonValue> = serde_json::from_slice(state.as_bytes())
+                .map_err(|e| format!("invalid state: {}", e))?;
+            let record = NativeRecord::from_raw(record);
+            serde_json::to_vec(&reducer.reduce(state, &record))
+                .map_err(|e| format!("can't serialize state: {}", e))
+        })).unwrap_or_else(|_| Err(String::from("reducer panicked")));
+        match outcome {
+            Ok(json) => {
+                *result = Buffer::from_vec(json);
+                0
+            },
+            Err(error) => {
+                *result = Buffer::from_vec(error.into_bytes());
+                1
+            },
+        }
+    }
+
+    pub unsafe extern "C" fn free_buffer(buffer: Buffer) {
+        if !buffer.ptr.is_null() {
+            mem::drop(Vec::from_raw_parts(buffer.ptr, buffer.len, buffer.capacity));
+        }
+    }
+
+    pub unsafe extern "C" fn drop(instance: *mut c_void) {
+        mem::drop(Box::from_raw(instance as *mut BoxedReducer<NativeRecord>));
+    }
+}
+
+/// Calls a reducer through the ABI
+///
+/// Failures are recorded in the `errors` array of the state,
+/// the same way [`DuktapeReducer`] does it.
+///
+/// [`DuktapeReducer`]: ../duktape/struct.DuktapeReducer.html
+unsafe fn reduce_with_vtable<R: Record>(vtable: &VTable, instance: *mut c_void, name: &str,
+                                        mut state: Map<String, JsonValue>, item: &R) -> Map<String, JsonValue> {
+    let files: Vec<(String, Vec<u8>)> = item.file_iter()
+        .filter_map(|(name, mut reader)| {
+            let mut content = vec![];
+            reader.read_to_end(&mut content).ok().map(|_| (String::from(name.as_ref()), content))
+        })
+        .collect();
+    let abi_files: Vec<File> = files.iter()
+        .map(|(name, content)| File { name: Slice::new(name.as_bytes()), content: Slice::new(content) })
+        .collect();
+    let hash = item.hash();
+    let encoded_hash = item.encoded_hash();
+    let record = RecordRef {
+        hash: Slice::new(hash.as_ref()),
+        encoded_hash: Slice::new(encoded_hash.as_ref().as_bytes()),
+        files: abi_files.as_ptr(),
+        files_len: abi_files.len(),
+    };
+    let json = serde_json::to_vec(&state).unwrap();
+    let mut buffer = Buffer::empty();
+    let res = (vtable.reduce)(instance, Slice::new(&json), &record, &mut buffer);
+    let output = Slice { ptr: buffer.ptr, len: buffer.len }.as_bytes().to_vec();
+    (vtable.free_buffer)(buffer);
+    let error = if res != 0 {
+        String::from_utf8_lossy(&output).into()
+    } else {
+        match serde_json::from_slice(&output) {
+            Ok(JsonValue::Object(new_state)) => return new_state,
+            Ok(value) => format!("TypeError: invalid return value {}, expected an object", value),
+            Err(err) => format!("invalid state: {}", err),
+        }
+    };
+    {
+        let arr = state.entry(String::from("errors")).or_insert(JsonValue::Array(vec![]));
+        let mut err = Map::new();
+        err.insert("file".into(), JsonValue::String(name.into()));
+        err.insert("record".into(), JsonValue::String(encoded_hash.as_ref().into()));
+        err.insert("error".into(), JsonValue::String(error));
+        if let Some(arr) = arr.as_array_mut() {
+            arr.push(JsonValue::Object(err));
+        }
+    }
+    state
+}
+
+/// A set of native reducers, applied sequentially
+pub struct NativeReducer<R> {
+    reducers: Vec<BoxedReducer<R>>,
+}
+
+impl<R> Default for NativeReducer<R> {
+    fn default() -> Self {
+        NativeReducer { reducers: vec![] }
+    }
+}
+
+impl<R> NativeReducer<R> {
+    /// Returns an empty set of reducers
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers an in-process reducer
+    ///
+    /// Note that in-process reducers are not accounted for by
+    /// [`source_files_hash`], so if reductions are cached, the scope
+    /// should identify them.
+    ///
+    /// [`source_files_hash`]: ../duktape/fn.source_files_hash.html
+    pub fn register<T>(&mut self, reducer: T) -> &mut Self
+        where T: Reducer<State = Map<String, JsonValue>, Item = R> + Send + 'static {
+        self.reducers.push(Box::new(reducer));
+        self
+    }
+
+    /// Returns the number of reducers
+    pub fn len(&self) -> usize {
+        self.reducers.len()
+    }
+
+    /// Returns true if there are no reducers
+    pub fn is_empty(&self) -> bool {
+        self.reducers.is_empty()
+    }
+}
+
+impl<R> Reducer for NativeReducer<R> {
+    type State = Map<String, JsonValue>;
+    type Item = R;
+
+    fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State {
+        self.reducers.iter_mut().fold(state, |state, reducer| reducer.reduce(state, item))
+    }
+
+    fn cacheable(&self) -> bool {
+        self.reducers.iter().all(|reducer| reducer.cacheable())
+    }
+
+    fn reset_state(&mut self) {
+        for reducer in self.reducers.iter_mut() {
+            reducer.reset_state();
+        }
+    }
+}
+
+#[cfg(feature = "native-reducers")]
+pub use self::dynamic::{DynamicReducer, Error};
+
+#[cfg(feature = "native-reducers")]
+mod dynamic {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::marker::PhantomData;
+    use std::path::{Path, PathBuf};
+
+    use derive_error::Error;
+    use libloading::{Library, Symbol};
+
+    use crate::reducers::duktape::{self, SourceFiles};
+
+    #[derive(Debug, Error)]
+    pub enum Error {
+        IoError(std::io::Error),
+        SourceFilesError(duktape::Error),
+        #[error(no_from, non_std)]
+        LoadError {
+            file: PathBuf,
+            error: String,
+        },
+        #[error(no_from, non_std)]
+        AbiVersionMismatch {
+            file: PathBuf,
+            version: u32,
+        },
+    }
+
+    /// Reducer loaded from a dynamic library
+    pub struct DynamicReducer<R: Record> {
+        file: PathBuf,
+        name: String,
+        vtable: *const VTable,
+        instance: *mut c_void,
+        // has to outlive the instance
+        _library: Library,
+        phantom_data: PhantomData<R>,
+    }
+
+    unsafe impl<R: Record> Send for DynamicReducer<R> {}
+
+    impl<R: Record> Drop for DynamicReducer<R> {
+        fn drop(&mut self) {
+            unsafe {
+                ((*self.vtable).drop)(self.instance);
+            }
+        }
+    }
+
+    impl<R: Record> DynamicReducer<R> {
+        /// Loads and instantiates a reducer from a dynamic library
+        pub fn load<P: AsRef<Path>>(file: P) -> Result<Self, Error> {
+            let file = file.as_ref().to_path_buf();
+            let load_error = |error: std::io::Error| Error::LoadError { file: file.clone(), error: error.to_string() };
+            let library = Library::new(&file).map_err(&load_error)?;
+            let vtable = unsafe {
+                let entry_point: Symbol<unsafe extern "C" fn() -> *const VTable> = library.get(ENTRY_POINT).map_err(&load_error)?;
+                entry_point()
+            };
+            if vtable.is_null() {
+                return Err(Error::LoadError { file, error: "no reducer exported".into() });
+            }
+            let version = unsafe { (*vtable).abi_version };
+            if version != ABI_VERSION {
+                return Err(Error::AbiVersionMismatch { file, version });
+            }
+            let instance = unsafe { ((*vtable).new)() };
+            Ok(DynamicReducer {
+                name: file.to_string_lossy().into(),
+                file,
+                vtable,
+                instance,
+                _library: library,
+                phantom_data: PhantomData,
+            })
+        }
+
+        /// Returns the path to the library
+        pub fn file(&self) -> &Path {
+            &self.file
+        }
+    }
+
+    impl<R: Record> Reducer for DynamicReducer<R> {
+        type State = Map<String, JsonValue>;
+        type Item = R;
+
+        fn reduce(&mut self, state: Self::State, item: &Self::Item) -> Self::State {
+            unsafe { reduce_with_vtable(&*self.vtable, self.instance, &self.name, state, item) }
+        }
+    }
+
+    impl<R: Record + 'static> NativeReducer<R> {
+        /// Loads dynamic library reducers
+        ///
+        /// Directories are scanned for libraries with the platform's extension
+        /// (`.so`, `.dylib` or `.dll`), files with that extension are loaded
+        /// directly. Everything else (such as JavaScript reducers) is ignored.
+        pub fn load<SF: SourceFiles>(source_files: SF) -> Result<Self, Error> {
+            let mut reducer = NativeReducer::new();
+            let ext = Some(OsStr::new(std::env::consts::DLL_EXTENSION));
+            for file in source_files.source_files()? {
+                let mut libraries = if file.is_dir() {
+                    fs::read_dir(&file)?.filter_map(Result::ok).map(|e| e.path())
+                        .filter(|p| p.is_file() && p.extension() == ext)
+                        .collect()
+                } else if file.is_file() && file.extension() == ext {
+                    vec![file]
+                } else {
+                    vec![]
+                };
+                libraries.sort();
+                for library in libraries {
+                    reducer.register(DynamicReducer::<R>::load(library)?);
+                }
+            }
+            Ok(reducer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Repository;
+    use crate::repository::Config;
+    use crate::record::{RecordOwningContainer, RecordContainerReduction};
+    use std::marker::PhantomData;
+
+    struct Counter<R>(PhantomData<R>);
+
+    fn counter<R>() -> Counter<R> {
+        Counter(PhantomData)
+    }
+
+    impl<R: Record> Reducer for Counter<R> {
+        type State = Map<String, JsonValue>;
+        type Item = R;
+
+        fn reduce(&mut self, mut state: Self::State, item: &Self::Item) -> Self::State {
+            let count = state.get("count").and_then(JsonValue::as_u64).unwrap_or(0);
+            state.insert("count".into(), (count + 1).into());
+            let files = item.file_iter().count() as u64;
+            let total = state.get("files").and_then(JsonValue::as_u64).unwrap_or(0);
+            state.insert("files".into(), (total + files).into());
+            state
+        }
+    }
+
+    struct Failing;
+
+    impl Reducer for Failing {
+        type State = Map<String, JsonValue>;
+        type Item = NativeRecord;
+
+        fn reduce(&mut self, _state: Self::State, _item: &Self::Item) -> Self::State {
+            panic!("failure")
+        }
+    }
+
+    #[test]
+    fn in_process() {
+        let repo = Repository::in_memory(Config::default());
+        repo.new_record(vec![("a", &b"1"[..]), ("b", &b"2"[..])].into_iter(), false).unwrap();
+        repo.new_record(vec![("c", &b"3"[..])].into_iter(), true).unwrap();
+        let mut reducer = NativeReducer::new();
+        reducer.register(counter());
+        assert_eq!(reducer.len(), 1);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert_eq!(state.get("count").unwrap(), &JsonValue::from(2));
+        // second record also links to the first one
+        assert_eq!(state.get("files").unwrap(), &JsonValue::from(4));
+    }
+
+    #[test]
+    fn abi_roundtrip() {
+        let repo = Repository::in_memory(Config::default());
+        let record = repo.new_record(vec![("a", &b"1"[..])].into_iter(), false).unwrap();
+        let vtable = VTable {
+            abi_version: ABI_VERSION,
+            new: {
+                unsafe extern "C" fn new() -> *mut c_void { plugin::new_instance(counter::<NativeRecord>()) }
+                new
+            },
+            reduce: plugin::reduce,
+            free_buffer: plugin::free_buffer,
+            drop: plugin::drop,
+        };
+        unsafe {
+            let instance = (vtable.new)();
+            let state = reduce_with_vtable(&vtable, instance, "counter", Map::new(), &record);
+            let state = reduce_with_vtable(&vtable, instance, "counter", state, &record);
+            (vtable.drop)(instance);
+            assert_eq!(state.get("count").unwrap(), &JsonValue::from(2));
+            assert_eq!(state.get("files").unwrap(), &JsonValue::from(2));
+        }
+    }
+
+    #[test]
+    fn abi_error() {
+        let repo = Repository::in_memory(Config::default());
+        let record = repo.new_record(vec![("a", &b"1"[..])].into_iter(), false).unwrap();
+        let vtable = VTable {
+            abi_version: ABI_VERSION,
+            new: {
+                unsafe extern "C" fn new() -> *mut c_void { plugin::new_instance(Failing) }
+                new
+            },
+            reduce: plugin::reduce,
+            free_buffer: plugin::free_buffer,
+            drop: plugin::drop,
+        };
+        unsafe {
+            let instance = (vtable.new)();
+            let state = reduce_with_vtable(&vtable, instance, "failing", Map::new(), &record);
+            (vtable.drop)(instance);
+            assert_eq!(state["errors"][0]["file"], "failing");
+            assert_eq!(state["errors"][0]["record"], record.encoded_hash());
+            assert_eq!(state["errors"][0]["error"], "reducer panicked");
+        }
+    }
+
+    #[test]
+    fn native_record() {
+        let repo = Repository::in_memory(Config::default());
+        let record = repo.new_record(vec![("a", &b"1"[..])].into_iter(), false).unwrap();
+        let name = "a";
+        let files = vec![File { name: Slice::new(name.as_bytes()), content: Slice::new(b"1") }];
+        let hash = record.hash();
+        let encoded_hash = record.encoded_hash();
+        let raw = RecordRef {
+            hash: Slice::new(hash.as_ref()),
+            encoded_hash: Slice::new(encoded_hash.as_ref().as_bytes()),
+            files: files.as_ptr(),
+            files_len: files.len(),
+        };
+        let native = unsafe { NativeRecord::from_raw(&raw) };
+        assert_eq!(native.encoded_hash(), record.encoded_hash());
+        assert!(native.integrity_intact(repo.config().hashing_algorithm()));
+    }
+
+    #[cfg(feature = "duktape-reducers")]
+    #[test]
+    fn chained_with_duktape() {
+        use crate::reducers::duktape::DuktapeReducer;
+        use crate::path::HasPath;
+        let mut tmp = tempdir::TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        std::fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        std::fs::write(repo.path().join("reducers/reducer.js"), "module.exports = function(state) { return Object.assign({js: true}, state); }").unwrap();
+        repo.new_record(vec![("a", &b"1"[..])].into_iter(), false).unwrap();
+        let mut native = NativeReducer::new();
+        native.register(counter());
+        let mut reducer = DuktapeReducer::new(&repo).unwrap().chain(native);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert_eq!(state.get("js").unwrap(), &JsonValue::Bool(true));
+        assert_eq!(state.get("count").unwrap(), &JsonValue::from(1));
+    }
+}
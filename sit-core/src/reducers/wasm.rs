@@ -0,0 +1,384 @@
+//! WebAssembly reducers
+//!
+//! Reducers can be compiled to WebAssembly (from Rust, AssemblyScript, etc.)
+//! and put next to JavaScript ones as `reducers/*.wasm`. Such a module
+//! must export:
+//!
+//! * `memory`
+//! * `alloc(len: i32) -> i32`, allocating `len` bytes for the input
+//! * `reduce(state: i32, state_len: i32, record: i32, record_len: i32) -> i64`
+//!
+//! `state` is the current state and `record` is the record being reduced,
+//! both passed as JSON. Record has the same shape as in JavaScript reducers,
+//! `{"hash": "...", "files": {"name": [bytes]}}`, with file contents as
+//! arrays of bytes. `reduce` returns a pointer to the new state (JSON object) in
+//! its upper 32 bits and its length in the lower 32 bits. Zero length means
+//! that the state is left unchanged.
+//!
+//! Optionally, `dealloc(ptr: i32, len: i32)` can be exported to free inputs
+//! and results once the host is done with them.
+//!
+//! Modules are instantiated once, so they can keep their own state
+//! across invocations. They can't import anything, which keeps them
+//! sandboxed.
+//!
+//! Failures (traps and invalid results) are reported in the state's `errors`
+//! array, the same way JavaScript reducers' are, and handled according to
+//! the reducer's [`ErrorPolicy`].
+//!
+//! [`ErrorPolicy`]: ../duktape/enum.ErrorPolicy.html
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+
+use derive_error::Error;
+use serde_json::{Map, Value as JsonValue};
+use std::marker::PhantomData;
+use wasmi::{ImportsBuilder, MemoryRef, Module, ModuleInstance, ModuleRef, NopExternals, RuntimeValue};
+
+use super::Reducer;
+use super::duktape::{self, push_errors, ErrorPolicy, SourceFiles};
+use crate::Record;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    IoError(std::io::Error),
+    SourceFilesError(duktape::Error),
+    #[error(no_from, non_std)]
+    LoadError {
+        file: PathBuf,
+        error: String,
+    },
+}
+
+struct Instance {
+    file: PathBuf,
+    module: ModuleRef,
+    memory: MemoryRef,
+    dealloc: bool,
+}
+
+impl Instance {
+    fn load(file: PathBuf) -> Result<Self, Error> {
+        let load_error = |error: String| Error::LoadError { file: file.clone(), error };
+        let module = Module::from_buffer(fs::read(&file)?).map_err(|e| load_error(e.to_string()))?;
+        let module = ModuleInstance::new(&module, &ImportsBuilder::default())
+            .map_err(|e| load_error(e.to_string()))?
+            .run_start(&mut NopExternals)
+            .map_err(|e| load_error(e.to_string()))?;
+        let memory = module.export_by_name("memory")
+            .and_then(|e| e.as_memory().cloned())
+            .ok_or_else(|| load_error("memory is not exported".into()))?;
+        for name in &["alloc", "reduce"] {
+            if module.export_by_name(name).and_then(|e| e.as_func().cloned()).is_none() {
+                return Err(load_error(format!("{} is not exported", name)));
+            }
+        }
+        let dealloc = module.export_by_name("dealloc").and_then(|e| e.as_func().cloned()).is_some();
+        Ok(Instance { file, module, memory, dealloc })
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<(i32, i32), String> {
+        let len = bytes.len() as i32;
+        let ptr = match self.module.invoke_export("alloc", &[RuntimeValue::I32(len)], &mut NopExternals)
+            .map_err(|e| e.to_string())? {
+            Some(RuntimeValue::I32(ptr)) => ptr,
+            _ => return Err("TypeError: alloc should return i32".into()),
+        };
+        self.memory.set(ptr as u32, bytes).map_err(|e| e.to_string())?;
+        Ok((ptr, len))
+    }
+
+    fn free(&self, (ptr, len): (i32, i32)) -> Result<(), String> {
+        if self.dealloc && len > 0 {
+            self.module.invoke_export("dealloc", &[RuntimeValue::I32(ptr), RuntimeValue::I32(len)], &mut NopExternals)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn reduce(&self, state: &[u8], record: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let state = self.write(state)?;
+        let record = self.write(record)?;
+        let res = self.module.invoke_export("reduce", &[RuntimeValue::I32(state.0), RuntimeValue::I32(state.1),
+                                                        RuntimeValue::I32(record.0), RuntimeValue::I32(record.1)],
+                                            &mut NopExternals).map_err(|e| e.to_string())?;
+        self.free(state)?;
+        self.free(record)?;
+        let result = match res {
+            Some(RuntimeValue::I64(result)) => result as u64,
+            _ => return Err("TypeError: reduce should return i64".into()),
+        };
+        let (ptr, len) = ((result >> 32) as u32, (result & 0xffff_ffff) as u32);
+        if len == 0 {
+            return Ok(None);
+        }
+        let new_state = self.memory.get(ptr, len as usize).map_err(|e| e.to_string())?;
+        self.free((ptr as i32, len as i32))?;
+        Ok(Some(new_state))
+    }
+}
+
+/// Reducer that runs WebAssembly modules
+pub struct WasmReducer<R: Record> {
+    instances: Vec<Instance>,
+    error_policy: ErrorPolicy,
+    stopped: bool,
+    phantom_data: PhantomData<R>,
+}
+
+// Instances are reference counted, but all references are owned by the reducer,
+// so it can be moved across threads as a whole
+unsafe impl<R: Record> Send for WasmReducer<R> {}
+
+impl<R: Record> WasmReducer<R> {
+    /// Loads WebAssembly reducers
+    ///
+    /// Directories are scanned for `.wasm` files, `.wasm` files are loaded directly.
+    /// Everything else (such as JavaScript reducers) is ignored.
+    pub fn new<SF: SourceFiles>(source_files: SF) -> Result<Self, Error> {
+        let ext = Some(OsStr::new("wasm"));
+        let mut instances = vec![];
+        for file in source_files.source_files()? {
+            let mut modules = if file.is_dir() {
+                fs::read_dir(&file)?.filter_map(Result::ok).map(|e| e.path())
+                    .filter(|p| p.is_file() && p.extension() == ext)
+                    .collect()
+            } else if file.is_file() && file.extension() == ext {
+                vec![file]
+            } else {
+                vec![]
+            };
+            modules.sort();
+            for module in modules {
+                instances.push(Instance::load(module)?);
+            }
+        }
+        Ok(WasmReducer { instances, error_policy: ErrorPolicy::default(), stopped: false, phantom_data: PhantomData })
+    }
+
+    /// Returns the number of loaded modules
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    /// Returns true if no modules were loaded
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Returns the error policy
+    pub fn error_policy(&self) -> ErrorPolicy {
+        self.error_policy
+    }
+
+    /// Changes the error policy
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+}
+
+/// Record in the shape passed to WebAssembly reducers
+fn record_json<R: Record>(item: &R) -> Vec<u8> {
+    use std::io::Read;
+    let mut files = Map::new();
+    for (name, mut reader) in item.file_iter() {
+        let mut buf = vec![];
+        if reader.read_to_end(&mut buf).is_ok() {
+            files.insert(name.as_ref().into(), JsonValue::Array(buf.into_iter().map(JsonValue::from).collect()));
+        }
+    }
+    let mut record = Map::new();
+    record.insert("hash".into(), JsonValue::String(item.encoded_hash().as_ref().into()));
+    record.insert("files".into(), JsonValue::Object(files));
+    serde_json::to_vec(&record).unwrap()
+}
+
+impl<R: Record> Reducer for WasmReducer<R> {
+    type State = Map<String, JsonValue>;
+    type Item = R;
+
+    fn reduce(&mut self, mut state: Self::State, item: &Self::Item) -> Self::State {
+        if self.instances.is_empty() || self.stopped {
+            return state;
+        }
+        let record = record_json(item);
+        let mut previous = match self.error_policy {
+            ErrorPolicy::SkipReducer => None,
+            ErrorPolicy::SkipRecord | ErrorPolicy::Stop => Some(state.clone()),
+        };
+        let mut errors = vec![];
+        for instance in self.instances.iter() {
+            let json = serde_json::to_vec(&state).unwrap();
+            let error = match instance.reduce(&json, &record) {
+                Ok(None) => continue,
+                Ok(Some(new_state)) => match serde_json::from_slice(&new_state) {
+                    Ok(JsonValue::Object(new_state)) => {
+                        state = new_state;
+                        continue;
+                    },
+                    Ok(value) => format!("TypeError: invalid return value {}, expected an object", value),
+                    Err(err) => format!("invalid state: {}", err),
+                },
+                Err(err) => err,
+            };
+            let mut err = Map::new();
+            err.insert("file".into(), JsonValue::String(instance.file.to_string_lossy().into()));
+            err.insert("record".into(), JsonValue::String(item.encoded_hash().as_ref().into()));
+            err.insert("error".into(), JsonValue::String(error));
+            errors.push(JsonValue::Object(err));
+            if let Some(previous) = previous.take() {
+                // discard changes made by the failed record
+                state = previous;
+                self.stopped = self.error_policy == ErrorPolicy::Stop;
+                break;
+            }
+        }
+        push_errors(&mut state, errors);
+        state
+    }
+
+    /// States are not cacheable once reduction was stopped, as records added
+    /// later must be ignored, too
+    fn cacheable(&self) -> bool {
+        !self.stopped
+    }
+
+    fn reset_state(&mut self) {
+        self.stopped = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use super::*;
+    use crate::Repository;
+    use crate::record::{RecordOwningContainer, RecordContainerReduction};
+    use crate::path::HasPath;
+
+    fn section(id: u8, contents: &[u8]) -> Vec<u8> {
+        let mut section = vec![id, contents.len() as u8];
+        section.extend_from_slice(contents);
+        section
+    }
+
+    /// Assembles a module with a bump allocator and a given `reduce` body
+    fn module(reduce: &[u8]) -> Vec<u8> {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        // types: (i32) -> i32, (i32, i32, i32, i32) -> i64
+        module.extend(section(1, &[2, 0x60, 1, 0x7f, 1, 0x7f, 0x60, 4, 0x7f, 0x7f, 0x7f, 0x7f, 1, 0x7e]));
+        // functions: alloc, reduce
+        module.extend(section(3, &[2, 0, 1]));
+        // memory: one page
+        module.extend(section(5, &[1, 0, 1]));
+        // heap pointer: mut i32 = 1024
+        module.extend(section(6, &[1, 0x7f, 1, 0x41, 0x80, 0x08, 0x0b]));
+        let mut exports = vec![3];
+        for (name, kind, index) in &[("memory", 2, 0), ("alloc", 0, 0), ("reduce", 0, 1)] {
+            exports.push(name.len() as u8);
+            exports.extend_from_slice(name.as_bytes());
+            exports.push(*kind);
+            exports.push(*index);
+        }
+        module.extend(section(7, &exports));
+        // alloc: heap += len, returns previous heap pointer
+        let alloc = [0, 0x23, 0, 0x23, 0, 0x20, 0, 0x6a, 0x24, 0, 0x0b];
+        let mut code = vec![2, alloc.len() as u8];
+        code.extend_from_slice(&alloc);
+        code.push(reduce.len() as u8 + 2);
+        code.push(0);
+        code.extend_from_slice(reduce);
+        code.push(0x0b);
+        module.extend(section(10, &code));
+        module
+    }
+
+    fn repository(reduce: &[u8]) -> Repository<crate::repository::ModuleDirectory<PathBuf>> {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.wasm"), module(reduce)).unwrap();
+        repo
+    }
+
+    #[test]
+    fn record_shape() {
+        // returns the record as the new state
+        let repo = repository(&[0x20, 2, 0xad, 0x42, 32, 0x86, 0x20, 3, 0xad, 0x84]);
+        let record = repo.new_record(vec![("text", &b"hi"[..])].into_iter(), false).unwrap();
+        let mut reducer = WasmReducer::new(&repo).unwrap();
+        assert_eq!(reducer.len(), 1);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert_eq!(state.get("hash").unwrap(), &JsonValue::String(record.encoded_hash()));
+        assert_eq!(state["files"]["text"], JsonValue::Array(vec![104.into(), 105.into()]));
+    }
+
+    #[test]
+    fn unchanged_state() {
+        let repo = repository(&[0x42, 0]);
+        repo.new_record(vec![("text", &b"hi"[..])].into_iter(), false).unwrap();
+        let mut state = Map::new();
+        state.insert("hello".into(), JsonValue::Bool(true));
+        let state = repo.reduce_with_reducer_and_state(&mut WasmReducer::new(&repo).unwrap(), state).unwrap();
+        assert_eq!(state.get("hello").unwrap(), &JsonValue::Bool(true));
+    }
+
+    #[test]
+    fn trap() {
+        let repo = repository(&[0x00]);
+        let record = repo.new_record(vec![("text", &b"hi"[..])].into_iter(), false).unwrap();
+        let state = repo.reduce_with_reducer(&mut WasmReducer::new(&repo).unwrap()).unwrap();
+        assert_eq!(state["errors"][0]["file"], repo.path().join("reducers/reducer.wasm").to_str().unwrap());
+        assert_eq!(state["errors"][0]["record"], record.encoded_hash());
+    }
+
+    #[test]
+    fn error_policy() {
+        // the first module returns the record as the new state, the second one traps
+        let repo = repository(&[0x20, 2, 0xad, 0x42, 32, 0x86, 0x20, 3, 0xad, 0x84]);
+        fs::write(repo.path().join("reducers/trap.wasm"), module(&[0x00])).unwrap();
+        repo.new_record(vec![("text", &b"1"[..])].into_iter(), true).unwrap();
+        repo.new_record(vec![("text", &b"2"[..])].into_iter(), true).unwrap();
+
+        let mut reducer = WasmReducer::new(&repo).unwrap();
+        assert_eq!(reducer.error_policy(), ErrorPolicy::SkipRecord);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert!(state.get("hash").is_none());
+        assert_eq!(state["errors"].as_array().unwrap().len(), 2);
+
+        reducer.set_error_policy(ErrorPolicy::SkipReducer);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert!(state.get("hash").is_some());
+
+        reducer.set_error_policy(ErrorPolicy::Stop);
+        let state = repo.reduce_with_reducer(&mut reducer).unwrap();
+        assert!(state.get("hash").is_none());
+        assert_eq!(state["errors"].as_array().unwrap().len(), 1);
+        assert!(!reducer.cacheable());
+        reducer.reset_state();
+        assert!(reducer.cacheable());
+    }
+
+    #[test]
+    fn invalid_module() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.wasm"), b"not wasm").unwrap();
+        assert!(WasmReducer::<crate::repository::Record>::new(&repo).is_err());
+    }
+
+    #[test]
+    fn ignores_other_files() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(tmp).unwrap();
+        fs::create_dir_all(repo.path().join("reducers")).unwrap();
+        fs::write(repo.path().join("reducers/reducer.js"), b"module.exports = function() {}").unwrap();
+        assert!(WasmReducer::<crate::repository::Record>::new(&repo).unwrap().is_empty());
+    }
+}
@@ -8,7 +8,7 @@
 
 use std::path::{Path, PathBuf};
 use std::fs;
-use std::io::Write;
+use std::io::{self, Read, Write};
 
 use tempdir::TempDir;
 
@@ -18,14 +18,21 @@ use serde_json;
 
 use super::hash::HashingAlgorithm;
 use super::encoding::Encoding;
+use super::store::{RecordStore, MemoryRecordStore};
+use super::pack::{PackCache, PackedRecord, PackWriter};
+use super::bundle::Bundle;
+use super::reducers::cache::ReductionCache;
+use super::graph::Generations;
 #[cfg(feature = "deprecated-item-api")]
 use super::id::IdGenerator;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
 use serde_derive::{Deserialize, Serialize};
 use typed_builder::TypedBuilder;
 use derive_error::Error;
+use fs2::FileExt;
 
 /// Current repository format version
 const VERSION: &str = "1";
@@ -48,11 +55,23 @@ const DEPRECATED_ITEMS_PATH: &str = "items";
 const RECORDS_PATH: &str = "records";
 /// Repository's modules path
 const MODULES_PATH: &str = "modules";
+/// Repository's packs path
+const PACKS_PATH: &str = "packs";
+/// Repository's cache path
+const CACHE_PATH: &str = "cache";
+/// Repository's writer lock file (under cache path)
+const LOCK_FILE: &str = "lock";
 
 
 /// Repository is the container for all SIT artifacts
+///
+/// Records are kept in a [`RecordStore`], which defaults to
+/// [`DirectoryRecordStore`] (split-directory layout under `records/`)
+///
+/// [`RecordStore`]: ../store/trait.RecordStore.html
+/// [`DirectoryRecordStore`]: struct.DirectoryRecordStore.html
 #[derive(Debug, Clone)]
-pub struct Repository<MI> {
+pub struct Repository<MI, S = DirectoryRecordStore> {
     /// Path to the container
     path: PathBuf,
     /// Path to the config file. Mainly to avoid creating
@@ -76,6 +95,8 @@ pub struct Repository<MI> {
     module_iterator: MI,
     /// Integrity check
     integrity_check: bool,
+    /// Record storage
+    store: S,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -152,6 +173,20 @@ pub struct Config {
     extra: HashMap<String, serde_json::Value>,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            hashing_algorithm: Default::default(),
+            encoding: Encoding::default(),
+            #[cfg(feature = "deprecated-item-api")]
+            id_generator: IdGenerator::default(),
+            version: String::from(VERSION),
+            extra: HashMap::new(),
+            features: default_features(),
+        }
+    }
+}
+
 impl Config {
     /// Returns hashing algorithm
     pub fn hashing_algorithm(&self) -> &HashingAlgorithm {
@@ -161,6 +196,21 @@ impl Config {
     pub fn encoding(&self) -> &Encoding {
         &self.encoding
     }
+    /// Sets hashing algorithm
+    ///
+    /// Records created with a different hashing algorithm will no longer
+    /// pass the integrity check, so this is only useful when creating
+    /// a new repository.
+    pub fn set_hashing_algorithm(&mut self, hashing_algorithm: HashingAlgorithm) {
+        self.hashing_algorithm = hashing_algorithm;
+    }
+    /// Sets encoding
+    ///
+    /// Records created with a different encoding will no longer be found,
+    /// so this is only useful when creating a new repository.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
     /// Returns extra configuration
     pub fn extra(&self) -> &HashMap<String, serde_json::Value> {
         &self.extra
@@ -229,6 +279,36 @@ impl From<glob::PatternError> for Error {
     }
 }
 
+/// Outcome of [`Repository::import_records_from`]
+///
+/// All records are listed by their encoded hashes.
+///
+/// [`Repository::import_records_from`]: struct.Repository.html#method.import_records_from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    /// Records that were copied
+    pub imported: Vec<String>,
+    /// Records that were already present
+    pub present: Vec<String>,
+    /// Records present in both repositories with different files
+    pub conflicting: Vec<String>,
+    /// Records whose files don't match their hashes
+    pub invalid: Vec<String>,
+}
+
+/// Outcome of [`Repository::gc`]
+///
+/// [`Repository::gc`]: struct.Repository.html#method.gc
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcReport {
+    /// Temporary directories abandoned by interrupted writers
+    pub temporary: Vec<PathBuf>,
+    /// Empty directories under `records/` (nested ones come first)
+    pub empty: Vec<PathBuf>,
+    /// Links to missing records, as pairs of encoded hashes of the record and its missing parent
+    pub orphaned_links: Vec<(String, String)>,
+}
+
 #[allow(unused_variables,dead_code)]
 mod default_files {
     include!(concat!(env!("OUT_DIR"), "/default_files.rs"));
@@ -263,15 +343,7 @@ impl Repository<ModuleDirectory<PathBuf>> {
     /// Attempts creating a new repository. Fails with `Error::AlreadyExists`
     /// if a repository already exists.
     pub fn new<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
-        Repository::new_with_config(path, Config {
-            hashing_algorithm: Default::default(),
-            encoding: Encoding::default(),
-            #[cfg(feature = "deprecated-item-api")]
-            id_generator: IdGenerator::default(),
-            version: String::from(VERSION),
-            extra: HashMap::new(),
-            features: default_features(),
-        })
+        Repository::new_with_config(path, Config::default())
     }
 
     /// Attempts creating a new repository with a specified config. Fails with `Error::AlreadyExists`
@@ -291,6 +363,7 @@ impl Repository<ModuleDirectory<PathBuf>> {
             fs::create_dir_all(&records_path)?;
             let modules_path = path.join(MODULES_PATH);
             let module_iterator = ModuleDirectory(modules_path.clone());
+            let store = DirectoryRecordStore::new(&config, records_path.clone(), path.clone());
             let repo = Repository {
                 path,
                 config_path,
@@ -301,6 +374,7 @@ impl Repository<ModuleDirectory<PathBuf>> {
                 modules_path,
                 module_iterator,
                 integrity_check: true,
+                store,
             };
             repo.save()?;
             Ok(repo)
@@ -387,6 +461,7 @@ impl Repository<ModuleDirectory<PathBuf>> {
             return Err(Error::InvalidVersion { expected: String::from(VERSION), got: config.version });
         }
         let module_iterator = ModuleDirectory(modules_path.clone());
+        let store = DirectoryRecordStore::new(&config, records_path.clone(), path.clone());
         let repository = Repository {
             path,
             config_path,
@@ -397,6 +472,7 @@ impl Repository<ModuleDirectory<PathBuf>> {
             modules_path,
             module_iterator,
             integrity_check: true,
+            store,
         };
         if upgraded {
             repository.save()?;
@@ -439,16 +515,38 @@ impl Repository<ModuleDirectory<PathBuf>> {
 
 }
 
-impl<'a, MI> HasPath for Repository<MI> {
+impl Repository<ModuleDirectory<PathBuf>, MemoryRecordStore> {
+    /// Creates a new repository that keeps its records in memory
+    ///
+    /// Such a repository has an empty path and never touches the file system,
+    /// which makes it useful for tests and tooling. It can't be saved.
+    pub fn in_memory(config: Config) -> Self {
+        let store = MemoryRecordStore::new(config.hashing_algorithm.clone(), config.encoding.clone());
+        Repository {
+            path: PathBuf::new(),
+            config_path: PathBuf::new(),
+            modules_path: PathBuf::new(),
+            #[cfg(feature = "deprecated-item-api")]
+            items_path: PathBuf::new(),
+            records_path: PathBuf::new(),
+            config,
+            module_iterator: ModuleDirectory(PathBuf::new()),
+            integrity_check: true,
+            store,
+        }
+    }
+}
+
+impl<'a, MI, S> HasPath for Repository<MI, S> {
     fn path(&self) -> &Path {
         self.path.as_path()
     }
 }
 
-impl<MI> Repository<MI> {
+impl<MI, S> Repository<MI, S> {
     /// Returns a new instance of this Repository with an additional module iterator
     /// chained to the existing one
-    pub fn with_module_iterator<MI1>(self, module_iterator: MI1) -> Repository<(MI, MI1)> {
+    pub fn with_module_iterator<MI1>(self, module_iterator: MI1) -> Repository<(MI, MI1), S> {
         Repository {
             path: self.path,
             config_path: self.config_path,
@@ -459,11 +557,12 @@ impl<MI> Repository<MI> {
             config: self.config,
             module_iterator: (self.module_iterator, module_iterator),
             integrity_check: self.integrity_check,
+            store: self.store,
         }
     }
 
     /// Returns a new instance of this Repository with a different module iterator
-    pub fn with_new_module_iterator<MI1>(self, module_iterator: MI1) -> Repository<MI1> {
+    pub fn with_new_module_iterator<MI1>(self, module_iterator: MI1) -> Repository<MI1, S> {
         Repository {
             path: self.path,
             config_path: self.config_path,
@@ -474,9 +573,31 @@ impl<MI> Repository<MI> {
             config: self.config,
             module_iterator,
             integrity_check: self.integrity_check,
+            store: self.store,
+        }
+    }
+
+    /// Returns a new instance of this Repository with a different record store
+    pub fn with_store<S1>(self, store: S1) -> Repository<MI, S1> {
+        Repository {
+            path: self.path,
+            config_path: self.config_path,
+            modules_path: self.modules_path,
+            #[cfg(feature = "deprecated-item-api")]
+            items_path: self.items_path,
+            records_path: self.records_path,
+            config: self.config,
+            module_iterator: self.module_iterator,
+            integrity_check: self.integrity_check,
+            store,
         }
     }
 
+    /// Returns a reference to the record store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
     /// Returns the status of integrity check
     pub fn integrity_check(&self) -> bool {
         self.integrity_check
@@ -500,6 +621,7 @@ impl<MI> Repository<MI> {
             config: self.config,
             module_iterator: self.module_iterator,
             integrity_check: value,
+            store: self.store,
         }
     }
 
@@ -546,6 +668,137 @@ impl<MI> Repository<MI> {
         &mut self.config
     }
 
+    /// Returns path to modules. The target directory may not exist.
+    pub fn modules_path(&self) -> &Path {
+        &self.modules_path
+    }
+
+    /// Finds a record by name (if there is one)
+    pub fn record<N: AsRef<str>>(&self, name: N) -> Option<S::Record> where S: RecordStore {
+        self.store.record(name)
+    }
+
+    /// Acquires an exclusive writer lock, waiting for other writers to release it
+    ///
+    /// Creating new records acquires it automatically, so that parents are always
+    /// linked correctly when there are concurrent writers.
+    pub fn lock(&self) -> Result<S::Lock, Error> where S: RecordStore {
+        self.store.lock()
+    }
+
+    /// Copies records this repository lacks from another repository
+    ///
+    /// Both repositories must use the same hashing algorithm and encoding.
+    /// See [`import_records`] for details.
+    ///
+    /// [`import_records`]: struct.Repository.html#method.import_records
+    pub fn import_records_from<MI1, S1: RecordStore>(&self, other: &Repository<MI1, S1>) -> Result<ImportReport, Error>
+        where S: RecordStore {
+        if self.config.hashing_algorithm != other.config.hashing_algorithm || self.config.encoding != other.config.encoding {
+            return Err(Error::OtherError("repositories use different hashing algorithms or encodings".into()));
+        }
+        self.import_records(other.store.record_iter()?.flat_map(|records| records))
+    }
+
+    /// Copies records this repository lacks
+    ///
+    /// Records are expected to come in the order of iteration (parents first),
+    /// so that an interrupted import never leaves a record without its parents.
+    /// Every record's hash is verified with this repository's hashing algorithm
+    /// and records that fail the check are never copied. See [`ImportReport`]
+    /// for details on the outcome.
+    ///
+    /// [`ImportReport`]: struct.ImportReport.html
+    pub fn import_records<R: RecordTrait, I: IntoIterator<Item = R>>(&self, records: I) -> Result<ImportReport, Error>
+        where S: RecordStore {
+        let _lock = self.store.lock()?;
+        let mut report = ImportReport::default();
+        for record in records {
+            let hash = String::from(record.encoded_hash().as_ref());
+            if !record.integrity_intact(&self.config.hashing_algorithm) {
+                report.invalid.push(hash);
+            } else if let Some(existing) = self.store.record(&hash) {
+                if record_files(&existing)? == record_files(&record)? {
+                    report.present.push(hash);
+                } else {
+                    report.conflicting.push(hash);
+                }
+            } else {
+                let files: OrderedFiles<_> = record.file_iter().into();
+                self.store.put(files)?;
+                report.imported.push(hash);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Copies records this repository lacks from a bundle
+    ///
+    /// The bundle must have been created with the same hashing algorithm and
+    /// encoding. See [`import_records`] for details.
+    ///
+    /// [`import_records`]: struct.Repository.html#method.import_records
+    pub fn apply_bundle(&self, bundle: &Bundle) -> Result<ImportReport, Error> where S: RecordStore {
+        if &self.config.hashing_algorithm != bundle.hashing_algorithm() || &self.config.encoding != bundle.encoding() {
+            return Err(Error::OtherError("bundle uses a different hashing algorithm or encoding".into()));
+        }
+        self.import_records(bundle.records())
+    }
+
+    /// Links all dangling records to `files` as their parents (if `link_parents` is `true`)
+    fn link_parents<'f, F: File + 'f, I: Into<OrderedFiles<'f, F>>>(&self, files: I, link_parents: bool) ->
+    Result<BoxedOrderedFiles<'f>, Error> where F::Read: 'f, S: RecordStore {
+        let files: OrderedFiles<F> = files.into();
+
+        if link_parents {
+            let records = self.heads()?;
+            let parents: OrderedFiles<_> = records.iter().map(|rec| (format!(".prev/{}", rec.encoded_hash().as_ref()), &b""[..])).into();
+            Ok(files + parents)
+        } else {
+            Ok(files.boxed())
+        }
+    }
+}
+
+/// Collects empty directories under `path` (including ones that only contain empty
+/// directories), nested ones first. Returns true if `path` has nothing else.
+///
+/// Records reside `depth` levels below `path` and are never collected
+/// (records without files are empty directories, too).
+fn empty_directories(path: &Path, depth: usize, encoding: &Encoding, found: &mut Vec<PathBuf>) -> Result<bool, Error> {
+    let mut entries: Vec<_> = fs::read_dir(path)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|e| e.path());
+    let mut empty = true;
+    for entry in entries {
+        let record = depth == 1 && entry.file_name().to_str()
+            .map(|name| encoding.decode(name.as_bytes()).is_ok())
+            .unwrap_or(false);
+        if record || !entry.file_type()?.is_dir() {
+            empty = false;
+        } else if empty_directories(&entry.path(), depth.saturating_sub(1), encoding, found)? {
+            found.push(entry.path());
+        } else {
+            empty = false;
+        }
+    }
+    Ok(empty)
+}
+
+/// Reads all files of a record, sorted by their names
+fn record_files<R: RecordTrait>(record: &R) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let mut files = record.file_iter()
+        .map(|(name, mut reader)| {
+            let mut contents = vec![];
+            reader.read_to_end(&mut contents)?;
+            Ok((String::from(name.as_ref()), contents))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    files.sort();
+    Ok(files)
+}
+
+impl<MI> Repository<MI, DirectoryRecordStore> {
+
     #[cfg(feature = "deprecated-item-api")]
     /// Returns an unordered (as in "order not defined") item iterator
     pub fn item_iter(&self) -> Result<ItemIter<MI>, Error> {
@@ -576,28 +829,6 @@ impl<MI> Repository<MI> {
         })
     }
 
-    /// Finds a record by name (if there is one)
-    pub fn record<S: AsRef<str>>(&self, name: S) -> Option<Record> {
-        let path = self.records_path().join(crate::record::split_path(name, 2));
-        let path = path.resolve_dir(self.path()).unwrap_or(path);
-        if path.is_dir() && path.strip_prefix(self.records_path()).is_ok() {
-            let hash = self.config.encoding.decode(path.file_name().unwrap().to_str().unwrap().as_bytes());
-            if hash.is_err() {
-                return None
-            }
-            let record = Record {
-                hash: hash.unwrap(),
-                encoding: self.config.encoding.clone(),
-                path,
-                #[cfg(feature = "deprecated-item-api")]
-                item: "".into(),
-            };
-            Some(record)
-        } else {
-            None
-        }
-    }
-
     /// Finds an item by name (if there is one)
     #[cfg(feature = "deprecated-item-api")]
     pub fn item<S: AsRef<str>>(&self, name: S) -> Option<Item<MI>> {
@@ -623,35 +854,178 @@ impl<MI> Repository<MI> {
         }
     }
 
-    /// Returns path to modules. The target directory may not exist.
-    pub fn modules_path(&self) -> &Path {
-        &self.modules_path
-    }
-
     pub fn new_record_in<'f, P: AsRef<Path>, F: File + 'f, I: Into<OrderedFiles<'f, F>>>(&self, path: P, files: I, link_parents: bool) ->
     Result<Record, Error> where F::Read: 'f {
-        let tempdir = TempDir::new_in(&self.path, "sit")?;
-        let mut hasher = self.config.hashing_algorithm.hasher();
+        let _lock = self.store.lock()?;
+        let files = self.link_parents(files, link_parents)?;
+        self.store.put_in(path, files)
+    }
 
-        let files: OrderedFiles<F> = files.into();
+    /// Moves all loose records into a new pack
+    ///
+    /// Records that fail integrity check (if it is enabled) are kept loose.
+    /// Returns a path to the new pack archive, or `None` if there was nothing
+    /// to pack.
+    pub fn pack(&self) -> Result<Option<PathBuf>, Error> {
+        let _lock = self.store.lock()?;
+        self.store.pack(self.integrity_check)
+    }
 
-        // Link parents if requested
-        let files = if link_parents {
-            let records = self.record_iter()?.last().unwrap_or(vec![]);
-            let parents: OrderedFiles<_> = records.iter().map(|rec| (format!(".prev/{}", rec.encoded_hash()), &b""[..])).into();
-            files + parents
-        } else {
-            files.boxed()
-        };
+    /// Finds (and removes, if `remove` is `true`) leftovers of interrupted writers
+    ///
+    /// Abandoned temporary directories and empty directories under `records/`
+    /// are removed. Orphaned links are only reported as removing them would
+    /// change records' hashes.
+    ///
+    /// The writer lock is held throughout, so temporary directories of writers
+    /// in progress are never touched.
+    pub fn gc(&self, remove: bool) -> Result<GcReport, Error> {
+        let _lock = self.store.lock()?;
+        let mut report = GcReport::default();
 
-        files.hash_and(&mut *hasher, |n| -> Result<fs::File, Error> {
-            let path = RelativePath::new(n).normalize();
-            if path.components().any(|c| match c {
-                RelativeComponent::Normal(_) => false,
-                _ => true,
-            }) {
-                return Err(Error::PathPrefixError);
+        for path in vec![self.path.clone(), self.store.packs_path()] {
+            if !path.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&path)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() && entry.file_name().to_str().map(|n| n.starts_with("sit.")).unwrap_or(false) {
+                    report.temporary.push(entry.path());
+                }
+            }
+        }
+        report.temporary.sort();
+
+        // every two characters of the encoded hash (including padding)
+        // make one level of directories
+        let depth = (self.config.encoding.encode_len(self.config.hashing_algorithm.len()) + 1) / 2;
+        empty_directories(&self.records_path, depth, &self.config.encoding, &mut report.empty)?;
+
+        let records: Vec<_> = self.store.record_iter()?.flat_map(|records| records).collect();
+        let names: HashSet<String> = records.iter().map(|r| r.encoded_hash()).collect();
+        for record in records.iter() {
+            for parent in record.parents() {
+                if !names.contains(&parent) {
+                    report.orphaned_links.push((record.encoded_hash(), parent));
+                }
+            }
+        }
+
+        if remove {
+            for path in report.temporary.iter() {
+                fs::remove_dir_all(path)?;
             }
+            for path in report.empty.iter() {
+                fs::remove_dir(path)?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Returns the reduction cache (kept under `cache/reductions`)
+    pub fn reduction_cache(&self) -> ReductionCache {
+        ReductionCache::new(self.path.join(CACHE_PATH).join("reductions"),
+                            self.config.hashing_algorithm.clone(), self.config.encoding.clone())
+    }
+}
+
+impl<MI, S: RecordStore> RecordOwningContainer for Repository<MI, S> {
+
+    fn new_record<'f, F: File + 'f, I: Into<OrderedFiles<'f, F>>>(&self, files: I, link_parents: bool) -> Result<S::Record, Error> where F::Read: 'f {
+        let _lock = self.store.lock()?;
+        let files = self.link_parents(files, link_parents)?;
+        self.store.put(files)
+    }
+}
+
+
+impl<MI, S> Repository<MI, S> where MI: ModuleIterator<PathBuf, Error>
+{
+    /// Returns an iterator over the list of modules (directories under `modules` directory)
+    pub fn module_iter<'a>(&'a self) -> Result<MI::Iter, Error> {
+        Ok(self.module_iterator.iter()?)
+    }
+}
+
+use crate::record::RecordContainerReduction;
+impl<MI, S: RecordStore> RecordContainerReduction for Repository<MI, S> { }
+
+impl<MI, S: RecordStore> RecordContainer for Repository<MI, S> {
+    type Error = Error;
+    type Record = S::Record;
+    type Records = Vec<S::Record>;
+    type Iter = RepositoryRecordIterator<S::Iter>;
+
+    fn record_iter(&self) -> Result<Self::Iter, Self::Error> {
+        Ok(RepositoryRecordIterator {
+            iter: self.store.record_iter()?,
+            hashing_algorithm: self.config.hashing_algorithm.clone(),
+            integrity_check: self.integrity_check,
+        })
+    }
+
+}
+
+
+/// Iterates over records of the repository's store, filtering out records that
+/// fail integrity check (unless it is disabled)
+pub struct RepositoryRecordIterator<I = DirectoryRecordIterator> {
+    iter: I,
+    hashing_algorithm: HashingAlgorithm,
+    integrity_check: bool,
+}
+
+impl<I, R> Iterator for RepositoryRecordIterator<I> where I: Iterator<Item = Vec<R>>, R: RecordTrait {
+    type Item = Vec<R>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let integrity_check = self.integrity_check;
+        let hashing_algorithm = &self.hashing_algorithm;
+        self.iter.next().map(|vec| {
+            vec.into_iter()
+                .filter(|r| integrity_check == false || r.integrity_intact(hashing_algorithm)).collect() }
+        )
+    }
+
+}
+
+/// Default record store, keeps every record in its own directory under `records/`
+///
+/// Directory names are split by two characters of the encoded hash
+/// to avoid having too many entries in a single directory.
+#[derive(Debug, Clone)]
+pub struct DirectoryRecordStore {
+    hashing_algorithm: HashingAlgorithm,
+    encoding: Encoding,
+    /// Path to records
+    path: PathBuf,
+    /// Path to the repository. Temporary directories are created there
+    /// and links are resolved within it.
+    root: PathBuf,
+    /// Pack indices, shared by clones of the store
+    packs: Arc<Mutex<PackCache>>,
+}
+
+impl DirectoryRecordStore {
+    /// Creates a new store for records under `path` within repository at `root`
+    pub fn new<P: Into<PathBuf>, R: Into<PathBuf>>(config: &Config, path: P, root: R) -> Self {
+        DirectoryRecordStore {
+            hashing_algorithm: config.hashing_algorithm.clone(),
+            encoding: config.encoding.clone(),
+            path: path.into(),
+            root: root.into(),
+            packs: Arc::new(Mutex::new(PackCache::default())),
+        }
+    }
+
+    /// Stores a record under a specific path (instead of the store's default one)
+    pub fn put_in<'f, P: AsRef<Path>, F: File + 'f>(&self, path: P, files: OrderedFiles<'f, F>) ->
+    Result<Record, Error> where F::Read: 'f {
+        let tempdir = TempDir::new_in(&self.root, "sit")?;
+        let mut hasher = self.hashing_algorithm.hasher();
+
+        files.hash_and(&mut *hasher, |n| -> Result<fs::File, Error> {
+            let path = normalize_file_name(n)?;
             let actual_path = path.to_path(tempdir.path());
             let mut dir = actual_path.clone();
             dir.pop();
@@ -662,106 +1036,215 @@ impl<MI> Repository<MI> {
 
 
         let hash = hasher.result_box();
-        let path = path.as_ref().join(crate::record::split_path(self.config.encoding.encode(&hash), 2));
-        if path.exists() {
-            fs::remove_dir_all(tempdir.into_path())?;
-        } else {
-            if cfg!(windows) {
-                // We have to handle Windows separately here because of how renaming works differently
-                // on Windows. From `std::fs::rename` documentation:
-                //
-                //     This function currently corresponds to the `rename` function on Unix
-                //     and the `MoveFileEx` function with the `MOVEFILE_REPLACE_EXISTING` flag on Windows.
-                //
-                //     Because of this, the behavior when both `from` and `to` exist differs. On
-                //     Unix, if `from` is a directory, `to` must also be an (empty) directory. If
-                //     `from` is not a directory, `to` must also be not a directory. In contrast,
-                //     on Windows, `from` can be anything, but `to` must *not* be a directory.
-                //
-                // So, we are avoiding creating the last directory component in the path on Windows:
-                fs::create_dir_all(path.parent().unwrap())?;
-            } else {
-                fs::create_dir_all(&path)?;
+        let name = self.encoding.encode(&hash);
+        // packed records are never stored again as loose ones
+        if path.as_ref() == self.path.as_path() {
+            if let Some(packed) = self.packed(&name)? {
+                return Ok(Record {
+                    hash,
+                    #[cfg(feature = "deprecated-item-api")]
+                    item: "".into(),
+                    path: packed.pack().to_path_buf(),
+                    encoding: self.encoding.clone(),
+                    packed: Some(packed),
+                });
+            }
+        }
+        let path = path.as_ref().join(crate::record::split_path(name, 2));
+        // The record is published by renaming the complete temporary directory
+        // into place. Renaming onto a path that doesn't exist is atomic, so readers
+        // never observe a partially written record.
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tempdir = tempdir.into_path();
+        if path.is_dir() {
+            fs::remove_dir_all(tempdir)?;
+        } else if let Err(err) = fs::rename(&tempdir, &path) {
+            fs::remove_dir_all(tempdir)?;
+            // a concurrent writer might have published the same record first
+            if !path.is_dir() {
+                return Err(err.into());
             }
-            fs::rename(tempdir.into_path(), &path)?;
         }
         Ok(Record {
             hash,
             #[cfg(feature = "deprecated-item-api")]
             item: "".into(),
             path,
-            encoding: self.config.encoding.clone(),
+            encoding: self.encoding.clone(),
+            packed: None,
         })
     }
-}
-
-impl<MI> RecordOwningContainer for Repository<MI> {
 
-    fn new_record<'f, F: File + 'f, I: Into<OrderedFiles<'f, F>>>(&self, files: I, link_parents: bool) -> Result<Record, Error> where F::Read: 'f {
-        self.new_record_in(&self.records_path, files, link_parents)
+    /// Returns path to packs
+    pub fn packs_path(&self) -> PathBuf {
+        self.root.join(PACKS_PATH)
     }
-}
 
+    /// Returns a packed record by its encoded hash
+    fn packed<S: AsRef<str>>(&self, name: S) -> Result<Option<Arc<PackedRecord>>, Error> {
+        self.packs.lock().unwrap().record(self.packs_path(), name)
+    }
 
-impl<MI> Repository<MI> where MI: ModuleIterator<PathBuf, Error>
-{
-    /// Returns an iterator over the list of modules (directories under `modules` directory)
-    pub fn module_iter<'a>(&'a self) -> Result<MI::Iter, Error> {
-        Ok(self.module_iterator.iter()?)
+    /// Moves all loose records into a new pack
+    ///
+    /// Records that fail integrity check (when `integrity_check` is `true`)
+    /// are left intact. Returns a path to the new pack archive, or `None` if
+    /// there was nothing to pack.
+    pub fn pack(&self, integrity_check: bool) -> Result<Option<PathBuf>, Error> {
+        let mut writer = PackWriter::new(self.packs_path(), &self.hashing_algorithm)?;
+        let mut packed = vec![];
+        for record in self.record_iter()?.flat_map(|v| v) {
+            if record.is_packed() || (integrity_check && !record.integrity_intact(&self.hashing_algorithm)) {
+                continue;
+            }
+            let name = record.encoded_hash();
+            for (file, reader) in record.file_iter() {
+                writer.add(&name, file, reader)?;
+            }
+            packed.push(record.path);
+        }
+        if writer.is_empty() {
+            return Ok(None);
+        }
+        let pack = writer.finish(&self.encoding)?;
+        let path = self.path.resolve_dir(&self.root).unwrap_or(self.path.clone());
+        for record in packed {
+            fs::remove_dir_all(&record)?;
+            // remove split path directories left empty
+            let mut dir = record;
+            while dir.pop() && dir != path && dir.starts_with(&path) {
+                if fs::remove_dir(&dir).is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(Some(pack))
     }
 }
 
-use crate::record::RecordContainerReduction;
-impl<MI> RecordContainerReduction for Repository<MI> { }
-
-impl<MI> RecordContainer for Repository<MI> {
-    type Error = Error;
+impl RecordStore for DirectoryRecordStore {
     type Record = Record;
-    type Records = Vec<Record>;
-    type Iter = RepositoryRecordIterator;
+    type Iter = DirectoryRecordIterator;
+    type Lock = DirectoryLock;
 
-    fn record_iter(&self) -> Result<Self::Iter, Self::Error> {
-        let path = self.records_path().resolve_dir(self.path()).unwrap_or(self.records_path().into());
-        let iter = GenericRecordIterator::new(self.config.hashing_algorithm.clone(),
-                                              self.config.encoding.clone(),
+    fn record_iter(&self) -> Result<Self::Iter, Error> {
+        let path = self.path.resolve_dir(&self.root).unwrap_or(self.path.clone());
+        let iter = GenericRecordIterator::new(self.hashing_algorithm.clone(),
+                                              self.encoding.clone(),
                                               path,
                                               None,
-                                              self.path().into());
-        Ok(RepositoryRecordIterator {
-            iter,
-            integrity_check: self.integrity_check,
-        })
+                                              self.root.clone(),
+                                              self.packs.lock().unwrap().records(self.packs_path())?);
+        Ok(DirectoryRecordIterator { iter })
+    }
+
+    fn record<N: AsRef<str>>(&self, name: N) -> Option<Record> {
+        let path = self.path.join(crate::record::split_path(name.as_ref(), 2));
+        let path = path.resolve_dir(&self.root).unwrap_or(path);
+        if path.is_dir() && path.strip_prefix(&self.path).is_ok() {
+            let hash = self.encoding.decode(path.file_name().unwrap().to_str().unwrap().as_bytes());
+            if hash.is_err() {
+                return None
+            }
+            let record = Record {
+                hash: hash.unwrap(),
+                encoding: self.encoding.clone(),
+                path,
+                #[cfg(feature = "deprecated-item-api")]
+                item: "".into(),
+                packed: None,
+            };
+            Some(record)
+        } else {
+            let name = name.as_ref();
+            let hash = self.encoding.decode(name.as_bytes()).ok()?;
+            let packed = self.packed(name).ok()??;
+            Some(Record {
+                hash,
+                encoding: self.encoding.clone(),
+                path: packed.pack().to_path_buf(),
+                #[cfg(feature = "deprecated-item-api")]
+                item: "".into(),
+                packed: Some(packed),
+            })
+        }
+    }
+
+    fn put<'f, F: File + 'f>(&self, files: OrderedFiles<'f, F>) -> Result<Record, Error> where F::Read: 'f {
+        self.put_in(&self.path, files)
     }
 
+    fn lock(&self) -> Result<DirectoryLock, Error> {
+        let path = self.root.join(CACHE_PATH);
+        fs::create_dir_all(&path)?;
+        let gitignore = path.join(".gitignore");
+        if !gitignore.is_file() {
+            // cache is local and should never be committed
+            fs::write(gitignore, b"*\n")?;
+        }
+        let file = fs::OpenOptions::new().create(true).write(true).open(path.join(LOCK_FILE))?;
+        file.lock_exclusive()?;
+        Ok(DirectoryLock(file))
+    }
 }
 
+/// Writer lock of [`DirectoryRecordStore`]
+///
+/// It is an advisory lock on `cache/lock` in the repository, so it is
+/// respected by all processes (and threads) writing to the repository.
+///
+/// [`DirectoryRecordStore`]: struct.DirectoryRecordStore.html
+#[derive(Debug)]
+pub struct DirectoryLock(fs::File);
 
-pub struct RepositoryRecordIterator {
+impl Drop for DirectoryLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+/// Iterates over records stored in [`DirectoryRecordStore`]
+///
+/// [`DirectoryRecordStore`]: struct.DirectoryRecordStore.html
+pub struct DirectoryRecordIterator {
     iter: GenericRecordIterator,
-    integrity_check: bool,
 }
 
-impl Iterator for RepositoryRecordIterator {
+impl Iterator for DirectoryRecordIterator {
     type Item = Vec<Record>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let encoding = &self.iter.encoding;
         self.iter.next().map(|vec| {
-            vec.into_iter().map(|(path, hash)|
+            vec.into_iter().map(|(path, hash, packed)|
                 Record {
                     hash,
                     #[cfg(feature = "deprecated-item-api")]
                     item: "".into(),
                     path,
-                    encoding: self.iter.encoding.clone(),
-            }).filter(|r| self.integrity_check == false || r.integrity_intact(&self.iter.hashing_algorithm)).collect() }
+                    encoding: encoding.clone(),
+                    packed,
+            }).collect() }
         )
     }
 
 }
 
-impl<MI> PartialEq for Repository<MI> {
-    fn eq(&self, rhs: &Repository<MI>) -> bool {
-        (self as *const Repository<MI>) == (rhs as *const Repository<MI>)
+/// Normalizes record's file name, ensuring it doesn't point outside of the record
+pub(crate) fn normalize_file_name(name: &str) -> Result<RelativePathBuf, Error> {
+    let path = RelativePath::new(name).normalize();
+    if path.components().any(|c| match c {
+        RelativeComponent::Normal(_) => false,
+        _ => true,
+    }) {
+        return Err(Error::PathPrefixError);
+    }
+    Ok(path)
+}
+
+impl<MI, S> PartialEq for Repository<MI, S> {
+    fn eq(&self, rhs: &Repository<MI, S>) -> bool {
+        (self as *const Repository<MI, S>) == (rhs as *const Repository<MI, S>)
     }
 }
 
@@ -780,8 +1263,8 @@ pub struct Item<'a, MI: 'a> {
     path: PathBuf,
 }
 
-use crate::record::{File, OrderedFiles};
-use relative_path::{RelativePath, Component as RelativeComponent};
+use crate::record::{File, OrderedFiles, BoxedOrderedFiles};
+use relative_path::{RelativePath, RelativePathBuf, Component as RelativeComponent};
 
 #[cfg(feature = "deprecated-item-api")]
 impl<'a, MI: 'a> HasPath for Item<'a, MI> {
@@ -835,7 +1318,7 @@ impl<'a, MI: 'a> RecordContainer for Item<'a, MI> {
         let iter = GenericRecordIterator::new(self.repository.config.hashing_algorithm.clone(),
                                               self.repository.config.encoding.clone(),
                                               path,
-                                              Some(1), self.repository.path().into());
+                                              Some(1), self.repository.path().into(), vec![]);
         Ok(ItemRecordIterator {
             iter,
             item: self.id.clone(),
@@ -888,12 +1371,13 @@ impl Iterator for ItemRecordIterator {
 
     fn next(&mut self) -> Option<Self::Item> {
         self.iter.next().map(|vec| {
-            vec.into_iter().map(|(path, hash)|
+            vec.into_iter().map(|(path, hash, packed)|
                 Record {
                     hash,
                     item: self.item.clone(),
                     path,
                     encoding: self.iter.encoding.clone(),
+                    packed,
             })
                 .filter(|r| self.integrity_check == false || r.integrity_intact(&self.iter.hashing_algorithm))
                 .collect() }
@@ -904,97 +1388,114 @@ impl Iterator for ItemRecordIterator {
 
 use walkdir;
 
+/// A record found by [`GenericRecordIterator`]
+type FoundRecord = (PathBuf, Vec<u8>, Option<Arc<PackedRecord>>);
+
 /// An iterator over records
+///
+/// The graph of records is built once, upon creation of the iterator.
 struct GenericRecordIterator {
     hashing_algorithm: HashingAlgorithm,
     encoding: Encoding,
-    path: PathBuf,
-    dir: Vec<walkdir::DirEntry>,
-    parents: Vec<String>,
-    root: PathBuf,
+    records: Vec<Option<FoundRecord>>,
+    generations: Generations,
 }
 
 impl GenericRecordIterator {
     fn new(hashing_algorithm: HashingAlgorithm, encoding: Encoding, path: PathBuf,
-           depth: Option<usize>, root: PathBuf) -> Self {
+           depth: Option<usize>, root: PathBuf, packs: Vec<(String, Arc<PackedRecord>)>) -> Self {
         let depth = depth.or_else(|| {
-            let mut depth = hashing_algorithm.len() * 4 / encoding.bit_width();
-            if hashing_algorithm.len() * 4 % encoding.bit_width() != 0 {
-                depth +=1;
-            }
-            Some(depth)
+            // every two characters of the encoded hash (including padding)
+            // make one level of directories
+            let chars = encoding.encode_len(hashing_algorithm.len());
+            Some((chars + 1) / 2)
         }).unwrap();
-        let dir: Vec<_> = walkdir::WalkDir::new(&path).min_depth(depth).max_depth(depth)
-            .into_iter().filter_map(Result::ok).collect();
+
+        // Returns true if there's a record to link to
+        let record_exists = |name: &str| -> bool {
+            #[cfg(feature ="deprecated-item-api")]
+            let is_dir = {
+                let p = path.join(name);
+                p.resolve_dir(&root).unwrap_or(p).is_dir()
+            };
+            #[cfg(not(feature ="deprecated-item-api"))]
+            let is_dir = false;
+            is_dir || {
+                let p = path.join(crate::record::split_path(name, 2));
+                p.resolve_dir(&root).unwrap_or(p).is_dir()
+            }
+        };
+
+        let mut names = vec![];
+        let mut records = vec![];
+        for e in walkdir::WalkDir::new(&path).min_depth(depth).max_depth(depth).into_iter().filter_map(Result::ok) {
+            let record_path = e.path().resolve_dir(&root).unwrap_or(e.path().to_path_buf());
+            if !record_path.is_dir() {
+                continue;
+            }
+            let name = e.file_name().to_str().unwrap();
+            if let Ok(hash) = encoding.decode(name.as_bytes()) {
+                names.push(String::from(name));
+                records.push((record_path, hash, None));
+            }
+        }
+        for (name, record) in packs {
+            // loose copies of a record take precedence
+            if record_exists(&name) {
+                continue;
+            }
+            if let Ok(hash) = encoding.decode(name.as_bytes()) {
+                names.push(name);
+                records.push((record.pack().to_path_buf(), hash, Some(record)));
+            }
+        }
+
+        let index: HashMap<&str, usize> = names.iter().enumerate().map(|(i, name)| (name.as_str(), i)).collect();
+        // Resolves links to parents. Links pointing to something other than records are ignored,
+        // and a record with a link to a record that can't be iterated over can't be iterated over either.
+        let resolve = |links: &mut dyn Iterator<Item = String>| -> Option<Vec<usize>> {
+            let mut parents = vec![];
+            for link in links {
+                match index.get(link.as_str()) {
+                    Some(&i) => parents.push(i),
+                    None if record_exists(&link) => return None,
+                    None => (),
+                }
+            }
+            Some(parents)
+        };
+        let parents: Vec<_> = records.iter().map(|(record_path, _, packed)| match packed {
+            Some(packed) => resolve(&mut packed.parents().map(String::from)),
+            None => {
+                let dot_prev = record_path.join(".prev");
+                if !dot_prev.is_dir() {
+                    Some(vec![])
+                } else {
+                    match fs::read_dir(dot_prev) {
+                        Err(_) => None,
+                        Ok(dir) => resolve(&mut dir.filter_map(Result::ok)
+                            .map(|l| String::from(l.file_name().to_str().unwrap()))),
+                    }
+                }
+            },
+        }).collect();
+
         GenericRecordIterator {
             encoding,
             hashing_algorithm,
-            dir,
-            path,
-            parents: vec![],
-            root,
+            records: records.into_iter().map(Some).collect(),
+            generations: Generations::new(parents),
         }
     }
 }
 
 impl Iterator for GenericRecordIterator {
-    type Item = Vec<(PathBuf, Vec<u8>)>;
+    type Item = Vec<FoundRecord>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // TODO: if https://github.com/rust-lang/rust/issues/43244 is finalized, try to use drain_filter instead
-        let (filtered, dir): (Vec<_>, Vec<_>) = std::mem::replace(&mut self.dir, vec![]).into_iter()
-            .partition(|e| {
-                let path = e.path().resolve_dir(&self.root).unwrap_or(e.path().to_path_buf());
-                if !path.is_dir() {
-                    return false
-                }
-
-                let name = e.file_name().to_str().unwrap();
-
-                let valid_name = self.encoding.decode(name.as_bytes()).is_ok();
-                if !valid_name {
-                    return false;
-                }
-
-                let dot_prev = path.join(".prev");
-                let has_all_valid_parents = !dot_prev.is_dir() || match fs::read_dir(dot_prev) {
-                    Err(_) => false,
-                    Ok(dir) => {
-                        dir.filter_map(Result::ok)
-                            // only use links pointing to actual directories
-                            .filter(|l| {
-                                #[cfg(feature ="deprecated-item-api")]
-                                let is_dir = {
-                                    let p = self.path.join(l.file_name().to_str().unwrap());
-                                    p.resolve_dir(&self.root).unwrap_or(p).is_dir()
-                                };
-                                #[cfg(not(feature ="deprecated-item-api"))]
-                                let is_dir = false;
-                                is_dir || {
-                                    let p = self.path.join(crate::record::split_path(l.file_name().to_str().unwrap(), 2));
-                                    p.resolve_dir(&self.root).unwrap_or(p).is_dir()
-                                }
-                            })
-                            // has to be already processed
-                            .all(|l| self.parents.iter().any(|p| p.as_str() == l.file_name().to_str().unwrap()))
-                    }
-                };
-
-                has_all_valid_parents
-            });
-        let result: Vec<_> = filtered.iter()
-            .map(|e| {
-                let name = e.file_name().to_str().unwrap();
-                let decoded_name = self.encoding.decode(name.as_bytes()).unwrap();
-                (e.path().resolve_dir(&self.root).unwrap_or(e.path().to_path_buf()), decoded_name)
-            }).collect();
-        self.dir = dir;
-        if result.len() == 0 {
-            return None
-        }
-        let encoding = self.encoding.clone();
-        self.parents.append(&mut result.iter().map(|(_, rhash)| encoding.encode(rhash)).collect());
-        Some(result)
+        let records = &mut self.records;
+        self.generations.next()
+            .map(|generation| generation.into_iter().filter_map(|i| records[i].take()).collect())
     }
 }
 
@@ -1043,11 +1544,21 @@ pub struct Record {
     item: OsString,
     encoding: Encoding,
     path: PathBuf,
+    packed: Option<Arc<PackedRecord>>,
+}
+
+impl Record {
+    /// Returns true if the record is stored in a pack
+    pub fn is_packed(&self) -> bool {
+        self.packed.is_some()
+    }
 }
 
 impl HasPath for Record {
 
     /// Returns path to the record
+    ///
+    /// For packed records, this is the path to the pack archive
     fn path(&self) -> &Path {
         self.path.as_path()
     }
@@ -1072,7 +1583,7 @@ impl PartialEq for Record {
 }
 
 impl RecordTrait for Record {
-    type Read = std::fs::File;
+    type Read = RecordFile;
     type Str = String;
     type Hash = Vec<u8>;
     type Iter = RecordFileIterator;
@@ -1086,11 +1597,19 @@ impl RecordTrait for Record {
     }
 
     fn file_iter(&self) -> Self::Iter {
-        let path = self.path();
-        let glob_pattern = format!("{}/**/*", path.to_str().unwrap());
-        RecordFileIterator {
-            glob: glob::glob(&glob_pattern).expect("invalid glob pattern"),
-            prefix: self.path().into(),
+        match self.packed {
+            Some(ref packed) => RecordFileIterator::Packed {
+                record: packed.clone(),
+                index: 0,
+            },
+            None => {
+                let path = self.path();
+                let glob_pattern = format!("{}/**/*", path.to_str().unwrap());
+                RecordFileIterator::Loose {
+                    glob: glob::glob(&glob_pattern).expect("invalid glob pattern"),
+                    prefix: self.path().into(),
+                }
+            },
         }
     }
     #[cfg(feature = "deprecated-item-api")]
@@ -1099,33 +1618,65 @@ impl RecordTrait for Record {
     }
 }
 
+/// A file in a record
+pub enum RecordFile {
+    /// File in a record directory
+    Loose(fs::File),
+    /// File in a pack archive
+    Packed(io::Take<fs::File>),
+}
+
+impl Read for RecordFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            RecordFile::Loose(f) => f.read(buf),
+            RecordFile::Packed(f) => f.read(buf),
+        }
+    }
+}
+
 /// An iterator over files in a record
-pub struct RecordFileIterator {
-    glob: glob::Paths,
-    prefix: PathBuf,
+pub enum RecordFileIterator {
+    /// Record directory
+    Loose {
+        glob: glob::Paths,
+        prefix: PathBuf,
+    },
+    /// Packed record
+    Packed {
+        record: Arc<PackedRecord>,
+        index: usize,
+    },
 }
 
 impl Iterator for RecordFileIterator {
-    type Item = (String, fs::File);
+    type Item = (String, RecordFile);
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.glob.next() {
-                None => return None,
-                // skip on errors
-                Some(Err(_)) => continue,
-                Some(Ok(name)) => {
-                    if name.is_file() {
-                        let stripped = String::from(name.strip_prefix(&self.prefix).unwrap().to_str().unwrap());
-                        #[cfg(windows)] // replace backslashes with slashes
-                        let stripped = stripped.replace("\\", "/");
-                        return Some((stripped, fs::File::open(name).unwrap()))
-                    } else {
-                        // if it is not a file, keep iterating
-                        continue
+        match self {
+            RecordFileIterator::Loose { glob, prefix } => loop {
+                match glob.next() {
+                    None => return None,
+                    // skip on errors
+                    Some(Err(_)) => continue,
+                    Some(Ok(name)) => {
+                        if name.is_file() {
+                            let stripped = String::from(name.strip_prefix(&prefix).unwrap().to_str().unwrap());
+                            #[cfg(windows)] // replace backslashes with slashes
+                            let stripped = stripped.replace("\\", "/");
+                            return Some((stripped, RecordFile::Loose(fs::File::open(name).unwrap())))
+                        } else {
+                            // if it is not a file, keep iterating
+                            continue
+                        }
                     }
                 }
-            }
+            },
+            RecordFileIterator::Packed { record, index } => {
+                let entry = record.entries().get(*index)?;
+                *index += 1;
+                Some((entry.name.clone(), RecordFile::Packed(record.open(entry).unwrap())))
+            },
         }
     }
 }
@@ -1419,6 +1970,13 @@ mod tests {
         assert_eq!(record.path().strip_prefix(repo.records_path()).unwrap(), path);
     }
 
+    #[test]
+    fn split_path_odd_length() {
+        // encodings without padding may produce identifiers of odd length
+        assert_eq!(crate::record::split_path("ABCDE", 2), Path::new("AB/CD/ABCDE"));
+        assert_eq!(crate::record::split_path("ABCD", 2), Path::new("AB/ABCD"));
+    }
+
     #[test]
     fn record_integrity_check() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
@@ -1501,6 +2059,68 @@ mod tests {
         assert_eq!(record.file_iter().next().unwrap().name(), "test2");
     }
 
+    /// Creates linked records in a repository using given hashing algorithm
+    /// and checks they can be found and are intact
+    fn records_with_hashing_algorithm(hashing_algorithm: HashingAlgorithm) {
+        let mut config = Config::default();
+        config.hashing_algorithm = hashing_algorithm;
+        records_with_config(config);
+    }
+
+    /// Creates linked records in a repository using given encoding
+    /// and checks they can be found and are intact
+    fn records_with_encoding(encoding: Encoding) {
+        let mut config = Config::default();
+        config.encoding = encoding;
+        records_with_config(config);
+    }
+
+    fn records_with_config(config: Config) {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new_with_config(&tmp, config).unwrap();
+        let record1 = repo.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        let record2 = repo.new_record(vec![("test", &[2u8][..])].into_iter(), true).unwrap();
+        assert_eq!(record1.hash().len(), repo.config().hashing_algorithm().len());
+        let records: Vec<Vec<_>> = repo.record_iter().unwrap().collect();
+        assert_eq!(records, vec![vec![record1.clone()], vec![record2.clone()]]);
+        assert!(records.iter().flat_map(|r| r).all(|r| r.integrity_intact(repo.config().hashing_algorithm())));
+        assert_eq!(repo.record(record2.encoded_hash()).unwrap(), record2);
+    }
+
+    #[test]
+    fn records_base32_lowercase() {
+        records_with_encoding(Encoding::Base32Lower);
+    }
+
+    #[test]
+    fn records_hex() {
+        records_with_encoding(Encoding::Hex);
+    }
+
+    #[test]
+    fn records_base58() {
+        records_with_encoding(Encoding::Base58);
+    }
+
+    #[test]
+    #[cfg(feature = "blake2")]
+    fn records_blake2b_256() {
+        records_with_hashing_algorithm(HashingAlgorithm::Blake2b { size: 32 });
+    }
+
+    #[test]
+    #[cfg(feature = "sha2")]
+    fn records_sha256() {
+        records_with_hashing_algorithm(HashingAlgorithm::SHA256);
+    }
+
+    #[test]
+    #[cfg(feature = "blake3")]
+    fn records_blake3() {
+        records_with_hashing_algorithm(HashingAlgorithm::Blake3);
+    }
+
     #[test]
     fn new_record_parents_linking() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
@@ -1517,6 +2137,53 @@ mod tests {
         assert!(record.file_iter().any(|(name, _)| name == *&record2link));
     }
 
+    #[test]
+    fn concurrent_writers() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        Repository::new(&tmp).unwrap();
+        let threads: Vec<_> = (0..8u8).map(|i| {
+            let tmp = tmp.clone();
+            std::thread::spawn(move || {
+                // every writer has its own repository (and lock file handle)
+                let repo = Repository::open(&tmp).unwrap();
+                for j in 0..10u8 {
+                    repo.new_record(vec![("test", &[i, j][..])].into_iter(), true).unwrap();
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let repo = Repository::open(&tmp).unwrap();
+        // every record is linked to the previous one
+        let generations: Vec<_> = repo.record_iter().unwrap().collect();
+        assert_eq!(generations.len(), 80);
+        assert!(generations.iter().all(|records| records.len() == 1));
+        assert!(tmp.join("cache").join(".gitignore").is_file());
+    }
+
+    #[test]
+    fn concurrent_same_record() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        Repository::new(&tmp).unwrap();
+        let threads: Vec<_> = (0..8).map(|_| {
+            let tmp = tmp.clone();
+            std::thread::spawn(move || {
+                let repo = Repository::open(&tmp).unwrap();
+                // bypass the lock to race publishing
+                let files: OrderedFiles<_> = vec![("test", &b"hello"[..])].into();
+                repo.store().put(files).unwrap()
+            })
+        }).collect();
+        let records: Vec<_> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        let repo = Repository::open(&tmp).unwrap();
+        let all: Vec<_> = repo.record_iter().unwrap().flat_map(|v| v).collect();
+        assert_eq!(all, vec![records[0].clone()]);
+        assert!(all[0].integrity_intact(repo.config().hashing_algorithm()));
+    }
+
     #[test]
     fn record_ordering() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
@@ -1636,6 +2303,63 @@ mod tests {
         assert!(row_1.iter().any(|r| r == &record1_2));
     }
 
+    #[test]
+    fn packed_records() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(&tmp).unwrap();
+        let record1 = repo.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        let record2 = repo.new_record(vec![("test", &[2u8][..]), ("z/a", &[3u8][..])].into_iter(), true).unwrap();
+        let pack = repo.pack().unwrap().unwrap();
+        assert!(pack.is_file());
+        assert!(!record1.path().exists());
+        assert!(!record2.path().exists());
+        // nothing left to pack
+        assert!(repo.pack().unwrap().is_none());
+
+        // packed records can be found
+        let packed = repo.record(record2.encoded_hash()).unwrap();
+        assert!(packed.is_packed());
+        assert_eq!(packed.hash(), record2.hash());
+        assert!(packed.integrity_intact(repo.config().hashing_algorithm()));
+        let mut files: Vec<_> = packed.file_iter().map(|(name, _)| name).collect();
+        files.sort();
+        assert_eq!(files, vec![format!(".prev/{}", record1.encoded_hash()), "test".into(), "z/a".into()]);
+
+        // new loose records are linked to packed ones
+        let record3 = repo.new_record(vec![("test", &[4u8][..])].into_iter(), true).unwrap();
+        assert!(!record3.is_packed());
+
+        let mut records: Vec<_> = repo.record_iter().unwrap().collect();
+        assert_eq!(records.pop().unwrap(), vec![record3]);
+        assert_eq!(records.pop().unwrap(), vec![record2]);
+        assert_eq!(records.pop().unwrap(), vec![record1]);
+        assert_eq!(records.len(), 0);
+    }
+
+    #[test]
+    fn packed_records_not_stored_again() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        let repo = Repository::new(tmp.join("repo")).unwrap();
+        tmp.push("other");
+        let other = Repository::new(&tmp).unwrap();
+        let record = repo.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        other.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        repo.pack().unwrap().unwrap();
+
+        let again = repo.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        assert!(again.is_packed());
+        assert_eq!(again.hash(), record.hash());
+        assert!(!record.path().exists());
+
+        let report = repo.import_records_from(&other).unwrap();
+        assert!(report.imported.is_empty());
+        assert_eq!(report.present, vec![record.encoded_hash()]);
+        assert!(!record.path().exists());
+        // nothing loose to pack
+        assert!(repo.pack().unwrap().is_none());
+    }
+
     #[test]
     fn record_deterministic_hashing() {
         let mut tmp = TempDir::new("sit").unwrap().into_path();
@@ -1935,5 +2659,110 @@ mod tests {
         assert!(iter.next().is_none());
     }
 
+    #[test]
+    fn import_records_from() {
+        let repo = Repository::in_memory(Config::default());
+        let other = Repository::in_memory(Config::default());
+        let record1 = other.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        let record2 = other.new_record(vec![("test", &[2u8][..])].into_iter(), true).unwrap();
+        let record3 = repo.new_record(vec![("test", &[3u8][..])].into_iter(), false).unwrap();
+        repo.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+
+        let report = repo.import_records_from(&other).unwrap();
+        assert_eq!(report.imported, vec![record2.encoded_hash()]);
+        assert_eq!(report.present, vec![record1.encoded_hash()]);
+        assert!(report.conflicting.is_empty());
+        assert!(report.invalid.is_empty());
+        assert_eq!(repo.record(record2.encoded_hash()).unwrap(), record2);
+
+        // the other way around
+        let report = other.import_records_from(&repo).unwrap();
+        assert_eq!(report.imported, vec![record3.encoded_hash()]);
+        assert_eq!(report.present.len(), 2);
+
+        // nothing left to import
+        assert!(repo.import_records_from(&other).unwrap().imported.is_empty());
+    }
+
+    #[test]
+    fn import_records_from_invalid_and_conflicting() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        let repo = Repository::new(tmp.join("repo")).unwrap();
+        tmp.push("other");
+        let other = Repository::new(&tmp).unwrap();
+        let record1 = other.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        let record2 = other.new_record(vec![("test", &[2u8][..])].into_iter(), false).unwrap();
+        let local = repo.new_record(vec![("test", &[2u8][..])].into_iter(), false).unwrap();
+        // corrupt one record in the other repository and another one locally
+        fs::File::create(record1.path().join("test")).unwrap().write_all(b"corrupted").unwrap();
+        fs::File::create(local.path().join("test")).unwrap().write_all(b"corrupted").unwrap();
+
+        let report = repo.import_records_from(&other).unwrap();
+        assert!(report.imported.is_empty());
+        assert_eq!(report.invalid, vec![record1.encoded_hash()]);
+        assert_eq!(report.conflicting, vec![record2.encoded_hash()]);
+        assert!(repo.record(record1.encoded_hash()).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "blake2")]
+    fn import_records_from_different_hashing() {
+        let repo = Repository::in_memory(Config::default());
+        let mut config = Config::default();
+        config.hashing_algorithm = HashingAlgorithm::Blake2b { size: 32 };
+        let other = Repository::in_memory(config);
+        assert_matches!(repo.import_records_from(&other), Err(Error::OtherError(_)));
+    }
+
+    #[test]
+    fn gc() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(&tmp).unwrap();
+        let record = repo.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        let orphan = repo.new_record(vec![("test", &[2u8][..]), (".prev/MISSING", &[][..])].into_iter(), false).unwrap();
+        // leftovers of interrupted writers (0, 1 and 9 never appear in record names)
+        let temporary = TempDir::new_in(&tmp, "sit").unwrap().into_path();
+        fs::create_dir_all(repo.records_path().join("00").join("01")).unwrap();
+        fs::create_dir_all(repo.records_path().join("99")).unwrap();
+
+        let report = repo.gc(false).unwrap();
+        assert_eq!(report.temporary, vec![temporary.clone()]);
+        assert_eq!(report.empty, vec![repo.records_path().join("00").join("01"),
+                                      repo.records_path().join("00"),
+                                      repo.records_path().join("99")]);
+        assert_eq!(report.orphaned_links, vec![(orphan.encoded_hash(), String::from("MISSING"))]);
+        // nothing is removed yet
+        assert!(temporary.is_dir());
+        assert!(repo.records_path().join("99").is_dir());
+
+        assert_eq!(repo.gc(true).unwrap(), report);
+        assert!(!temporary.exists());
+        assert!(!repo.records_path().join("00").exists());
+        assert!(!repo.records_path().join("99").exists());
+        assert!(record.path().is_dir());
+        assert!(orphan.path().is_dir());
+
+        let report = repo.gc(true).unwrap();
+        assert!(report.temporary.is_empty());
+        assert!(report.empty.is_empty());
+        assert_eq!(report.orphaned_links.len(), 1);
+    }
+
+    #[test]
+    fn gc_keeps_records_without_files() {
+        let mut tmp = TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let repo = Repository::new(&tmp).unwrap();
+        let record = repo.new_record(Vec::<(&str, &[u8])>::new().into_iter(), false).unwrap();
+        assert!(record.path().is_dir());
+        assert_eq!(fs::read_dir(record.path()).unwrap().count(), 0);
+
+        let report = repo.gc(true).unwrap();
+        assert!(report.empty.is_empty());
+        assert!(record.path().is_dir());
+        assert!(repo.record(record.encoded_hash()).is_some());
+    }
+
 }
 
@@ -0,0 +1,339 @@
+//! Record stores are responsible for keeping records
+//!
+//! [`Repository`] is generic over a [`RecordStore`], with
+//! [`DirectoryRecordStore`] being the default one. [`MemoryRecordStore`]
+//! keeps records in memory and never touches the file system.
+//!
+//! [`Repository`]: ../repository/struct.Repository.html
+//! [`RecordStore`]: trait.RecordStore.html
+//! [`DirectoryRecordStore`]: ../repository/struct.DirectoryRecordStore.html
+//! [`MemoryRecordStore`]: struct.MemoryRecordStore.html
+
+use std::collections::HashMap;
+use std::io::{self, Cursor};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::cell::RefCell;
+
+use crate::hash::HashingAlgorithm;
+use crate::encoding::Encoding;
+use crate::record::{Record, File, OrderedFiles};
+use crate::repository::Error;
+use crate::graph::Generations;
+
+/// Storage backend for records
+pub trait RecordStore {
+    /// Record type used by the store
+    type Record: Record;
+    /// Iterator over generations of records
+    type Iter: Iterator<Item = Vec<Self::Record>>;
+    /// Writer lock (released when dropped)
+    type Lock;
+
+    /// Iterates through the tree of records, one generation at a time
+    ///
+    /// Every generation only contains records whose (known) parents have
+    /// been returned in preceding generations.
+    fn record_iter(&self) -> Result<Self::Iter, Error>;
+
+    /// Finds a record by its encoded hash (if there is one)
+    fn record<S: AsRef<str>>(&self, name: S) -> Option<Self::Record>;
+
+    /// Hashes and stores files as a record
+    ///
+    /// If a record with the same hash is already present, it is kept intact.
+    fn put<'f, F: File + 'f>(&self, files: OrderedFiles<'f, F>) -> Result<Self::Record, Error> where F::Read: 'f;
+
+    /// Acquires an exclusive writer lock, waiting for other writers to release it
+    ///
+    /// Writers hold this lock while they derive new records from the existing
+    /// ones (such as when linking parents), so that they never race each other.
+    fn lock(&self) -> Result<Self::Lock, Error>;
+}
+
+type MemoryFiles = Arc<Vec<(String, Vec<u8>)>>;
+
+/// Keeps records in memory
+///
+/// Clones of the store share the same records.
+#[derive(Debug, Clone)]
+pub struct MemoryRecordStore {
+    hashing_algorithm: HashingAlgorithm,
+    encoding: Encoding,
+    records: Arc<RwLock<HashMap<Vec<u8>, MemoryFiles>>>,
+    writer: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl MemoryRecordStore {
+    /// Creates an empty store
+    pub fn new(hashing_algorithm: HashingAlgorithm, encoding: Encoding) -> Self {
+        MemoryRecordStore {
+            hashing_algorithm,
+            encoding,
+            records: Arc::new(RwLock::new(HashMap::new())),
+            writer: Arc::new((Mutex::new(false), Condvar::new())),
+        }
+    }
+
+    fn memory_record(&self, hash: Vec<u8>, files: MemoryFiles) -> MemoryRecord {
+        MemoryRecord {
+            hash,
+            encoding: self.encoding.clone(),
+            files,
+        }
+    }
+}
+
+impl RecordStore for MemoryRecordStore {
+    type Record = MemoryRecord;
+    type Iter = std::vec::IntoIter<Vec<MemoryRecord>>;
+    type Lock = MemoryLock;
+
+    fn record_iter(&self) -> Result<Self::Iter, Error> {
+        let records = self.records.read().unwrap();
+        let mut records: Vec<_> = records.iter()
+            .map(|(hash, files)| Some(self.memory_record(hash.clone(), files.clone())))
+            .collect();
+        let names: Vec<String> = records.iter().map(|r| r.as_ref().unwrap().encoded_hash()).collect();
+        let index: HashMap<&str, usize> = names.iter().enumerate().map(|(i, name)| (name.as_str(), i)).collect();
+        let parents = records.iter()
+            .map(|r| Some(r.as_ref().unwrap().parents().into_iter()
+                // only use links pointing to actual records
+                .filter_map(|p| index.get(p.as_str()).cloned())
+                .collect()))
+            .collect();
+        let generations: Vec<Vec<_>> = Generations::new(parents)
+            .map(|generation| generation.into_iter().filter_map(|i| records[i].take()).collect())
+            .collect();
+        Ok(generations.into_iter())
+    }
+
+    fn record<S: AsRef<str>>(&self, name: S) -> Option<MemoryRecord> {
+        let hash = self.encoding.decode(name.as_ref().as_bytes()).ok()?;
+        let records = self.records.read().unwrap();
+        records.get(&hash).map(|files| self.memory_record(hash.clone(), files.clone()))
+    }
+
+    fn put<'f, F: File + 'f>(&self, files: OrderedFiles<'f, F>) -> Result<MemoryRecord, Error> where F::Read: 'f {
+        let mut hasher = self.hashing_algorithm.hasher();
+        let contents = RefCell::new(vec![]);
+
+        files.hash_and(&mut *hasher, |n| -> Result<usize, Error> {
+            let path = crate::repository::normalize_file_name(n)?;
+            if path.as_str().is_empty() {
+                return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+            }
+            let mut contents = contents.borrow_mut();
+            contents.push((String::from(path.as_str()), vec![]));
+            Ok(contents.len() - 1)
+        }, |i, c| -> Result<usize, Error> {
+            contents.borrow_mut()[i].1.extend_from_slice(c);
+            Ok(i)
+        })?;
+
+        let hash = hasher.result_box();
+        let mut records = self.records.write().unwrap();
+        let files = records.entry(hash.clone())
+            .or_insert_with(|| Arc::new(contents.into_inner()))
+            .clone();
+        Ok(self.memory_record(hash, files))
+    }
+
+    fn lock(&self) -> Result<MemoryLock, Error> {
+        let (ref locked, ref condvar) = *self.writer;
+        let mut locked = locked.lock().unwrap();
+        while *locked {
+            locked = condvar.wait(locked).unwrap();
+        }
+        *locked = true;
+        Ok(MemoryLock(self.writer.clone()))
+    }
+}
+
+/// Writer lock of [`MemoryRecordStore`]
+///
+/// [`MemoryRecordStore`]: struct.MemoryRecordStore.html
+#[derive(Debug)]
+pub struct MemoryLock(Arc<(Mutex<bool>, Condvar)>);
+
+impl Drop for MemoryLock {
+    fn drop(&mut self) {
+        let (ref locked, ref condvar) = *self.0;
+        *locked.lock().unwrap() = false;
+        condvar.notify_one();
+    }
+}
+
+/// Record kept in [`MemoryRecordStore`]
+///
+/// [`MemoryRecordStore`]: struct.MemoryRecordStore.html
+#[derive(Debug, Clone)]
+pub struct MemoryRecord {
+    hash: Vec<u8>,
+    encoding: Encoding,
+    files: MemoryFiles,
+}
+
+impl PartialEq for MemoryRecord {
+    fn eq(&self, other: &MemoryRecord) -> bool {
+        self.hash == other.hash
+    }
+}
+
+use serde::{Serialize, Serializer};
+
+impl Serialize for MemoryRecord {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        use crate::record::RecordExt;
+        self.serde_serialize(serializer)
+    }
+}
+
+impl Record for MemoryRecord {
+    type Read = Cursor<Vec<u8>>;
+    type Str = String;
+    type Hash = Vec<u8>;
+    type Iter = MemoryRecordFileIterator;
+
+    fn hash(&self) -> Self::Hash {
+        self.hash.clone()
+    }
+
+    fn encoded_hash(&self) -> Self::Str {
+        self.encoding.encode(&self.hash)
+    }
+
+    fn file_iter(&self) -> Self::Iter {
+        MemoryRecordFileIterator {
+            files: self.files.clone(),
+            index: 0,
+        }
+    }
+
+    #[cfg(feature = "deprecated-item-api")]
+    fn item_id(&self) -> Self::Str {
+        String::new()
+    }
+}
+
+/// An iterator over files in [`MemoryRecord`]
+///
+/// [`MemoryRecord`]: struct.MemoryRecord.html
+pub struct MemoryRecordFileIterator {
+    files: MemoryFiles,
+    index: usize,
+}
+
+impl Iterator for MemoryRecordFileIterator {
+    type Item = (String, Cursor<Vec<u8>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.files.get(self.index)
+            .map(|(name, contents)| (name.clone(), Cursor::new(contents.clone())));
+        self.index += 1;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Repository;
+    use crate::repository::Config;
+    use crate::record::{RecordContainer, RecordOwningContainer};
+    use assert_matches::assert_matches;
+
+    fn repository() -> Repository<crate::repository::ModuleDirectory<std::path::PathBuf>, MemoryRecordStore> {
+        Repository::in_memory(Config::default())
+    }
+
+    #[test]
+    fn new_record() {
+        let repo = repository();
+        let record = repo.new_record(vec![("test", &b"hello"[..])].into_iter(), true).unwrap();
+        let mut files: Vec<_> = record.file_iter().collect();
+        assert_eq!(files.len(), 1);
+        let (name, mut file) = files.pop().unwrap();
+        assert_eq!(name, "test");
+        use std::io::Read;
+        let mut string = String::new();
+        assert!(file.read_to_string(&mut string).is_ok());
+        assert_eq!(string, "hello");
+        let mut records: Vec<MemoryRecord> = repo.record_iter().unwrap().flat_map(|v| v).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records.pop().unwrap().hash(), record.hash());
+        assert_eq!(repo.record(record.encoded_hash()).unwrap().hash(), record.hash());
+    }
+
+    #[test]
+    fn same_hash_as_directory_store() {
+        let repo = repository();
+        let mut tmp = tempdir::TempDir::new("sit").unwrap().into_path();
+        tmp.push(".sit");
+        let dir_repo = Repository::new(&tmp).unwrap();
+        let record = repo.new_record(vec![("test", &b"hello"[..]), ("z/a", &b"world"[..])].into_iter(), false).unwrap();
+        let dir_record = dir_repo.new_record(vec![("test", &b"hello"[..]), ("z/a", &b"world"[..])].into_iter(), false).unwrap();
+        assert_eq!(record.hash(), dir_record.hash());
+    }
+
+    #[test]
+    fn record_files_path() {
+        let repo = repository();
+        assert_matches!(repo.new_record(vec![(".", &b"hello"[..])].into_iter(), false), Err(Error::IoError(_)));
+        assert_matches!(repo.new_record(vec![("../test", &b"hello"[..])].into_iter(), false), Err(Error::PathPrefixError));
+        let record = repo.new_record(vec![("/test2", &b"hello"[..])].into_iter(), false).unwrap();
+        assert_eq!(record.file_iter().next().unwrap().0, "test2");
+    }
+
+    #[test]
+    fn record_ordering() {
+        let repo = repository();
+        let record1 = repo.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        let record2 = repo.new_record(vec![("test", &[2u8][..])].into_iter(), false).unwrap();
+        let record3 = repo.new_record(vec![("test", &[3u8][..])].into_iter(), true).unwrap();
+        let record4 = repo.new_record(vec![("test", &[4u8][..])].into_iter(), false).unwrap();
+        let record5 = repo.new_record(vec![("test", &[5u8][..])].into_iter(), true).unwrap();
+
+        let mut records: Vec<_> = repo.record_iter().unwrap().collect();
+        let row_3 = records.pop().unwrap();
+        let row_2 = records.pop().unwrap();
+        let row_1 = records.pop().unwrap();
+        assert_eq!(records.len(), 0);
+
+        assert_eq!(row_1.len(), 3);
+        assert!(row_1.iter().any(|r| r == &record1));
+        assert!(row_1.iter().any(|r| r == &record2));
+        assert!(row_1.iter().any(|r| r == &record4));
+
+        assert_eq!(row_2, vec![record3]);
+        assert_eq!(row_3, vec![record5]);
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let repo = repository();
+        let threads: Vec<_> = (0..8u8).map(|i| {
+            let repo = repo.clone();
+            std::thread::spawn(move || {
+                for j in 0..10u8 {
+                    repo.new_record(vec![("test", &[i, j][..])].into_iter(), true).unwrap();
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        // every record is linked to the previous one
+        let generations: Vec<_> = repo.record_iter().unwrap().collect();
+        assert_eq!(generations.len(), 80);
+        assert!(generations.iter().all(|records| records.len() == 1));
+    }
+
+    #[test]
+    fn shared_between_clones() {
+        let repo = repository();
+        let clone = repo.clone();
+        let record = repo.new_record(vec![("test", &[1u8][..])].into_iter(), false).unwrap();
+        assert_eq!(clone.record(record.encoded_hash()).unwrap(), record);
+    }
+
+}
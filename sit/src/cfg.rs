@@ -2,6 +2,9 @@
 #[cfg(feature = "git")]
 use std::path::PathBuf;
 use serde_derive::{Serialize, Deserialize};
+use sit_core::Record;
+use sit_core::reducers::duktape::{DuktapeReducer, Error, ErrorPolicy, Limits, SourceFiles};
+use std::time::Duration;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Author {
@@ -76,6 +79,51 @@ impl Signing {
     }
 }
 
+/// Reducers' settings
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Reducers {
+    /// Maximum execution time, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Maximum number of executed instructions per invocation (exceeding it fails the invocation)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<u64>,
+    /// Maximum heap size, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heap_size: Option<usize>,
+    /// What to do when a reducer fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<ErrorPolicy>,
+    /// Load native reducers (they run with full privileges and come with
+    /// repositories, so they are never loaded unless enabled)
+    #[serde(default)]
+    pub native: bool,
+}
+
+impl Reducers {
+    pub fn is_none(&self) -> bool {
+        self.timeout.is_none() && self.instructions.is_none() && self.heap_size.is_none() && self.on_error.is_none() &&
+            !self.native
+    }
+
+    /// Creates a reducer with these settings
+    pub fn reducer<R: Record, SF: SourceFiles>(&self, source_files: SF) -> Result<DuktapeReducer<R>, Error> {
+        let mut reducer = DuktapeReducer::with_limits(source_files, self.limits())?;
+        if let Some(policy) = self.on_error {
+            reducer.set_error_policy(policy);
+        }
+        Ok(reducer)
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            timeout: self.timeout.map(Duration::from_millis),
+            instructions: self.instructions,
+            heap_size: self.heap_size,
+        }
+    }
+}
+
 #[derive(Serialize, Clone, Deserialize)]
 pub struct ExtensibleConfiguration<T> {
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -86,6 +134,8 @@ pub struct ExtensibleConfiguration<T> {
     pub records: JMESPathConfig,
     #[serde(default, skip_serializing_if = "Signing::is_none")]
     pub signing: Signing,
+    #[serde(default, skip_serializing_if = "Reducers::is_none")]
+    pub reducers: Reducers,
     #[serde(default, flatten)]
     pub extra: T,
 }
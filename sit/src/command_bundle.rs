@@ -0,0 +1,64 @@
+use std::fs;
+use std::io::Read;
+use chrono::prelude::*;
+use clap::ArgMatches;
+use sit_core::{Repository, Record, record::RecordContainer, bundle::{Bundle, BundleWriter}};
+use crate::command_log::parse_date;
+use crate::command_sync::print_report;
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>) -> i32 {
+    if let Some(matches) = matches.subcommand_matches("create") {
+        return match matches.values_of("root") {
+            Some(roots) => create(matches, repo, &repo.fixed_roots(roots)),
+            None => create(matches, repo, repo),
+        };
+    }
+    if let Some(matches) = matches.subcommand_matches("apply") {
+        let bundle = match Bundle::open(matches.value_of("bundle").unwrap()) {
+            Ok(bundle) => bundle,
+            Err(err) => {
+                eprintln!("Can't open bundle: {:?}", err);
+                return 1;
+            },
+        };
+        return match repo.apply_bundle(&bundle) {
+            Ok(report) => if print_report(report) { 0 } else { 1 },
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                1
+            },
+        };
+    }
+    0
+}
+
+/// Returns record's timestamp (if it has a valid one)
+fn timestamp<R: Record>(record: &R) -> Option<DateTime<Utc>> {
+    let (_, mut reader) = record.file_iter().find(|(name, _)| name.as_ref() == ".timestamp")?;
+    let mut s = String::new();
+    reader.read_to_string(&mut s).ok()?;
+    s.trim().parse().ok()
+}
+
+fn create<MI, RC: RecordContainer>(matches: &ArgMatches, repo: &Repository<MI>, container: &RC) -> i32 {
+    let since = matches.value_of("since").map(|v| parse_date(v).unwrap());
+    let mut writer = BundleWriter::new(repo.config().hashing_algorithm(), repo.config().encoding())
+        .expect("can't start a bundle");
+    for record in container.record_iter().expect("can't list records").flat_map(|records| records) {
+        if let Some(since) = since {
+            if timestamp(&record).map(|t| t < since).unwrap_or(true) {
+                continue;
+            }
+        }
+        writer.add(&record).expect("can't add record to the bundle");
+        println!("{}", record.encoded_hash().as_ref());
+    }
+    let file = fs::File::create(matches.value_of("bundle").unwrap()).expect("can't create bundle file");
+    match writer.finish(file) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            1
+        },
+    }
+}
@@ -0,0 +1,23 @@
+use clap::ArgMatches;
+use sit_core::{Repository, repository::GcReport};
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>) -> i32 {
+    match repo.gc(matches.is_present("remove")) {
+        Ok(GcReport { temporary, empty, orphaned_links }) => {
+            for path in temporary {
+                println!("temporary {}", path.display());
+            }
+            for path in empty {
+                println!("empty {}", path.display());
+            }
+            for (record, parent) in orphaned_links {
+                println!("orphaned {} {}", record, parent);
+            }
+            0
+        },
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            1
+        },
+    }
+}
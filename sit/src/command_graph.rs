@@ -0,0 +1,46 @@
+use clap::ArgMatches;
+use sit_core::{Repository, Record, record::RecordContainer};
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>) -> i32 {
+    let graph = repo.graph().expect("can't build the graph of records");
+    let records = match matches.value_of("query") {
+        None => {
+            // print every record followed by its parents
+            for record in graph.records() {
+                let hash = record.encoded_hash();
+                let parents: Vec<_> = graph.parents(&hash).iter().map(|r| r.encoded_hash()).collect();
+                if parents.is_empty() {
+                    println!("{}", hash);
+                } else {
+                    println!("{} {}", hash, parents.join(" "));
+                }
+            }
+            return 0;
+        },
+        Some("heads") => graph.heads(),
+        Some(query) => {
+            let record = match matches.value_of("record") {
+                None => {
+                    eprintln!("Record is required for {}", query);
+                    return 1;
+                },
+                Some(record) => record,
+            };
+            if graph.record(record).is_none() {
+                eprintln!("Record {} not found", record);
+                return 1;
+            }
+            match query {
+                "parents" => graph.parents(record),
+                "children" => graph.children(record),
+                "ancestors" => graph.ancestors(record),
+                "descendants" => graph.descendants(record),
+                _ => unreachable!(),
+            }
+        },
+    };
+    for record in records {
+        println!("{}", record.encoded_hash());
+    }
+    0
+}
@@ -34,7 +34,7 @@ pub fn command<MI: Send + Sync>(matches: &ArgMatches, repo: &Repository<MI>, con
     let query = jmespath::compile(&query_expr).expect("can't compile query expression");
 
     let tl_reducer : ThreadLocal<RefCell<DuktapeReducer<sit_core::repository::Record>>> = ThreadLocal::new();
-    let reducer = Arc::new(Mutex::new(DuktapeReducer::new(repo).unwrap()));
+    let reducer = Arc::new(Mutex::new(config.reducers.reducer(repo).unwrap()));
 
     items.into_par_iter()
         .map(|item| {
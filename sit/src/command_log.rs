@@ -0,0 +1,259 @@
+use std::io::Read;
+use chrono::prelude::*;
+use clap::ArgMatches;
+use sit_core::{Repository, Record, record::RecordContainer};
+
+/// Default `--format` template
+pub const DEFAULT_FORMAT: &str = "{hash}\nType: {types}\nAuthor: {authors}\nDate: {timestamp}\n";
+
+/// Parses `--since`/`--until` arguments (RFC 3339 timestamps or YYYY-MM-DD dates)
+pub fn parse_date(s: &str) -> Result<DateTime<Utc>, String> {
+    s.parse::<DateTime<Utc>>()
+        .or_else(|_| NaiveDate::parse_from_str(s, "%Y-%m-%d").map(|d| DateTime::from_utc(d.and_hms(0, 0, 0), Utc)))
+        .map_err(|e| format!("Invalid date {}: {}", s, e))
+}
+
+/// Record details rendered by `sit log`
+struct Details {
+    hash: String,
+    parents: Vec<String>,
+    types: Vec<String>,
+    authors: Option<String>,
+    timestamp: Option<String>,
+}
+
+impl Details {
+    fn new<R: Record>(record: &R) -> Self {
+        let mut parents = record.parents();
+        parents.sort();
+        let mut details = Details {
+            hash: record.encoded_hash().as_ref().into(),
+            parents,
+            types: vec![],
+            authors: None,
+            timestamp: None,
+        };
+        for (name, mut reader) in record.file_iter() {
+            let name = name.as_ref();
+            if name.starts_with(".type/") {
+                details.types.push(name[6..].into());
+            } else if name == ".authors" || name == ".timestamp" {
+                let mut s = String::new();
+                if reader.read_to_string(&mut s).is_ok() {
+                    let s = Some(String::from(s.trim()));
+                    if name == ".authors" {
+                        details.authors = s;
+                    } else {
+                        details.timestamp = s;
+                    }
+                }
+            }
+        }
+        details.types.sort();
+        details
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.timestamp.as_ref().and_then(|t| t.parse().ok())
+    }
+
+    /// Renders the template, substituting `{hash}`, `{parents}`, `{types}`,
+    /// `{authors}` and `{timestamp}` (unknown placeholders are left intact)
+    fn render(&self, template: &str) -> String {
+        let mut result = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let value = rest.find('}').and_then(|end| {
+                let value = match &rest[1..end] {
+                    "hash" => self.hash.clone(),
+                    "parents" => self.parents.join(" "),
+                    "types" => self.types.join(", "),
+                    "authors" => self.authors.clone().unwrap_or_default(),
+                    "timestamp" => self.timestamp.clone().unwrap_or_default(),
+                    _ => return None,
+                };
+                Some((value, end))
+            });
+            match value {
+                Some((value, end)) => {
+                    result.push_str(&value);
+                    rest = &rest[end + 1..];
+                },
+                None => {
+                    result.push('{');
+                    rest = &rest[1..];
+                },
+            }
+        }
+        result.push_str(rest);
+        result
+    }
+}
+
+/// Selects records to be shown
+struct Filter {
+    types: Vec<String>,
+    authors: Vec<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+impl Filter {
+    fn matches(&self, details: &Details) -> bool {
+        if !self.types.is_empty() && !details.types.iter().any(|t| self.types.contains(t)) {
+            return false;
+        }
+        if !self.authors.is_empty() {
+            let authors = details.authors.clone().unwrap_or_default().to_lowercase();
+            if !self.authors.iter().any(|a| authors.contains(&a.to_lowercase())) {
+                return false;
+            }
+        }
+        if self.since.is_some() || self.until.is_some() {
+            match details.timestamp() {
+                None => return false,
+                Some(timestamp) => {
+                    if self.since.map(|since| timestamp < since).unwrap_or(false) ||
+                       self.until.map(|until| timestamp > until).unwrap_or(false) {
+                        return false;
+                    }
+                },
+            }
+        }
+        true
+    }
+}
+
+/// Lane of the graph, occupied by a record until all of its children are shown
+struct Lane {
+    hash: String,
+    children: usize,
+}
+
+/// Renders lanes, two characters per lane
+fn render_lanes(lanes: &[Option<Lane>], width: usize) -> Vec<char> {
+    let mut line = vec![' '; width * 2];
+    for (i, lane) in lanes.iter().enumerate() {
+        if lane.is_some() {
+            line[i * 2] = '|';
+        }
+    }
+    line
+}
+
+/// Draws an edge between two lanes
+fn render_edge(line: &mut [char], from: usize, to: usize) {
+    if from < to {
+        for pos in from * 2 + 1..to * 2 - 1 {
+            if line[pos] == ' ' {
+                line[pos] = '-';
+            }
+        }
+        line[to * 2 - 1] = '\\';
+    } else {
+        line[to * 2 + 1] = '/';
+        for pos in to * 2 + 2..from * 2 {
+            if line[pos] == ' ' {
+                line[pos] = '-';
+            }
+        }
+    }
+}
+
+fn print_line(prefix: &[char], line: &str) {
+    let line = format!("{}{}", prefix.iter().collect::<String>(), line);
+    println!("{}", line.trim_end());
+}
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>) -> i32 {
+    match matches.values_of("root") {
+        Some(roots) => log(matches, &repo.fixed_roots(roots)),
+        None => log(matches, repo),
+    }
+}
+
+fn log<RC: RecordContainer>(matches: &ArgMatches, container: &RC) -> i32 where RC::Error: std::fmt::Debug {
+    let graph = container.graph().expect("can't build the graph of records");
+    let template = matches.value_of("format").unwrap_or(DEFAULT_FORMAT);
+    let filter = Filter {
+        types: matches.values_of("type").map(|v| v.map(String::from).collect()).unwrap_or_default(),
+        authors: matches.values_of("author").map(|v| v.map(String::from).collect()).unwrap_or_default(),
+        since: matches.value_of("since").map(|v| parse_date(v).unwrap()),
+        until: matches.value_of("until").map(|v| parse_date(v).unwrap()),
+    };
+
+    let mut lanes: Vec<Option<Lane>> = vec![];
+    for record in graph.records() {
+        let hash = record.encoded_hash();
+        let hash = hash.as_ref();
+        let parents: Vec<String> = graph.parents(hash).iter().map(|r| r.encoded_hash().as_ref().into()).collect();
+        let children = graph.children(hash).len();
+
+        let parent_lanes: Vec<usize> = lanes.iter().enumerate()
+            .filter_map(|(i, lane)| match lane {
+                Some(lane) if parents.contains(&lane.hash) => Some(i),
+                _ => None,
+            })
+            .collect();
+        // continue the lane of a parent this record is the last child of,
+        // otherwise branch off into a new lane
+        let column = parent_lanes.iter().cloned()
+            .find(|&i| lanes[i].as_ref().unwrap().children == 1)
+            .unwrap_or_else(|| lanes.iter().position(Option::is_none).unwrap_or(lanes.len()));
+        if column == lanes.len() {
+            lanes.push(None);
+        }
+
+        let details = Details::new(record);
+        let visible = filter.matches(&details);
+
+        if visible && parent_lanes.iter().any(|&i| i != column) {
+            let mut line = render_lanes(&lanes, lanes.len());
+            for &i in parent_lanes.iter().filter(|&&i| i != column) {
+                // lanes of parents that have no other children end here
+                if lanes[i].as_ref().unwrap().children == 1 {
+                    line[i * 2] = ' ';
+                }
+                render_edge(&mut line, i, column);
+            }
+            print_line(&line, "");
+        }
+
+        let width = lanes.len();
+        for &i in parent_lanes.iter() {
+            let closed = {
+                let lane = lanes[i].as_mut().unwrap();
+                lane.children -= 1;
+                lane.children == 0
+            };
+            if closed {
+                lanes[i] = None;
+            }
+        }
+        lanes[column] = if children > 0 {
+            Some(Lane { hash: hash.into(), children })
+        } else {
+            None
+        };
+
+        if visible {
+            let continuation = render_lanes(&lanes, width);
+            let mut commit_line = continuation.clone();
+            commit_line[column * 2] = '*';
+            let rendered = details.render(template);
+            let mut lines = rendered.split('\n');
+            print_line(&commit_line, lines.next().unwrap_or(""));
+            for line in lines {
+                print_line(&continuation, line);
+            }
+        }
+
+        while let Some(&None) = lanes.last() {
+            lanes.pop();
+        }
+    }
+    0
+}
+
@@ -0,0 +1,15 @@
+use sit_core::Repository;
+
+pub fn command<MI>(repo: Repository<MI>) -> i32 {
+    match repo.pack() {
+        Ok(Some(pack)) => {
+            println!("{}", pack.to_str().unwrap());
+            0
+        },
+        Ok(None) => 0,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            1
+        },
+    }
+}
@@ -1,13 +1,14 @@
 use clap::ArgMatches;
-use sit_core::{Repository, Record, record::RecordContainer, record::OrderedFiles, path::HasPath};
+use sit_core::{Repository, Record, record::RecordContainer, record::OrderedFiles, path::HasPath, redaction::RedactedRecordContainer};
 use crate::cfg::Configuration;
 use serde_json;
 use super::get_named_expression;
 use jmespath;
 use super::gnupg;
-use serde;
 
 pub fn command<MI>(matches: &ArgMatches, repo: Repository<MI>, config: Configuration) -> i32 {
+    // redacted files are never shown
+    let redacted = repo.redacted().expect("can't read redactions");
     #[cfg(feature = "deprecated-items")] {
         if matches.is_present("id") {
             let id = matches.value_of("id").unwrap();
@@ -17,16 +18,17 @@ pub fn command<MI>(matches: &ArgMatches, repo: Repository<MI>, config: Configura
                     return 1;
                 },
                 Some(item) => {
+                    let item = RedactedRecordContainer::new(&item, redacted.redactions().clone());
                     return list(matches, &item, &repo, config);
                 }
             }
         }
     }
-    list(matches, &repo, &repo, config)
+    list(matches, &redacted, &repo, config)
 }
 
-fn list<R: RecordContainer, MI>(matches: &ArgMatches, iter: &R, repo: &Repository<MI>, config: Configuration) -> i32
-    where <R as RecordContainer>::Record: serde::Serialize + HasPath {
+fn list<RC: RecordContainer, MI>(matches: &ArgMatches, iter: &RedactedRecordContainer<RC>, repo: &Repository<MI>, config: Configuration) -> i32
+    where RC::Record: HasPath {
     let records = iter.record_iter().expect("can't list records");
 
     let filter_expr = matches.value_of("named-filter")
@@ -53,9 +55,18 @@ fn list<R: RecordContainer, MI>(matches: &ArgMatches, iter: &R, repo: &Repositor
             // ...and back so that we can treat the record as a plain JSON
             let mut json: serde_json::Value = serde_json::from_str(&json).unwrap();
             if let serde_json::Value::Object(ref mut map) = json {
-                let verify = matches.is_present("verify") && rec.path().join(".signature").is_file();
-
-                if verify {
+                // signatures cover all files of the record, including redacted ones
+                let signature = if matches.is_present("verify") {
+                    rec.record().file_iter().find(|(name, _)| name.as_ref() == ".signature")
+                } else {
+                    None
+                };
+
+                if let Some((_, mut signature)) = signature {
+                    // signature is copied to a temporary file as the record
+                    // might be packed and therefore not have it on the file system
+                    let mut signature_file = tempfile::NamedTempFile::new().expect("can't create a temporary file");
+                    ::std::io::copy(&mut signature, &mut signature_file).expect("can't read signature");
                     let program = gnupg(matches, &config).expect("can't find GnuPG");
                     let mut command = ::std::process::Command::new(program);
 
@@ -64,13 +75,13 @@ fn list<R: RecordContainer, MI>(matches: &ArgMatches, iter: &R, repo: &Repositor
                         .stdout(::std::process::Stdio::piped())
                         .stderr(::std::process::Stdio::piped())
                         .arg("--verify")
-                        .arg(rec.path().join(".signature"))
+                        .arg(signature_file.path())
                         .arg("-");
 
                     let mut child = command.spawn().expect("failed spawning gnupg");
 
                     {
-                        let files: OrderedFiles<_> = rec.file_iter().into();
+                        let files: OrderedFiles<_> = rec.record().file_iter().into();
                         let files = files - ".signature";
                         let mut hasher = repo.config().hashing_algorithm().hasher();
                         files.hash(&mut *hasher).expect("failed hashing files");
@@ -0,0 +1,31 @@
+use chrono::prelude::*;
+use clap::ArgMatches;
+use sit_core::{Repository, Record, record::RecordOwningContainer, redaction};
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>) -> i32 {
+    let hash = matches.value_of("record").unwrap();
+    let record = match repo.record(hash) {
+        Some(record) => record,
+        None => {
+            eprintln!("Record {} not found", hash);
+            return 1;
+        },
+    };
+    let files: Vec<&str> = matches.values_of("files").unwrap().collect();
+    for file in files.iter() {
+        if file.starts_with(".prev/") {
+            eprintln!("Links to previous records can't be redacted");
+            return 1;
+        }
+        if !record.file_iter().any(|(name, _)| name == *file) {
+            eprintln!("Record {} has no file {}", hash, file);
+            return 1;
+        }
+    }
+    let mut redaction_files = redaction::redaction_files(hash, files);
+    redaction_files.push((String::from(".timestamp"), format!("{:?}", Utc::now()).into_bytes()));
+    let redaction = repo.new_record(redaction_files.iter().map(|(name, content)| (name.as_str(), &content[..])), true)
+        .expect("can't create a record");
+    println!("{}", redaction.encoded_hash());
+    0
+}
@@ -1,13 +1,20 @@
 use clap::{ArgMatches, Values};
-use sit_core::{self, Repository, record::RecordContainerReduction, repository, 
-               reducers::duktape, path::{HasPath, ResolvePath}};
+use sit_core::{self, Repository, Record, record::{RecordContainer, RecordContainerReduction}, repository,
+               reducers::{Reducer, ChainedReducer, duktape, native::NativeReducer, cache::ReductionCache}, path::{HasPath, ResolvePath},
+               hash::HashingAlgorithm,
+               redaction::{Redactions, RedactedRecord, RedactedRecordContainer}};
 use crate::cfg::Configuration;
 use serde_json;
 use super::get_named_expression;
 use jmespath;
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use thread_local::ThreadLocal;
 
-pub fn command<MI>(matches: &ArgMatches, repo: Repository<MI>, config: Configuration) -> i32
+pub fn command<MI: Send + Sync>(matches: &ArgMatches, repo: Repository<MI>, config: Configuration) -> i32
     where MI: repository::ModuleIterator<PathBuf, repository::Error> {
     if let Some(vals) = matches.values_of_os("reducer") {
         let reducers_path = repo.path().join("reducers");
@@ -19,18 +26,31 @@ pub fn command<MI>(matches: &ArgMatches, repo: Repository<MI>, config: Configura
                 dir
             } else {
                 p
-            });
+            }).collect::<Vec<_>>();
         command_impl(matches, &repo, config, reducers)
     } else {
         command_impl(matches, &repo, config, &repo)
     }
 }
 
-fn command_impl<MI, SF>(matches: &ArgMatches, repo: &Repository<MI>, config: Configuration, source_files: SF) -> i32
-    where MI: repository::ModuleIterator<PathBuf, repository::Error>, SF: duktape::SourceFiles {
+fn command_impl<MI: Send + Sync, SF>(matches: &ArgMatches, repo: &Repository<MI>, config: Configuration, source_files: SF) -> i32
+    where MI: repository::ModuleIterator<PathBuf, repository::Error>, SF: duktape::SourceFiles + Clone + Sync {
 
     let fixed_roots = matches.values_of("root");
+    let batch = matches.values_of("batch");
     let state = matches.value_of("state").map(serde_json::from_str).filter(Result::is_ok).map(Result::unwrap);
+    let debug = matches.is_present("debug");
+    // cached records are not reduced again, so their output would be missing
+    let cache = if matches.is_present("no-cache") || debug {
+        None
+    } else {
+        let reducers = reducers_hash(source_files.clone(), &config, repo.config().hashing_algorithm())
+            .expect("can't hash reducers");
+        Some((repo.reduction_cache(), reducers))
+    };
+
+    // redacted files are never reduced
+    let redactions = repo.redacted().expect("can't read redactions").redactions().clone();
 
     #[cfg(feature = "deprecated-items")] {
         if let Some(id) = matches.value_of("id") {
@@ -46,8 +66,13 @@ fn command_impl<MI, SF>(matches: &ArgMatches, repo: &Repository<MI>, config: Con
                         .or_else(|| matches.value_of("query").or_else(|| Some("@")).map(String::from))
                         .unwrap();
 
-                    reduce(&query_expr, &item, source_files, fixed_roots, state);
-                    return 0;
+                    return match reduce(&query_expr, &item, &redactions, &format!("item:{}", id), source_files, &config, fixed_roots, batch, state, cache, debug) {
+                        Ok(()) => 0,
+                        Err(err) => {
+                            eprintln!("{}", err);
+                            1
+                        },
+                    };
                 }
             }
         }
@@ -59,24 +84,52 @@ fn command_impl<MI, SF>(matches: &ArgMatches, repo: &Repository<MI>, config: Con
         .or_else(|| matches.value_of("query").or_else(|| Some("@")).map(String::from))
         .unwrap();
 
-    reduce(&query_expr, repo, source_files, fixed_roots, state);
-
-    return 0;
+    match reduce(&query_expr, repo, &redactions, "", source_files, &config, fixed_roots, batch, state, cache, debug) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        },
+    }
 }
 
-fn reduce<RCR: RecordContainerReduction<Record = repository::Record>, SF: duktape::SourceFiles>
-    (query_expr: &str, container: &RCR, source_files: SF, roots: Option<Values>, state: Option<serde_json::Value>) {
-    let mut reducer = sit_core::reducers::duktape::DuktapeReducer::new(source_files).unwrap();
+fn reduce<RCR: RecordContainerReduction<Record = repository::Record> + Sync, SF: duktape::SourceFiles + Clone + Sync>
+    (query_expr: &str, container: &RCR, redactions: &Redactions, scope: &str, source_files: SF, config: &Configuration,
+     roots: Option<Values>, batch: Option<Values>, state: Option<serde_json::Value>, cache: Option<(ReductionCache, Vec<u8>)>, debug: bool) -> Result<(), String> {
+    let logger: Option<duktape::Logger> = if debug {
+        Some(Arc::new(|entry: &duktape::LogEntry| eprintln!("{}", serde_json::to_string(entry).unwrap())))
+    } else {
+        None
+    };
     let query = jmespath::compile(&query_expr).expect("can't compile query expression");
     let state = container.initialize_state(match state {
         None => Default::default(),
         Some(s) => s.as_object().unwrap().to_owned(),
     });
+    let scope = redactions_scope(scope, redactions);
+    let container = RedactedRecordContainer::new(container, redactions.clone());
+    if let Some(batch) = batch {
+        let root_sets = batch.map(|roots| roots.split(',').filter(|root| !root.is_empty()).map(String::from).collect()).collect();
+        let workers = Workers::new(source_files, config, logger)?;
+        let results = reduce_root_sets(root_sets, |roots, key| workers.with(|reducer| {
+            let scope = format!("{}roots:{}", scope, key);
+            let container = container.fixed_roots(roots.to_vec());
+            let result = reduce_with_cache(&container, reducer, &scope, state.clone(), &cache);
+            let data = jmespath::Variable::from(serde_json::Value::Object(result));
+            serde_json::to_value(&*query.search(&data).unwrap()).unwrap()
+        }));
+        println!("{}", serde_json::to_string_pretty(&results).unwrap());
+        return Ok(());
+    }
+    let mut reducer = reducer(source_files, config, logger)?;
     let result = match roots {
-        None => container.reduce_with_reducer_and_state(&mut reducer, state).expect("can't reduce"),
+        None => reduce_with_cache(&container, &mut reducer, &scope, state, &cache),
         Some(fixed_roots) => {
-            let container = container.fixed_roots(fixed_roots);
-            container.reduce_with_reducer_and_state(&mut reducer, state).expect("can't reduce")
+            let mut roots: Vec<_> = fixed_roots.collect();
+            roots.sort();
+            let scope = format!("{}roots:{}", scope, roots.join(","));
+            let container = container.fixed_roots(roots);
+            reduce_with_cache(&container, &mut reducer, &scope, state, &cache)
         },
     };
     let data = jmespath::Variable::from(serde_json::Value::Object(result));
@@ -86,4 +139,181 @@ fn reduce<RCR: RecordContainerReduction<Record = repository::Record>, SF: duktap
     } else {
         println!("{}", serde_json::to_string_pretty(&view).unwrap());
     }
+    Ok(())
+}
+
+/// Hashes reducers along with their settings that affect reduced states
+///
+/// The hash identifies reduction cache entries.
+pub fn reducers_hash<SF>(source_files: SF, config: &Configuration, hashing_algorithm: &HashingAlgorithm) -> Result<Vec<u8>, String>
+    where SF: duktape::SourceFiles {
+    let reducers = duktape::source_files_hash(source_files, hashing_algorithm)
+        .map_err(|e| format!("can't hash reducers: {:?}", e))?;
+    // execution limits decide which records fail, error policy decides what happens next
+    if config.reducers.is_none() {
+        return Ok(reducers);
+    }
+    let mut hasher = hashing_algorithm.hasher();
+    hasher.process(&reducers);
+    hasher.process(serde_json::to_string(&config.reducers).unwrap().as_bytes());
+    Ok(hasher.result_box())
+}
+
+/// Extends a reduction cache scope with redactions
+///
+/// Cached states must not be reused once redactions change.
+pub fn redactions_scope(scope: &str, redactions: &Redactions) -> String {
+    if redactions.is_empty() {
+        String::from(scope)
+    } else {
+        format!("{}redactions:{}", scope, redactions.fingerprint())
+    }
+}
+
+/// Loads JavaScript reducers, followed by WebAssembly and native ones (if enabled)
+pub fn reducer<R, SF>(source_files: SF, config: &Configuration, logger: Option<duktape::Logger>)
+    -> Result<impl Reducer<State = serde_json::Map<String, serde_json::Value>, Item = R>, String>
+    where R: Record + HasPath + 'static, SF: duktape::SourceFiles + Clone {
+    Ok(javascript_reducer(source_files.clone(), config, logger)?.chain(compiled_reducers(source_files, config)?))
+}
+
+/// Loads JavaScript reducers
+pub fn javascript_reducer<R, SF>(source_files: SF, config: &Configuration, logger: Option<duktape::Logger>) -> Result<duktape::DuktapeReducer<R>, String>
+    where R: Record, SF: duktape::SourceFiles {
+    let mut reducer = config.reducers.reducer(source_files).map_err(|e| format!("can't load reducers: {:?}", e))?;
+    if let Some(logger) = logger {
+        reducer.set_logger(logger);
+    }
+    Ok(reducer)
+}
+
+/// Loads WebAssembly and native reducers (if enabled)
+///
+/// Native reducers are only loaded if `reducers.native` is enabled in the configuration.
+#[cfg_attr(not(any(feature = "wasm-reducers", feature = "native-reducers")), allow(unused_mut, unused_variables))]
+pub fn compiled_reducers<R, SF>(source_files: SF, config: &Configuration) -> Result<NativeReducer<R>, String>
+    where R: Record + 'static, SF: duktape::SourceFiles + Clone {
+    let mut reducer = NativeReducer::new();
+    #[cfg(feature = "wasm-reducers")] {
+        let mut wasm = sit_core::reducers::wasm::WasmReducer::new(source_files.clone())
+            .map_err(|e| format!("can't load WebAssembly reducers: {:?}", e))?;
+        if let Some(policy) = config.reducers.on_error {
+            wasm.set_error_policy(policy);
+        }
+        reducer.register(wasm);
+    }
+    #[cfg(feature = "native-reducers")] {
+        if config.reducers.native {
+            reducer.register(NativeReducer::load(source_files).map_err(|e| format!("can't load native reducers: {:?}", e))?);
+        }
+    }
+    Ok(reducer)
+}
+
+/// Reducers of a thread
+type Worker<R> = (duktape::DuktapeReducer<R>, NativeReducer<R>);
+
+/// Reducers for threads reducing in parallel
+///
+/// JavaScript reducers are loaded once and cloned for every thread that
+/// uses them, WebAssembly and native reducers are loaded by every such thread.
+/// Threads keep their reducers for as long as `Workers` live.
+pub struct Workers<R: Record> {
+    source_files: Vec<PathBuf>,
+    config: Configuration,
+    prototype: Mutex<duktape::DuktapeReducer<R>>,
+    workers: ThreadLocal<RefCell<Worker<R>>>,
+}
+
+impl<R: Record + HasPath + 'static> Workers<R> {
+    pub fn new<SF>(source_files: SF, config: &Configuration, logger: Option<duktape::Logger>) -> Result<Self, String>
+        where SF: duktape::SourceFiles + Clone {
+        let prototype = javascript_reducer(source_files.clone(), config, logger)?;
+        let source_files: Vec<_> = source_files.source_files().map_err(|e| format!("can't load reducers: {:?}", e))?.collect();
+        // fail early rather than in every thread
+        compiled_reducers::<R, _>(source_files.clone(), config)?;
+        Ok(Workers {
+            source_files,
+            config: config.clone(),
+            prototype: Mutex::new(prototype),
+            workers: ThreadLocal::new(),
+        })
+    }
+
+    /// Calls `f` with reducers of the current thread, reset to their initial state
+    pub fn with<T, F>(&self, f: F) -> T
+        where F: FnOnce(&mut ChainedReducer<&mut duktape::DuktapeReducer<R>, &mut NativeReducer<R>>) -> T {
+        let mut worker = self.workers.get_or(|| {
+            let compiled = compiled_reducers(self.source_files.clone(), &self.config).expect("can't load reducers");
+            Box::new(RefCell::new((self.prototype.lock().unwrap().clone(), compiled)))
+        }).borrow_mut();
+        let (ref mut javascript, ref mut compiled) = *worker;
+        javascript.reset_state();
+        compiled.reset_state();
+        f(&mut javascript.chain(compiled))
+    }
+}
+
+/// Reducers of a long-lived process (such as `sit web`)
+///
+/// Reducers are reloaded when their source files change.
+pub struct ReducerPool<R: Record> {
+    config: Configuration,
+    current: Mutex<Option<(Vec<u8>, Arc<Workers<R>>)>>,
+}
+
+impl<R: Record + HasPath + 'static> ReducerPool<R> {
+    pub fn new(config: Configuration) -> Self {
+        ReducerPool {
+            config,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Returns reducers loaded from `source_files`, along with their hash
+    pub fn workers<SF>(&self, source_files: SF, hashing_algorithm: &HashingAlgorithm) -> Result<(Vec<u8>, Arc<Workers<R>>), String>
+        where SF: duktape::SourceFiles + Clone {
+        let reducers = reducers_hash(source_files.clone(), &self.config, hashing_algorithm)?;
+        let mut current = self.current.lock().unwrap();
+        if let Some((ref hash, ref workers)) = *current {
+            if hash == &reducers {
+                return Ok((reducers, workers.clone()));
+            }
+        }
+        let workers = Arc::new(Workers::new(source_files, &self.config, None)?);
+        *current = Some((reducers.clone(), workers.clone()));
+        Ok((reducers, workers))
+    }
+}
+
+/// Reduces every set of roots in parallel
+///
+/// Sets are deduplicated and the results are keyed by their sorted, comma-separated roots.
+/// `reduce` is called with roots of a set and its key, typically using [`Workers`].
+///
+/// [`Workers`]: struct.Workers.html
+pub fn reduce_root_sets<F, T>(root_sets: Vec<Vec<String>>, reduce: F) -> BTreeMap<String, T>
+    where F: Fn(&[String], &str) -> T + Sync, T: Send {
+    let root_sets: BTreeMap<String, Vec<String>> = root_sets.into_iter()
+        .map(|mut roots| {
+            roots.sort();
+            roots.dedup();
+            (roots.join(","), roots)
+        }).collect();
+    root_sets.into_par_iter()
+        .map(|(key, roots)| {
+            let result = reduce(&roots, &key);
+            (key, result)
+        }).collect()
+}
+
+fn reduce_with_cache<RCR, R>
+    (container: &RCR, reducer: &mut R, scope: &str,
+     state: serde_json::Map<String, serde_json::Value>, cache: &Option<(ReductionCache, Vec<u8>)>) -> serde_json::Map<String, serde_json::Value>
+    where RCR: RecordContainerReduction<Record = RedactedRecord<repository::Record>>,
+          R: Reducer<State = serde_json::Map<String, serde_json::Value>, Item = RedactedRecord<repository::Record>> {
+    match cache {
+        Some((cache, reducers)) => cache.reduce_with_reducer_and_state(container, reducer, reducers, scope, state),
+        None => container.reduce_with_reducer_and_state(reducer, state),
+    }.expect("can't reduce")
 }
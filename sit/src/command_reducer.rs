@@ -0,0 +1,188 @@
+use clap::ArgMatches;
+use sit_core::{Repository, Record, record::{RecordOwningContainer, RecordContainerReduction}};
+use crate::cfg::Configuration;
+use crate::command_reduce;
+use serde_derive::Deserialize;
+use serde_json::{self, Map, Value as JsonValue};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use jmespath;
+use tempdir::TempDir;
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Reducer test fixture (JSON or YAML)
+#[derive(Deserialize)]
+struct Fixture {
+    /// Reducers to test, relative to the fixture's directory
+    #[serde(default)]
+    reducers: Vec<PathBuf>,
+    tests: Vec<Test>,
+}
+
+#[derive(Deserialize)]
+struct Test {
+    name: String,
+    #[serde(default)]
+    records: Vec<FixtureRecord>,
+    /// Initial state
+    #[serde(default)]
+    state: Map<String, JsonValue>,
+    /// Expected state
+    #[serde(default)]
+    expect: Option<JsonValue>,
+    /// Expected results of JMESPath queries against the state
+    #[serde(default)]
+    queries: Vec<Query>,
+}
+
+#[derive(Deserialize)]
+struct FixtureRecord {
+    /// Identifier other records' `prev` can refer to
+    #[serde(default)]
+    id: Option<String>,
+    /// Identifiers of parent records
+    #[serde(default)]
+    prev: Vec<String>,
+    /// File contents (strings are taken verbatim, other values are serialized as JSON)
+    #[serde(default)]
+    files: Map<String, JsonValue>,
+}
+
+#[derive(Deserialize)]
+struct Query {
+    query: String,
+    expect: JsonValue,
+}
+
+fn yaml_to_json(yaml: Yaml) -> Result<JsonValue, String> {
+    Ok(match yaml {
+        Yaml::Null => JsonValue::Null,
+        Yaml::Boolean(b) => JsonValue::Bool(b),
+        Yaml::Integer(i) => JsonValue::from(i),
+        Yaml::Real(r) => r.parse::<f64>().map(JsonValue::from).map_err(|_| format!("invalid number {}", r))?,
+        Yaml::String(s) => JsonValue::String(s),
+        Yaml::Array(array) => JsonValue::Array(array.into_iter().map(yaml_to_json).collect::<Result<_, _>>()?),
+        Yaml::Hash(hash) => {
+            let mut map = Map::new();
+            for (k, v) in hash {
+                let key = match k {
+                    Yaml::String(s) => s,
+                    Yaml::Integer(i) => i.to_string(),
+                    Yaml::Boolean(b) => b.to_string(),
+                    k => return Err(format!("unsupported key {:?}", k)),
+                };
+                map.insert(key, yaml_to_json(v)?);
+            }
+            JsonValue::Object(map)
+        },
+        yaml => return Err(format!("unsupported value {:?}", yaml)),
+    })
+}
+
+fn load_fixture(path: &Path) -> Result<Fixture, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let value = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml") | Some("yml") => {
+            let mut docs = YamlLoader::load_from_str(&content).map_err(|e| e.to_string())?;
+            if docs.is_empty() {
+                return Err("empty document".into());
+            }
+            yaml_to_json(docs.remove(0))?
+        },
+        _ => serde_json::from_str(&content).map_err(|e| e.to_string())?,
+    };
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+/// Runs a test, returns a list of failures
+fn run(test: &Test, reducers: Vec<PathBuf>, config: &Configuration) -> Result<Vec<String>, String> {
+    let tmp = TempDir::new("sit").map_err(|e| e.to_string())?;
+    let repo = Repository::new(tmp.path().join(".sit")).map_err(|e| format!("{:?}", e))?;
+
+    let mut hashes = HashMap::new();
+    for record in test.records.iter() {
+        let mut files: Vec<(String, Vec<u8>)> = record.files.iter().map(|(name, value)| (name.clone(), match value {
+            JsonValue::String(s) => s.clone().into_bytes(),
+            value => serde_json::to_vec(value).unwrap(),
+        })).collect();
+        for prev in record.prev.iter() {
+            match hashes.get(prev) {
+                Some(hash) => files.push((format!(".prev/{}", hash), vec![])),
+                None => return Err(format!("record {} is not defined before it is referenced", prev)),
+            }
+        }
+        let new_record = repo.new_record(files.iter().map(|(name, content)| (name.as_str(), &content[..])), false)
+            .map_err(|e| format!("{:?}", e))?;
+        if let Some(ref id) = record.id {
+            hashes.insert(id.clone(), new_record.encoded_hash());
+        }
+    }
+
+    let mut reducer = command_reduce::reducer(reducers, config, None)?;
+    let state = repo.initialize_state(test.state.clone());
+    let state = JsonValue::Object(repo.reduce_with_reducer_and_state(&mut reducer, state).map_err(|e| format!("{:?}", e))?);
+
+    let mut failures = vec![];
+    if let Some(ref expect) = test.expect {
+        if expect != &state {
+            failures.push(format!("expected state {}, got {}", expect, state));
+        }
+    }
+    let data = jmespath::Variable::from(state);
+    for query in test.queries.iter() {
+        let expr = jmespath::compile(&query.query).map_err(|e| e.to_string())?;
+        let result = serde_json::to_value(&*expr.search(&data).map_err(|e| e.to_string())?).unwrap();
+        if result != query.expect {
+            failures.push(format!("expected `{}` to be {}, got {}", query.query, query.expect, result));
+        }
+    }
+    Ok(failures)
+}
+
+pub fn command(matches: &ArgMatches, config: &Configuration) -> i32 {
+    let matches = matches.subcommand_matches("test").unwrap();
+    let (mut passed, mut failed) = (0, 0);
+    for fixture_path in matches.values_of("fixture").unwrap().map(PathBuf::from) {
+        let fixture = match load_fixture(&fixture_path) {
+            Ok(fixture) => fixture,
+            Err(err) => {
+                eprintln!("can't load {}: {}", fixture_path.display(), err);
+                return 1;
+            },
+        };
+        let reducers = match matches.values_of_os("reducer") {
+            Some(vals) => vals.map(PathBuf::from).collect::<Vec<_>>(),
+            None => {
+                let base = fixture_path.parent().unwrap_or(Path::new(""));
+                fixture.reducers.iter().map(|p| base.join(p)).collect()
+            },
+        };
+        if reducers.is_empty() {
+            eprintln!("no reducers specified for {}", fixture_path.display());
+            return 1;
+        }
+        for test in fixture.tests.iter() {
+            match run(test, reducers.clone(), config) {
+                Ok(ref failures) if failures.is_empty() => {
+                    passed += 1;
+                    println!("{}: {} ... ok", fixture_path.display(), test.name);
+                },
+                Ok(failures) => {
+                    failed += 1;
+                    println!("{}: {} ... FAILED", fixture_path.display(), test.name);
+                    for failure in failures {
+                        println!("    {}", failure);
+                    }
+                },
+                Err(err) => {
+                    failed += 1;
+                    println!("{}: {} ... ERROR", fixture_path.display(), test.name);
+                    println!("    {}", err);
+                },
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 { 1 } else { 0 }
+}
@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+use clap::ArgMatches;
+use sit_core::{Repository, repository::ImportReport};
+
+pub fn command<MI>(matches: &ArgMatches, repo: &Repository<MI>) -> i32 {
+    let path = PathBuf::from(matches.value_of("other").unwrap());
+    // accept both the repository itself and a directory containing it
+    let path = if path.join(".sit").is_dir() { path.join(".sit") } else { path };
+    let other = match Repository::open(&path) {
+        Ok(other) => other,
+        Err(err) => {
+            eprintln!("Can't open repository {}: {:?}", path.display(), err);
+            return 1;
+        },
+    };
+
+    let mut reports = vec![];
+    if !matches.is_present("push") {
+        reports.push(repo.import_records_from(&other));
+    }
+    if !matches.is_present("pull") {
+        reports.push(other.import_records_from(repo));
+    }
+
+    let mut success = true;
+    for report in reports {
+        match report {
+            Ok(report) => success &= print_report(report),
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                return 1;
+            },
+        }
+    }
+    if success { 0 } else { 1 }
+}
+
+/// Prints imported records and reports conflicting or invalid ones.
+/// Returns false if there were any of the latter.
+pub fn print_report(report: ImportReport) -> bool {
+    let ImportReport { imported, conflicting, invalid, .. } = report;
+    for hash in imported {
+        println!("{}", hash);
+    }
+    for hash in conflicting.iter() {
+        eprintln!("Conflicting record {}", hash);
+    }
+    for hash in invalid.iter() {
+        eprintln!("Invalid record {}", hash);
+    }
+    conflicting.is_empty() && invalid.is_empty()
+}
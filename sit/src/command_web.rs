@@ -23,6 +23,7 @@ pub fn command<MI: 'static + Send + Sync, P: AsRef<Path>, P1: AsRef<Path>>(repo:
 
 mod webapp {
     use crate::cfg;
+    use crate::command_reduce;
     #[allow(dead_code)]
     mod assets {
         include!(concat!(env!("OUT_DIR"), "/web_assets.rs"));
@@ -90,8 +91,9 @@ mod webapp {
     use std::fs;
     use std::net::ToSocketAddrs;
 
-    use sit_core::{Repository, repository, reducers::duktape::{self, DuktapeReducer}, record::OrderedFiles,
-    record::{RecordContainer, RecordContainerReduction, RecordOwningContainer}, path::{HasPath, ResolvePath}};
+    use sit_core::{Repository, repository, record::OrderedFiles,
+    record::{RecordContainer, RecordContainerReduction, RecordOwningContainer}, path::{HasPath, ResolvePath},
+    redaction::{RedactedRecord, RedactedRecordContainer}};
     use std::io::Cursor;
 
     use mime_guess::get_mime_type_str;
@@ -106,9 +108,8 @@ mod webapp {
 
     use serde_json;
 
-    use std::sync::{Arc, Mutex};
-    use std::cell::RefCell;
-    use thread_local::ThreadLocal;
+    use std::sync::Arc;
+    use std::collections::BTreeMap;
     use clap::ArgMatches;
 
     fn path_to_response<P: Into<PathBuf>>(path: P, request: &Request) -> Response {
@@ -243,10 +244,42 @@ mod webapp {
         Ok(record)
     }
 
-    fn reduce<MI, RCR: RecordContainerReduction<Record = repository::Record>>
-        (repo: &Repository<MI>, container: &RCR, request: &Request, query_expr: String) -> Response
+    /// Reducers of the repository, kept for the lifetime of the server
+    type Pool = command_reduce::ReducerPool<RedactedRecord<repository::Record>>;
+
+    fn reduce<MI>(repo: &Repository<MI>, pool: &Pool, roots: Option<Vec<String>>, request: &Request, query_expr: String, config: &cfg::Configuration) -> Response
+        where MI: repository::ModuleIterator<PathBuf, repository::Error> {
+            // redacted files are never reduced
+            let redactions = match repo.redacted() {
+                Ok(redacted) => redacted.redactions().clone(),
+                Err(err) => return Response::text(format!("can't read redactions: {:?}", err)).with_status_code(500),
+            };
+            let scope = command_reduce::redactions_scope("", &redactions);
+            let container = RedactedRecordContainer::new(repo, redactions);
+            match roots {
+                None => reduce_container(repo, pool, &container, &scope, request, query_expr, config),
+                Some(mut roots) => {
+                    roots.sort();
+                    let scope = format!("{}roots:{}", scope, roots.join(","));
+                    reduce_container(repo, pool, &container.fixed_roots(roots), &scope, request, query_expr, config)
+                },
+            }
+    }
+
+    fn reduce_container<MI, RCR: RecordContainerReduction<Record = RedactedRecord<repository::Record>>>
+        (repo: &Repository<MI>, pool: &Pool, container: &RCR, scope: &str, request: &Request, query_expr: String, config: &cfg::Configuration) -> Response
             where MI: repository::ModuleIterator<PathBuf, repository::Error> {
-                if let Some(vals) = request.get_param("reducers") {
+                use jmespath;
+                let query = match jmespath::compile(&query_expr) {
+                    Ok(query) => query,
+                    _ => return Response::empty_400(),
+                };
+                let state = match request.get_param("state").map(|state| serde_json::from_str(&state)) {
+                    None => Default::default(),
+                    Some(Ok(serde_json::Value::Object(state))) => state,
+                    Some(_) => return Response::empty_400(),
+                };
+                let workers = if let Some(vals) = request.get_param("reducers") {
                     let reducers_path = repo.path().join("reducers");
                     let reducers = vals.split(",").map(PathBuf::from)
                         .map(|p| if p.is_file() {
@@ -256,37 +289,79 @@ mod webapp {
                             dir
                         } else {
                             p
-                        });
-                    return reduce__(container, request, query_expr, reducers)
+                        }).collect::<Vec<_>>();
+                    // only repository's own reducers are kept in the pool
+                    command_reduce::reducers_hash(reducers.clone(), config, repo.config().hashing_algorithm())
+                        .and_then(|hash| Ok((hash, Arc::new(command_reduce::Workers::new(reducers, config, None)?))))
                 } else {
-                    return reduce__(container, request, query_expr, repo)
+                    pool.workers(repo, repo.config().hashing_algorithm())
+                };
+                let (reducers, workers) = match workers {
+                    Ok(workers) => workers,
+                    Err(err) => return Response::text(err).with_status_code(500),
+                };
+                let state = container.initialize_state(state);
+                let reduced = match workers.with(|reducer|
+                    repo.reduction_cache().reduce_with_reducer_and_state(container, reducer, &reducers, scope, state)) {
+                    Ok(reduced) => reduced,
+                    Err(err) => return Response::text(format!("can't reduce: {:?}", err)).with_status_code(500),
+                };
+                let data = jmespath::Variable::from(serde_json::Value::Object(reduced));
+                match query.search(&data) {
+                    Ok(result) => Response::json(&result),
+                    Err(_) => Response::empty_400(),
                 }
-                // implementation
-                fn reduce__<RCR: RecordContainerReduction<Record = repository::Record>, SF: duktape::SourceFiles>
-                    (container: &RCR, request: &Request, query_expr: String, source_files: SF) -> Response {
-                        use jmespath;
-                        let reducer = sit_core::reducers::duktape::DuktapeReducer::new(source_files).unwrap();
-                        let query = match jmespath::compile(&query_expr) {
-                            Ok(query) => query,
-                            _ => return Response::empty_400(),
-                        };
-                        fn reduce_<RCR: RecordContainerReduction<Record = repository::Record>>
-                            (container: &RCR, query: jmespath::Expression, mut reducer: duktape::DuktapeReducer<repository::Record>, state: serde_json::Value)-> Response {
-                                let state = container.initialize_state(state.as_object().unwrap().to_owned());
-                                let reduced = container.reduce_with_reducer_and_state(&mut reducer, state).unwrap();
-                                let data = jmespath::Variable::from(serde_json::Value::Object(reduced));
-                                let result = query.search(&data).unwrap();
-                                Response::json(&result)
-                            }
-                        if let Some(state) = request.get_param("state") {
-                            reduce_(container, query, reducer, serde_json::from_str(&state).unwrap())
-                        } else {
-                            reduce_(container, query, reducer, serde_json::Value::Object(Default::default()))
-                        }
-                    }
             }
 
 
+    fn reduce_batch<MI: Send + Sync>(repo: &Repository<MI>, pool: &Pool, request: &Request, query_expr: String) -> Response
+        where MI: repository::ModuleIterator<PathBuf, repository::Error> {
+            use jmespath;
+            // sets of roots are separated by semicolons, roots within a set by commas
+            let root_sets = match request.get_param("roots") {
+                Some(roots) => roots.split(";")
+                    .map(|roots| roots.split(",").filter(|root| !root.is_empty()).map(String::from).collect())
+                    .collect(),
+                None => return Response::empty_400(),
+            };
+            let query = match jmespath::compile(&query_expr) {
+                Ok(query) => query,
+                _ => return Response::empty_400(),
+            };
+            let state = match request.get_param("state").map(|state| serde_json::from_str(&state)) {
+                None => Default::default(),
+                Some(Ok(serde_json::Value::Object(state))) => state,
+                Some(_) => return Response::empty_400(),
+            };
+            // redacted files are never reduced
+            let redactions = match repo.redacted() {
+                Ok(redacted) => redacted.redactions().clone(),
+                Err(err) => return Response::text(format!("can't read redactions: {:?}", err)).with_status_code(500),
+            };
+            let scope = command_reduce::redactions_scope("", &redactions);
+            let container = RedactedRecordContainer::new(repo, redactions);
+            let (reducers, workers) = match pool.workers(repo, repo.config().hashing_algorithm()) {
+                Ok(workers) => workers,
+                Err(err) => return Response::text(err).with_status_code(500),
+            };
+            let cache = repo.reduction_cache();
+            let results = command_reduce::reduce_root_sets(root_sets, |roots, key| workers.with(|reducer| {
+                let container = container.fixed_roots(roots.to_vec());
+                let state = container.initialize_state(state.clone());
+                let reduced = cache.reduce_with_reducer_and_state(&container, reducer, &reducers, &format!("{}roots:{}", scope, key), state)
+                    .map_err(|err| Response::text(format!("can't reduce: {:?}", err)).with_status_code(500))?;
+                let data = jmespath::Variable::from(serde_json::Value::Object(reduced));
+                match query.search(&data) {
+                    Ok(result) => Ok(serde_json::to_value(&*result).unwrap()),
+                    Err(_) => Err(Response::empty_400()),
+                }
+            }));
+            match results.into_iter().map(|(key, result)| result.map(|value| (key, value))).collect::<Result<BTreeMap<_, _>, _>>() {
+                Ok(results) => Response::json(&results),
+                Err(response) => response,
+            }
+    }
+
     pub fn start<A: ToSocketAddrs, MI: 'static + Send + Sync>(addr: A, config: cfg::Configuration, repo: Repository<MI>, readonly: bool, overlays: Vec<&str>, matches: ArgMatches<'static>)
         where MI: sit_core::repository::ModuleIterator<PathBuf, sit_core::repository::Error> {
             let mut overlays: Vec<_> = overlays.iter().map(|o| PathBuf::from(o)).collect();
@@ -311,6 +386,7 @@ mod webapp {
             let repo_config = Config {
                 readonly,
             };
+            let pool = Pool::new(config.clone());
             use rouille::router;
             start_server(addr, move |request|
                          router!(request,
@@ -325,8 +401,15 @@ mod webapp {
                                          use jmespath;
                                          use sit_core::record::RecordContainerReduction;
                                          let items: Vec<_> = repo.item_iter().expect("can't list items").collect();
-                                         let reducer = Arc::new(Mutex::new(sit_core::reducers::duktape::DuktapeReducer::new(&repo).unwrap()));
-                                         let tl_reducer: ThreadLocal<RefCell<DuktapeReducer<sit_core::repository::Record>>>= ThreadLocal::new();
+                                         // redacted files are never reduced
+                                         let redactions = match repo.redacted() {
+                                             Ok(redacted) => redacted.redactions().clone(),
+                                             Err(err) => return Response::text(format!("can't read redactions: {:?}", err)).with_status_code(500),
+                                         };
+                                         let (_, workers) = match pool.workers(&repo, repo.config().hashing_algorithm()) {
+                                             Ok(workers) => workers,
+                                             Err(err) => return Response::text(err).with_status_code(500),
+                                         };
 
                                          let filter_defined = filter_expr != "";
                                          let filter = if filter_defined {
@@ -344,11 +427,9 @@ mod webapp {
 
                                          let result: Vec<_> =
                                              items.into_par_iter()
-                                             .map(|item| {
-                                                 let mut reducer = tl_reducer.get_or(|| Box::new(RefCell::new(reducer.lock().unwrap().clone()))).borrow_mut();
-                                                 reducer.reset_state();
-                                                 item.reduce_with_reducer(&mut *reducer).unwrap()
-                                             }).map(|json| {
+                                             .map(|item| workers.with(|reducer| {
+                                                 RedactedRecordContainer::new(&item, redactions.clone()).reduce_with_reducer(reducer).unwrap()
+                                             })).map(|json| {
                                                  let data = jmespath::Variable::from(serde_json::Value::Object(json));
                                                  let result = if filter_defined {
                                                      let res = filter.search(&data).unwrap();
@@ -374,7 +455,15 @@ mod webapp {
                                          use jmespath;
                                          use sit_core::record::RecordContainerReduction;
                                          use sit_core::Item;
-                                         let mut reducer = sit_core::reducers::duktape::DuktapeReducer::new(&repo).unwrap();
+                                         // redacted files are never reduced
+                                         let redactions = match repo.redacted() {
+                                             Ok(redacted) => redacted.redactions().clone(),
+                                             Err(err) => return Response::text(format!("can't read redactions: {:?}", err)).with_status_code(500),
+                                         };
+                                         let (_, workers) = match pool.workers(&repo, repo.config().hashing_algorithm()) {
+                                             Ok(workers) => workers,
+                                             Err(err) => return Response::text(err).with_status_code(500),
+                                         };
                                          let query = match jmespath::compile(&query_expr) {
                                              Ok(query) => query,
                                              _ => return Response::empty_400(),
@@ -383,7 +472,7 @@ mod webapp {
                                              Some(item) => item,
                                              _ => return Response::empty_404(),
                                          };
-                                         let reduced = item.reduce_with_reducer(&mut reducer).unwrap();
+                                         let reduced = workers.with(|reducer| RedactedRecordContainer::new(&item, redactions).reduce_with_reducer(reducer)).unwrap();
                                          let data = jmespath::Variable::from(serde_json::Value::Object(reduced));
                                          let result = query.search(&data).unwrap();
                                          Response::json(&result)
@@ -392,12 +481,14 @@ mod webapp {
                                          Response::not_found()
                                      }
                                  },
+                                 (GET) (/api/batch/reduce/{query_expr: String}) => {
+                                     reduce_batch(&repo, &pool, &request, query_expr)
+                                 },
                                  (GET) (/api/{roots: String}/reduce/{query_expr: String}) => {
-                                     let container = repo.fixed_roots(roots.split(","));
-                                     reduce(&repo, &container, &request, query_expr)
+                                     reduce(&repo, &pool, Some(roots.split(",").map(String::from).collect()), &request, query_expr, &config)
                                  },
                                  (GET) (/api/reduce/{query_expr: String}) => {
-                                     reduce(&repo, &repo, &request, query_expr)
+                                     reduce(&repo, &pool, None, &request, query_expr, &config)
                                  },
                                  (GET) (/api/item/{id: String}/{record: String}/files) => { // DEPRECATED
                                      #[cfg(feature = "deprecated-items")] {
@@ -406,6 +497,12 @@ mod webapp {
                                              Some(item) => item,
                                              None => return Response::empty_404(),
                                          };
+                                         // redacted files are never shown
+                                         let redactions = match repo.redacted() {
+                                             Ok(redacted) => redacted.redactions().clone(),
+                                             Err(err) => return Response::text(format!("can't read redactions: {:?}", err)).with_status_code(500),
+                                         };
+                                         let item = RedactedRecordContainer::new(&item, redactions);
                                          let record = match ::itertools::Itertools::flatten(item.record_iter().unwrap()).find(|r| r.encoded_hash() == record) {
                                              Some(record) => record,
                                              None => return Response::empty_404(),
@@ -423,7 +520,15 @@ mod webapp {
                                          Some(record) => record,
                                          None => return Response::empty_404(),
                                      };
-                                     let files: Vec<_> = record.file_iter().map(|(name, _)| name).collect();
+                                     // redacted files are never shown
+                                     let redacted = match repo.redacted() {
+                                         Ok(redacted) => redacted,
+                                         Err(err) => return Response::text(format!("can't read redactions: {:?}", err)).with_status_code(500),
+                                     };
+                                     let redacted_files = redacted.redactions().redacted_files(record.encoded_hash());
+                                     let files: Vec<_> = record.file_iter().map(|(name, _)| name)
+                                         .filter(|name| !redacted_files.map(|files| files.contains(name)).unwrap_or(false))
+                                         .collect();
                                      Response::json(&files)
                                  },
                                  (POST) (/api/item) => {
@@ -7,7 +7,7 @@ use clap::{crate_version, crate_description};
 
 mod cfg;
 mod rebuild;
-use crate::rebuild::rebuild_repository;
+use crate::rebuild::{rebuild_repository, RewriteRules};
 mod command_config;
 mod command_args;
 mod command_init;
@@ -15,10 +15,18 @@ mod command_item;
 mod command_record;
 mod command_items;
 mod command_reduce;
+mod command_reducer;
 mod command_records;
+mod command_log;
 mod command_external;
 mod command_jmespath;
 mod command_integrity;
+mod command_pack;
+mod command_gc;
+mod command_graph;
+mod command_sync;
+mod command_bundle;
+mod command_redact;
 #[cfg(feature="web")]
 mod command_web;
 mod authorship;
@@ -108,6 +116,95 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
         .subcommand(SubCommand::with_name("integrity")
             .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
             .about("Checks the integrity of record hashes and lists invalid records"))
+        .subcommand(SubCommand::with_name("gc")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Lists (and removes) leftovers of interrupted writers")
+            .long_about("Lists abandoned temporary directories, empty directories under records/ and \
+            links to missing records. With --remove, removes all of them except for links to missing \
+            records (removing these would change records' hashes).")
+            .arg(Arg::with_name("remove")
+                .long("remove")
+                .help("Remove abandoned temporary and empty directories")))
+        .subcommand(SubCommand::with_name("redact")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Hides files of a record from views")
+            .long_about("Creates a redaction record that hides given files of a record from `records`, \
+            `reduce` and other views. The record itself (and therefore the history) is left intact, \
+            so redacted files are still present in the repository.")
+            .arg(Arg::with_name("record")
+                .required(true)
+                .takes_value(true)
+                .help("Record to redact"))
+            .arg(Arg::with_name("files")
+                .required(true)
+                .multiple(true)
+                .takes_value(true)
+                .help("Names of files to redact")))
+        .subcommand(SubCommand::with_name("sync")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Copies missing records between this and another repository")
+            .long_about("Copies missing records between this and another repository, in both directions \
+            unless --pull or --push is specified. Prints copied records and reports conflicting or \
+            invalid ones (these are never copied).")
+            .arg(Arg::with_name("other")
+                .required(true)
+                .takes_value(true)
+                .help("Path to the other repository (or a directory containing .sit)"))
+            .arg(Arg::with_name("pull")
+                .long("pull")
+                .conflicts_with("push")
+                .help("Only copy records from the other repository"))
+            .arg(Arg::with_name("push")
+                .long("push")
+                .conflicts_with("pull")
+                .help("Only copy records to the other repository")))
+        .subcommand(SubCommand::with_name("bundle")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto,
+                        clap::AppSettings::SubcommandRequiredElseHelp])
+            .about("Exchanges records through bundle files")
+            .subcommand(SubCommand::with_name("create")
+                .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+                .about("Creates a bundle of records and lists them")
+                .arg(Arg::with_name("root")
+                     .long("root")
+                     .short("R")
+                     .takes_value(true)
+                     .multiple(true)
+                     .help("Only bundle records descending from these roots"))
+                .arg(Arg::with_name("since")
+                     .long("since")
+                     .takes_value(true)
+                     .validator(|v| command_log::parse_date(&v).map(|_| ()))
+                     .help("Only bundle records timestamped at or after this date (RFC 3339 or YYYY-MM-DD)"))
+                .arg(Arg::with_name("bundle")
+                     .required(true)
+                     .takes_value(true)
+                     .help("Bundle file")))
+            .subcommand(SubCommand::with_name("apply")
+                .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+                .about("Copies missing records from a bundle, verifying their hashes")
+                .arg(Arg::with_name("bundle")
+                     .required(true)
+                     .takes_value(true)
+                     .help("Bundle file"))))
+        .subcommand(SubCommand::with_name("graph")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Queries the graph of records")
+            .long_about("Without a query, lists every record (in the order of iteration) \
+            followed by its parents")
+            .arg(Arg::with_name("query")
+                .takes_value(true)
+                .possible_values(&["heads", "parents", "children", "ancestors", "descendants"])
+                .help("Query (`heads` lists records new records get linked to)"))
+            .arg(Arg::with_name("record")
+                .takes_value(true)
+                .help("Record to query")))
+        .subcommand(SubCommand::with_name("pack")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Moves loose records into a pack")
+            .long_about("Records stored as individual directories are moved into a single \
+            archive (under `packs/`) to reduce the number of files in the repository. \
+            Record hashes are preserved. Records that fail integrity check are kept intact."))
         .subcommand(SubCommand::with_name("upgrade")
             .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
             .about("Upgrades the repository"))
@@ -150,7 +247,30 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                      .long("on-record")
                      .takes_value(true)
                      .long_help("Execute this command on every record before re-hashing it. \
-                     The directory is passed as the first argument.")))
+                     The directory is passed as the first argument."))
+            .arg(Arg::with_name("hashing-algorithm")
+                     .long("hashing-algorithm")
+                     .takes_value(true)
+                     .validator(|v| v.parse::<sit_core::hash::HashingAlgorithm>().map(|_| ()))
+                     .help("Hashing algorithm to use in the destination repository \
+                     (blake2b, blake2b-<bits>, sha1, sha256, blake3; defaults to the source repository's)"))
+            .arg(Arg::with_name("encoding")
+                     .long("encoding")
+                     .takes_value(true)
+                     .possible_values(&["base32", "base32lower", "hex", "base58"])
+                     .help("Encoding to use in the destination repository (defaults to the source repository's)"))
+            .arg(Arg::with_name("mapping")
+                     .long("mapping")
+                     .takes_value(true)
+                     .help("Write old and new hashes of every record into this file, one pair per line"))
+            .arg(Arg::with_name("rules")
+                     .long("rules")
+                     .takes_value(true)
+                     .long_help("Rewrite records according to rules in this JSON file. \
+                     `drop` is a JMESPath filter selecting records to be dropped (their children get linked to their parents), \
+                     `remove` is a list of globs of files to be removed and \
+                     `replace` is a list of {\"files\": <glob>, \"from\": <string>, \"to\": <string>} replacements. \
+                     Rules are applied before the --on-record hook.")))
         .conditionally(cfg!(feature = "deprecated-items"), |app|
         app.subcommand(SubCommand::with_name("item")
             .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
@@ -265,6 +385,42 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                      .short("Q")
                      .takes_value(true)
                      .help("Render a result of a named JMESPath query over the record")))
+        .subcommand(SubCommand::with_name("log")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+            .about("Shows the history of records")
+            .arg(Arg::with_name("root")
+                 .long("root")
+                 .short("R")
+                 .takes_value(true)
+                 .multiple(true)
+                 .help("Only show records descending from these roots"))
+            .arg(Arg::with_name("since")
+                 .long("since")
+                 .takes_value(true)
+                 .validator(|v| command_log::parse_date(&v).map(|_| ()))
+                 .help("Only show records timestamped at or after this date (RFC 3339 or YYYY-MM-DD)"))
+            .arg(Arg::with_name("until")
+                 .long("until")
+                 .takes_value(true)
+                 .validator(|v| command_log::parse_date(&v).map(|_| ()))
+                 .help("Only show records timestamped at or before this date (RFC 3339 or YYYY-MM-DD)"))
+            .arg(Arg::with_name("type")
+                 .long("type")
+                 .short("t")
+                 .takes_value(true)
+                 .multiple(true)
+                 .help("Only show records of these types"))
+            .arg(Arg::with_name("author")
+                 .long("author")
+                 .short("a")
+                 .takes_value(true)
+                 .multiple(true)
+                 .help("Only show records whose authors contain this text (case-insensitive)"))
+            .arg(Arg::with_name("format")
+                 .long("format")
+                 .short("f")
+                 .takes_value(true)
+                 .help("Record template with {hash}, {parents}, {types}, {authors} and {timestamp} placeholders")))
         .subcommand(SubCommand::with_name("reduce")
             .about("Reduce records")
             .conditionally(cfg!(feature = "deprecated-items"), |app|
@@ -283,6 +439,21 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                  .takes_value(true)
                  .multiple(true)
                  .help("Specifies fixed roots to begin the reduction from"))
+            .arg(Arg::with_name("batch")
+                 .long("batch")
+                 .short("B")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .conflicts_with("root")
+                 .help("Reduces every specified (comma-separated) set of fixed roots in parallel \
+                        and renders a JSON object of results keyed by roots"))
+            .arg(Arg::with_name("no-cache")
+                 .long("no-cache")
+                 .help("Reduces all records without using (or updating) the reduction cache"))
+            .arg(Arg::with_name("debug")
+                 .long("debug")
+                 .help("Prints reducers' console output to stderr as JSON lines (implies --no-cache)"))
             .arg(Arg::with_name("format")
                  .short("f")
                  .long("format")
@@ -308,6 +479,30 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                      .short("Q")
                      .takes_value(true)
                      .help("Render a result of a named JMESPath query")))
+        .subcommand(SubCommand::with_name("reducer")
+            .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto,
+                        clap::AppSettings::SubcommandRequiredElseHelp])
+            .about("Reducer development tools")
+            .subcommand(SubCommand::with_name("test")
+                .settings(&[clap::AppSettings::ColoredHelp, clap::AppSettings::ColorAuto])
+                .about("Tests reducers against fixtures")
+                .long_about("Every fixture (JSON, or YAML if the file has a .yaml or .yml extension) \
+                lists reducers to test (relative to the fixture) and tests. Every test describes records \
+                (`files` and `prev`, referring to `id` of earlier records), an optional initial `state`, \
+                an optional expected state (`expect`) and `queries` (JMESPath `query` and its `expect`ed result). \
+                Exits with a non-zero status if any test fails.")
+                .arg(Arg::with_name("reducer")
+                     .short("r")
+                     .long("reducer")
+                     .takes_value(true)
+                     .multiple(true)
+                     .number_of_values(1)
+                     .help("Reducers to test (instead of ones specified in fixtures)"))
+                .arg(Arg::with_name("fixture")
+                     .required(true)
+                     .takes_value(true)
+                     .multiple(true)
+                     .help("Fixture file"))))
         .subcommand(SubCommand::with_name("config")
             .about("Prints configuration file")
             .arg(Arg::with_name("kind")
@@ -404,12 +599,30 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
         return command_jmespath::command(matches);
     }
 
+    if let Some(matches) = matches.subcommand_matches("reducer") {
+        return command_reducer::command(matches, &config);
+    }
+
     if let Some(init_matches) = matches.subcommand_matches("init") {
         return command_init::command(&init_matches, &matches, &working_dir, &dot_sit);
     } else if let Some(matches) = matches.subcommand_matches("rebuild") {
+        let rules = match matches.value_of("rules") {
+            Some(path) => match RewriteRules::load(path) {
+                Ok(rules) => rules,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return 1;
+                },
+            },
+            None => RewriteRules::default(),
+        };
         rebuild_repository(matches.value_of("SRC").unwrap(),
                            matches.value_of("DEST").unwrap(),
-                           matches.value_of("on-record"));
+                           matches.value_of("on-record"),
+                           matches.value_of("hashing-algorithm").map(|v| v.parse().unwrap()),
+                           matches.value_of("encoding").map(|v| v.parse().unwrap()),
+                           matches.value_of("mapping"),
+                           &rules);
         return 0;
     } else if let Some(_) = matches.subcommand_matches("upgrade") {
         let mut upgrades = vec![];
@@ -505,6 +718,10 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                 return command_records::command(matches, repo, config);
             }
 
+            if let Some(matches) = matches.subcommand_matches("log") {
+                return command_log::command(matches, &repo);
+            }
+
             if let Some(matches) = matches.subcommand_matches("reduce") {
                 return command_reduce::command(matches, repo, config);
             }
@@ -520,6 +737,30 @@ fn main_with_result(allow_external_subcommands: bool) -> i32 {
                 return command_integrity::command(repo);
             }
 
+            if let Some(matches) = matches.subcommand_matches("gc") {
+                return command_gc::command(matches, &repo);
+            }
+
+            if let Some(matches) = matches.subcommand_matches("redact") {
+                return command_redact::command(matches, &repo);
+            }
+
+            if let Some(matches) = matches.subcommand_matches("sync") {
+                return command_sync::command(matches, &repo);
+            }
+
+            if let Some(matches) = matches.subcommand_matches("bundle") {
+                return command_bundle::command(matches, &repo);
+            }
+
+            if let Some(matches) = matches.subcommand_matches("graph") {
+                return command_graph::command(matches, &repo);
+            }
+
+            if let Some(_) = matches.subcommand_matches("pack") {
+                return command_pack::command(repo);
+            }
+
             #[cfg(feature = "web")] {
                 if let Some(web_matches) = matches.subcommand_matches("web") {
                     return command_web::command(repo, web_matches, matches.clone(), config, canonical_working_dir, config_path);
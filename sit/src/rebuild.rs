@@ -1,13 +1,170 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::io::{self, Read, Write};
 use std::ffi::OsString;
+use std::collections::HashMap;
 use fs_extra;
-use sit_core::{Repository, Item, Record, record::RecordOwningContainer, record::RecordContainer, path::HasPath};
+use sit_core::{Repository, Record, record::RecordOwningContainer, record::RecordContainer, path::HasPath};
+#[cfg(feature = "deprecated-items")]
+use sit_core::Item;
+use sit_core::{hash::HashingAlgorithm, encoding::Encoding};
 use pbr::ProgressBar;
 use tempdir::TempDir;
 use glob;
+use jmespath;
+use serde_derive::Deserialize;
+use serde;
+use serde_json;
 
-pub fn rebuild_repository<S: Into<PathBuf>>(src: S, dest: S, on_record: Option<S>) {
+/// Declarative rules for rewriting records during rebuild
+///
+/// Rules are read from a JSON file:
+///
+/// ```json
+/// {
+///   "drop": "files.\".type/Secret\" != null",
+///   "remove": ["attachments/*.zip"],
+///   "replace": [{"files": "text", "from": "hunter2", "to": "[REDACTED]"}]
+/// }
+/// ```
+///
+/// * `drop` is a JMESPath filter (evaluated against the same JSON representation
+///   `sit records` uses); matching records are not rebuilt and their children
+///   get linked to their parents instead
+/// * `remove` lists globs of files to be removed from every record
+/// * `replace` replaces all occurrences of `from` with `to` in files matching `files` glob
+///
+/// Links to previous records (`.prev/`) are never removed or replaced by these rules.
+#[derive(Default)]
+pub struct RewriteRules {
+    drop: Option<jmespath::Expression<'static>>,
+    remove: Vec<glob::Pattern>,
+    replace: Vec<(glob::Pattern, Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Deserialize)]
+struct RewriteRulesFile {
+    #[serde(default)]
+    drop: Option<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+    #[serde(default)]
+    replace: Vec<Replacement>,
+}
+
+#[derive(Deserialize)]
+struct Replacement {
+    files: String,
+    from: String,
+    to: String,
+}
+
+impl RewriteRules {
+    /// Loads rules from a JSON file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let file = fs::File::open(path.as_ref()).map_err(|e| format!("can't open {}: {}", path.as_ref().display(), e))?;
+        let rules: RewriteRulesFile = serde_json::from_reader(file)
+            .map_err(|e| format!("can't parse {}: {}", path.as_ref().display(), e))?;
+        let drop = match rules.drop {
+            Some(ref expr) => Some(jmespath::compile(expr).map_err(|e| format!("invalid drop filter {}: {}", expr, e))?),
+            None => None,
+        };
+        let pattern = |p: &str| glob::Pattern::new(p).map_err(|e| format!("invalid glob {}: {}", p, e));
+        let remove = rules.remove.iter().map(|p| pattern(p)).collect::<Result<Vec<_>, _>>()?;
+        let mut replace = vec![];
+        for r in rules.replace {
+            if r.from.is_empty() {
+                return Err(format!("can't replace an empty string in {}", r.files));
+            }
+            replace.push((pattern(&r.files)?, r.from.into_bytes(), r.to.into_bytes()));
+        }
+        Ok(RewriteRules { drop, remove, replace })
+    }
+
+    /// Returns true if the record should be dropped
+    fn drops<R: Record + serde::Serialize>(&self, record: &R) -> bool {
+        match self.drop {
+            Some(ref filter) => {
+                let json = serde_json::to_value(record).expect("can't convert record to JSON");
+                filter.search(&jmespath::Variable::from(json)).expect("can't evaluate drop filter")
+                    .as_boolean().unwrap_or(false)
+            },
+            None => false,
+        }
+    }
+
+    /// Returns rewritten file contents, or `None` if the file should be removed
+    fn rewrite<Rd: Read>(&self, name: &str, mut reader: Rd) -> io::Result<Option<Vec<u8>>> {
+        if self.remove.iter().any(|p| p.matches(name)) {
+            return Ok(None);
+        }
+        let mut contents = vec![];
+        reader.read_to_end(&mut contents)?;
+        for &(ref files, ref from, ref to) in self.replace.iter() {
+            if files.matches(name) {
+                contents = replace_bytes(&contents, from, to);
+            }
+        }
+        Ok(Some(contents))
+    }
+}
+
+fn replace_bytes(haystack: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(haystack.len());
+    let mut i = 0;
+    while i < haystack.len() {
+        if haystack[i..].starts_with(from) {
+            result.extend_from_slice(to);
+            i += from.len();
+        } else {
+            result.push(haystack[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+/// Old records mapped to the new ones
+#[derive(Default)]
+struct Renames {
+    /// old encoded hash => new encoded hash
+    renamed: HashMap<String, String>,
+    /// dropped record's encoded hash => new encoded hashes of its (non-dropped) ancestors
+    dropped: HashMap<String, Vec<String>>,
+}
+
+impl Renames {
+    /// Returns new encoded hashes a link to `hash` should be replaced with
+    ///
+    /// Links to unknown records are kept intact.
+    fn resolve(&self, hash: &str) -> Vec<String> {
+        match (self.renamed.get(hash), self.dropped.get(hash)) {
+            (Some(new_hash), _) => vec![new_hash.clone()],
+            (None, Some(parents)) => parents.clone(),
+            (None, None) => vec![hash.into()],
+        }
+    }
+
+    fn drop_record<R: Record>(&mut self, record: &R) {
+        let mut parents: Vec<String> = record.parents().iter().flat_map(|p| self.resolve(p)).collect();
+        parents.sort();
+        parents.dedup();
+        self.dropped.insert(record.encoded_hash().as_ref().into(), parents);
+    }
+}
+
+/// Rebuilds repository `src` into a new repository `dest`
+///
+/// Every record is re-hashed (using `hashing_algorithm` and `encoding`, if given,
+/// or the ones `src` uses otherwise) and links to previous records are
+/// rewritten accordingly. Records are processed generation by generation,
+/// so parents are always rebuilt before their children. Records are rewritten
+/// according to `rules` before `on_record` hook is executed. If `mapping` is given,
+/// old and new encoded hashes of every record are written into it, one pair per line
+/// (dropped records are mapped to `-`).
+pub fn rebuild_repository<S: Into<PathBuf>>(src: S, dest: S, on_record: Option<S>,
+                                            hashing_algorithm: Option<HashingAlgorithm>, encoding: Option<Encoding>,
+                                            mapping: Option<S>, rules: &RewriteRules) {
     let on_record: Option<OsString> = match on_record {
         Some(command) => {
             let path: PathBuf = command.into();
@@ -17,9 +174,21 @@ pub fn rebuild_repository<S: Into<PathBuf>>(src: S, dest: S, on_record: Option<S
     };
 
     let src = Repository::open(src).expect("can't open source repository");
-    let dest = Repository::new_with_config(dest, src.config().clone())
+    let mut config = src.config().clone();
+    if let Some(hashing_algorithm) = hashing_algorithm {
+        config.set_hashing_algorithm(hashing_algorithm);
+    }
+    if let Some(encoding) = encoding {
+        config.set_encoding(encoding);
+    }
+    let dest = Repository::new_with_config(dest, config)
         .expect("can't create destination repository");
-    // Copy all files and directories except for `config`, `items` and `records`
+    let mut mapping = mapping.map(|path| {
+        let path = path.into();
+        fs::File::create(&path).expect(&format!("can't create mapping file {}", path.display()))
+    });
+    let mut renames = Renames::default();
+    // Copy all files and directories except for `config`, `items`, `records`, `packs` and `cache`
     print!("Copying all supplementary files: ");
     let dir = fs::read_dir(src.path()).expect("can't read source repository record");
     dir.filter(Result::is_ok)
@@ -29,7 +198,9 @@ pub fn rebuild_repository<S: Into<PathBuf>>(src: S, dest: S, on_record: Option<S
             let name = file_name.to_str().unwrap();
             name != "config.json" &&
             name != "items" &&
-            name != "records"
+            name != "records" &&
+            name != "packs" &&
+            name != "cache"
         })
         .for_each(|f| {
             let file_name = f.file_name();
@@ -48,77 +219,126 @@ pub fn rebuild_repository<S: Into<PathBuf>>(src: S, dest: S, on_record: Option<S
         });
     println!("done");
 
-    // Process items
-    let item_count = src.item_iter().expect("can't iterate over source repository's items")
-        .count();
-
-    println!("Processing items");
-
-    let mut pb = ProgressBar::new(item_count as u64);
-    for item in src.item_iter().expect("can't iterate over source repository's items") {
-        let dest_item = dest.new_named_item(item.id())
-            .expect("can't create an item in the destination repository");
-        use std::collections::HashMap;
-        let mut renames = HashMap::new();
-        pb.inc();
-        let recs = item.record_iter()
-            .expect(&format!("can't iterate through records of {}", item.id()));
-        for records in recs {
-            for record in records {
-                let tmp = TempDir::new("sit").expect("can't create temp directory");
-                for (name, _reader) in record.file_iter() {
-                    let path = src.items_path().join(item.id()).join(record.encoded_hash())
-                        .join(&name);
-                    let p = PathBuf::from(&name);
-                    if p.components().count() > 1 {
-                        let mut dir = p.clone();
-                        dir.pop();
-                        let dir = tmp.path().join(&dir);
-                        fs::create_dir_all(&dir).expect(&format!("can't create directory {:?}", dir));
-                    }
+    // Process records
+    let record_count = src.record_iter().expect("can't iterate over source repository's records")
+        .flat_map(|records| records).count();
 
-                    if name.starts_with(".prev/") {
-                        // if there's a reference to a previous hash, it must have
-                        // been recorded in `renames` already
-                        let hash = &name[6..];
-                        let new_prev = renames.get(hash).unwrap();
-                        fs::File::create(tmp.path().join(".prev").join(new_prev))
-                            .expect("can't create a new reference to a previous record");
-                    } else {
-                        let copy_options = fs_extra::file::CopyOptions::new();
-                        fs_extra::file::copy(path, tmp.path().join(p), &copy_options)
-                            .expect(&format!("can't copy file {}", name));
-                    }
+    println!("Processing records");
 
-                }
+    let mut pb = ProgressBar::new(record_count as u64);
+    for records in src.record_iter().expect("can't iterate over source repository's records") {
+        for record in records {
+            pb.inc();
+            let new_hash = if rules.drops(&record) {
+                renames.drop_record(&record);
+                String::from("-")
+            } else {
+                let new_record = rebuild_record(&record, &renames, rules, on_record.as_ref(), |files| dest.new_record(files, false))
+                    .expect("can't create a record in destination repository");
+                renames.renamed.insert(record.encoded_hash(), new_record.encoded_hash());
+                new_record.encoded_hash()
+            };
+            if let Some(ref mut mapping) = mapping {
+                writeln!(mapping, "{} {}", record.encoded_hash(), new_hash)
+                    .expect("can't write to mapping file");
+            }
+        }
+    }
+    pb.finish();
+
+    // Process items
+    #[cfg(feature = "deprecated-items")] {
+        let item_count = src.item_iter().expect("can't iterate over source repository's items")
+            .count();
+
+        println!("Processing items");
 
-                match on_record {
-                    Some(ref command) => {
-                        ::std::process::Command::new(command)
-                            .arg(tmp.path().to_str().unwrap())
-                            .current_dir(tmp.path())
-                            .status()
-                            .expect("can't execute on-record hook");
-                    },
-                    None => (),
+        let mut pb = ProgressBar::new(item_count as u64);
+        for item in src.item_iter().expect("can't iterate over source repository's items") {
+            let dest_item = dest.new_named_item(item.id())
+                .expect("can't create an item in the destination repository");
+            pb.inc();
+            let recs = item.record_iter()
+                .expect(&format!("can't iterate through records of {}", item.id()));
+            for records in recs {
+                for record in records {
+                    if renames.dropped.contains_key(&record.encoded_hash()) {
+                        continue;
+                    }
+                    // item's records reside in `records/` and have been rebuilt
+                    // already, so the new record only needs to be linked to the item
+                    // (re-creating it with the same files yields the same record)
+                    let new_record = match renames.renamed.get(&record.encoded_hash()).and_then(|hash| dest.record(hash)) {
+                        Some(new_record) => dest_item.new_record(new_record.file_iter(), false),
+                        None => rebuild_record(&record, &renames, rules, on_record.as_ref(), |files| dest_item.new_record(files, false)),
+                    }.expect("can't create a record in destination repository");
+                    if !renames.renamed.contains_key(&record.encoded_hash()) {
+                        if let Some(ref mut mapping) = mapping {
+                            writeln!(mapping, "{} {}", record.encoded_hash(), new_record.encoded_hash())
+                                .expect("can't write to mapping file");
+                        }
+                        renames.renamed.insert(record.encoded_hash(), new_record.encoded_hash());
+                    }
                 }
+            }
+        }
+        pb.finish();
+    }
+}
 
-                let new_files =
-                glob::glob(&format!("{}/**/*", tmp.path().to_str().unwrap()))
-                    .expect("invalid glob pattern")
-                    .filter(Result::is_ok)
-                    .map(Result::unwrap)
-                    .filter(|f| f.is_file())
-                    .map(|f| {
-                        let f1 = f.clone();
-                        let name = f1.strip_prefix(tmp.path()).unwrap().to_str().unwrap();
-                        (String::from(name), fs::File::open(f).expect("can't open file"))
-                    });
-
-                let new_record = dest_item.new_record(new_files, false).expect("can't create a record in destination repository");
-                renames.insert(record.encoded_hash(), new_record.encoded_hash());
+/// Copies record's files into a temporary directory (rewriting them according to `rules`
+/// and links to previous records according to `renames`), runs the `on_record` hook
+/// on it and creates a new record out of its contents with `new_record`
+fn rebuild_record<R, T, E, F>(record: &R, renames: &Renames, rules: &RewriteRules, on_record: Option<&OsString>, new_record: F) -> Result<T, E>
+    where R: Record, F: FnOnce(Vec<(String, fs::File)>) -> Result<T, E> {
+    let tmp = TempDir::new("sit").expect("can't create temp directory");
+    fs::create_dir_all(tmp.path().join(".prev")).expect("can't create directory .prev");
+    for (name, reader) in record.file_iter() {
+        let name = name.as_ref();
+        if name.starts_with(".prev/") {
+            // if there's a reference to a previous hash, it must have
+            // been recorded in `renames` already, unless the previous record
+            // is missing (in which case the link is kept intact)
+            for new_prev in renames.resolve(&name[6..]) {
+                fs::File::create(tmp.path().join(".prev").join(new_prev))
+                    .expect("can't create a new reference to a previous record");
             }
+            continue;
+        }
+        let contents = match rules.rewrite(name, reader).expect(&format!("can't read file {}", name)) {
+            Some(contents) => contents,
+            None => continue,
+        };
+        let p = PathBuf::from(name);
+        if p.components().count() > 1 {
+            let mut dir = p.clone();
+            dir.pop();
+            let dir = tmp.path().join(&dir);
+            fs::create_dir_all(&dir).expect(&format!("can't create directory {:?}", dir));
         }
+        fs::write(tmp.path().join(p), contents).expect(&format!("can't copy file {}", name));
     }
-    pb.finish();
+
+    if let Some(command) = on_record {
+        ::std::process::Command::new(command)
+            .arg(tmp.path().to_str().unwrap())
+            .current_dir(tmp.path())
+            .status()
+            .expect("can't execute on-record hook");
+    }
+
+    let new_files: Vec<_> =
+    glob::glob(&format!("{}/**/*", tmp.path().to_str().unwrap()))
+        .expect("invalid glob pattern")
+        .filter(Result::is_ok)
+        .map(Result::unwrap)
+        .filter(|f| f.is_file())
+        .map(|f| {
+            let f1 = f.clone();
+            let name = f1.strip_prefix(tmp.path()).unwrap().to_str().unwrap();
+            (String::from(name), fs::File::open(f).expect("can't open file"))
+        })
+        .collect();
+
+    new_record(new_files)
 }
@@ -0,0 +1,98 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use std::fs;
+
+use sit_core::{Repository, record::RecordOwningContainer, Record};
+
+use cli_test_dir::*;
+
+include!("includes/config.rs");
+
+/// Should bundle all records and apply them to another repository
+#[test]
+fn bundle() {
+    let dir = TestDir::new("sit", "bundle");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), true).unwrap();
+    let rec2 = repo.new_record(vec![("test", &b"2"[..])].into_iter(), true).unwrap();
+    let expected = format!("{}\n{}\n", rec1.encoded_hash(), rec2.encoded_hash());
+    let output = String::from_utf8(dir.cmd().args(&["bundle", "create", "records.bundle"]).expect_success().stdout).unwrap();
+    assert_eq!(output, expected);
+    assert!(dir.path("records.bundle").is_file());
+
+    let other = Repository::new(dir.path("other").join(".sit")).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["-r", "other/.sit", "bundle", "apply", "records.bundle"]).expect_success().stdout).unwrap();
+    assert_eq!(output, expected);
+    assert!(other.record(rec1.encoded_hash()).is_some());
+    assert!(other.record(rec2.encoded_hash()).is_some());
+    // applying it again changes nothing
+    let output = String::from_utf8(dir.cmd().args(&["-r", "other/.sit", "bundle", "apply", "records.bundle"]).expect_success().stdout).unwrap();
+    assert_eq!(output, "");
+}
+
+/// Should only bundle records descending from given roots
+#[test]
+fn bundle_root() {
+    let dir = TestDir::new("sit", "bundle_root");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), false).unwrap();
+    repo.new_record(vec![("test", &b"2"[..])].into_iter(), false).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["bundle", "create", "--root", &rec1.encoded_hash(), "records.bundle"])
+        .expect_success().stdout).unwrap();
+    assert_eq!(output, format!("{}\n", rec1.encoded_hash()));
+}
+
+/// Should only bundle records timestamped at or after a given date
+#[test]
+fn bundle_since() {
+    let dir = TestDir::new("sit", "bundle_since");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![(".timestamp", &b"2018-06-01T12:00:00Z"[..])].into_iter(), true).unwrap();
+    let rec2 = repo.new_record(vec![(".timestamp", &b"2018-07-01T12:00:00Z"[..])].into_iter(), true).unwrap();
+    repo.new_record(vec![("test", &b"no timestamp"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["bundle", "create", "--since", "2018-06-15", "records.bundle"])
+        .expect_success().stdout).unwrap();
+    assert_eq!(output, format!("{}\n", rec2.encoded_hash()));
+}
+
+/// Should refuse to apply records that fail verification
+#[test]
+fn bundle_apply_invalid() {
+    let dir = TestDir::new("sit", "bundle_apply_invalid");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec = repo.new_record(vec![("test", &b"hello"[..])].into_iter(), true).unwrap();
+    dir.cmd().args(&["bundle", "create", "records.bundle"]).expect_success();
+    let mut contents = fs::read(dir.path("records.bundle")).unwrap();
+    let len = contents.len();
+    contents[len - 1] = b'!';
+    fs::write(dir.path("records.bundle"), contents).unwrap();
+
+    let other = Repository::new(dir.path("other").join(".sit")).unwrap();
+    let output = dir.cmd().args(&["-r", "other/.sit", "bundle", "apply", "records.bundle"]).expect_failure();
+    assert!(String::from_utf8(output.stderr).unwrap().contains(&format!("Invalid record {}", rec.encoded_hash())));
+    assert!(other.record(rec.encoded_hash()).is_none());
+}
+
+/// Should fail on files that are not bundles
+#[test]
+fn bundle_apply_not_a_bundle() {
+    let dir = TestDir::new("sit", "bundle_apply_not_a_bundle");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    fs::write(dir.path("records.bundle"), b"hello").unwrap();
+    dir.cmd().args(&["bundle", "apply", "records.bundle"]).expect_failure();
+}
@@ -0,0 +1,51 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use std::fs;
+
+use sit_core::{Repository, record::RecordOwningContainer, Record};
+
+use cli_test_dir::*;
+
+include!("includes/config.rs");
+
+/// Should report nothing in a clean repository
+#[test]
+fn gc_clean() {
+    let dir = TestDir::new("sit", "gc_clean");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![("test", &b"1"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().arg("gc").expect_success().stdout).unwrap();
+    assert_eq!(output, "");
+}
+
+/// Should report leftovers and only remove them if asked to
+#[test]
+fn gc_remove() {
+    let dir = TestDir::new("sit", "gc_remove");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let record = repo.new_record(vec![("test", &b"1"[..]), (".prev/MISSING", &b""[..])].into_iter(), false).unwrap();
+    fs::create_dir_all(dir.path(".sit/sit.abandoned/test")).unwrap();
+    fs::create_dir_all(dir.path(".sit/records/99")).unwrap();
+
+    let output = String::from_utf8(dir.cmd().arg("gc").expect_success().stdout).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("temporary ") && lines[0].ends_with("sit.abandoned"));
+    assert!(lines[1].starts_with("empty ") && lines[1].ends_with("99"));
+    assert_eq!(lines[2], format!("orphaned {} MISSING", record.encoded_hash()));
+    assert!(dir.path(".sit/sit.abandoned").is_dir());
+    assert!(dir.path(".sit/records/99").is_dir());
+
+    assert_eq!(String::from_utf8(dir.cmd().args(&["gc", "--remove"]).expect_success().stdout).unwrap(), output);
+    assert!(!dir.path(".sit/sit.abandoned").exists());
+    assert!(!dir.path(".sit/records/99").exists());
+    let output = String::from_utf8(dir.cmd().arg("gc").expect_success().stdout).unwrap();
+    assert_eq!(output, format!("orphaned {} MISSING\n", record.encoded_hash()));
+}
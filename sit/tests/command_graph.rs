@@ -0,0 +1,95 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use sit_core::{Repository, record::RecordOwningContainer, Record};
+
+use cli_test_dir::*;
+
+include!("includes/config.rs");
+
+fn lines(output: Vec<u8>) -> Vec<String> {
+    let mut lines: Vec<_> = String::from_utf8(output).unwrap().lines().map(String::from).collect();
+    lines.sort();
+    lines
+}
+
+fn sorted(mut hashes: Vec<String>) -> Vec<String> {
+    hashes.sort();
+    hashes
+}
+
+/// Should list every record along with its parents
+#[test]
+fn graph() {
+    let dir = TestDir::new("sit", "graph");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), false).unwrap();
+    let rec2 = repo.new_record(vec![("test", &b"2"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().arg("graph").expect_success().stdout).unwrap();
+    assert_eq!(output, format!("{}\n{} {}\n", rec1.encoded_hash(), rec2.encoded_hash(), rec1.encoded_hash()));
+}
+
+/// Should list heads
+#[test]
+fn graph_heads() {
+    let dir = TestDir::new("sit", "graph_heads");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), false).unwrap();
+    let rec2 = repo.new_record(vec![("test", &b"2"[..])].into_iter(), false).unwrap();
+    assert_eq!(lines(dir.cmd().args(&["graph", "heads"]).expect_success().stdout),
+               sorted(vec![rec1.encoded_hash(), rec2.encoded_hash()]));
+    let rec3 = repo.new_record(vec![("test", &b"3"[..])].into_iter(), true).unwrap();
+    assert_eq!(lines(dir.cmd().args(&["graph", "heads"]).expect_success().stdout), vec![rec3.encoded_hash()]);
+}
+
+/// Should list parents and children of a record
+#[test]
+fn graph_parents_children() {
+    let dir = TestDir::new("sit", "graph_parents_children");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), false).unwrap();
+    let rec2 = repo.new_record(vec![("test", &b"2"[..])].into_iter(), false).unwrap();
+    let rec3 = repo.new_record(vec![("test", &b"3"[..])].into_iter(), true).unwrap();
+    assert_eq!(lines(dir.cmd().args(&["graph", "parents", &rec3.encoded_hash()]).expect_success().stdout),
+               sorted(vec![rec1.encoded_hash(), rec2.encoded_hash()]));
+    assert!(lines(dir.cmd().args(&["graph", "parents", &rec1.encoded_hash()]).expect_success().stdout).is_empty());
+    assert_eq!(lines(dir.cmd().args(&["graph", "children", &rec1.encoded_hash()]).expect_success().stdout),
+               vec![rec3.encoded_hash()]);
+}
+
+/// Should list ancestors and descendants of a record
+#[test]
+fn graph_ancestors_descendants() {
+    let dir = TestDir::new("sit", "graph_ancestors_descendants");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), false).unwrap();
+    let rec2 = repo.new_record(vec![("test", &b"2"[..])].into_iter(), true).unwrap();
+    let rec3 = repo.new_record(vec![("test", &b"3"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["graph", "ancestors", &rec3.encoded_hash()]).expect_success().stdout).unwrap();
+    assert_eq!(output, format!("{}\n{}\n", rec1.encoded_hash(), rec2.encoded_hash()));
+    let output = String::from_utf8(dir.cmd().args(&["graph", "descendants", &rec1.encoded_hash()]).expect_success().stdout).unwrap();
+    assert_eq!(output, format!("{}\n{}\n", rec2.encoded_hash(), rec3.encoded_hash()));
+}
+
+/// Should fail if the record is missing or can't be found
+#[test]
+fn graph_no_record() {
+    let dir = TestDir::new("sit", "graph_no_record");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.cmd().args(&["graph", "parents"]).expect_failure();
+    dir.cmd().args(&["graph", "parents", "missing"]).expect_failure();
+}
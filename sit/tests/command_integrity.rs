@@ -1,5 +1,7 @@
 extern crate cli_test_dir;
 extern crate sit_core;
+#[cfg(any(feature = "sha2", feature = "blake3"))]
+extern crate serde_json;
 
 use sit_core::{Repository, path::HasPath};
 #[cfg(feature = "deprecated-items")]
@@ -116,3 +118,40 @@ fn integrity_check_flag() {
      let output = String::from_utf8(dir.cmd().env("SIT_DISABLE_INTEGRITY_CHECK", "1").args(&["records"]).expect_success().stdout).unwrap();
     assert_eq!(output, format!("{}\n", record.trim()));
 }
+
+/// Initializes a repository using given hashing algorithm, creates a couple of records
+/// and checks their integrity
+#[cfg(any(feature = "sha2", feature = "blake3"))]
+fn integrity_with_hashing_algorithm(name: &str, algorithm: &str) {
+    let dir = TestDir::new("sit", name);
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let mut config: serde_json::Value = serde_json::from_str(&::std::fs::read_to_string(dir.path(".sit/config.json")).unwrap()).unwrap();
+    config["hashing_algorithm"] = serde_json::Value::String(algorithm.into());
+    dir.create_file(".sit/config.json", serde_json::to_string(&config).unwrap());
+    for _ in 0..2 {
+        dir.cmd()
+            .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+            .args(&["record", "--no-author", "-t", "Sometype"])
+            .expect_success();
+    }
+    use sit_core::record::RecordContainer;
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    assert_eq!(repo.record_iter().unwrap().flat_map(|r| r).count(), 2);
+    dir.cmd().arg("integrity").expect_success();
+}
+
+/// Should pass integrity check in a repository using SHA-256
+#[test]
+#[cfg(feature = "sha2")]
+fn integrity_sha256() {
+    integrity_with_hashing_algorithm("integrity_sha256", "sha256");
+}
+
+/// Should pass integrity check in a repository using BLAKE3
+#[test]
+#[cfg(feature = "blake3")]
+fn integrity_blake3() {
+    integrity_with_hashing_algorithm("integrity_blake3", "blake3");
+}
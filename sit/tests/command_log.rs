@@ -0,0 +1,126 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use sit_core::{Repository, record::RecordOwningContainer, Record};
+
+use cli_test_dir::*;
+
+include!("includes/config.rs");
+
+/// Should show nothing if there are no records
+#[test]
+fn log_no_records() {
+    let dir = TestDir::new("sit", "log_no_records");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let output = String::from_utf8(dir.cmd().arg("log").expect_success().stdout).unwrap();
+    assert_eq!(output, "");
+}
+
+/// Should render record details with the default template
+#[test]
+fn log_default_format() {
+    let dir = TestDir::new("sit", "log_default_format");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec = repo.new_record(vec![(".type/Comment", &b""[..]), (".authors", &b"John Doe <john@example.com>"[..]),
+                                   (".timestamp", &b"2018-06-01T12:00:00Z"[..])].into_iter(), false).unwrap();
+    let output = String::from_utf8(dir.cmd().arg("log").expect_success().stdout).unwrap();
+    assert_eq!(output, format!("* {}\n  Type: Comment\n  Author: John Doe <john@example.com>\n  Date: 2018-06-01T12:00:00Z\n\n",
+                               rec.encoded_hash()));
+}
+
+/// Should render branching records
+#[test]
+fn log_branches() {
+    let dir = TestDir::new("sit", "log_branches");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), false).unwrap();
+    let rec2 = repo.new_record(vec![("test", &b"2"[..])].into_iter(), true).unwrap();
+    let prev = format!(".prev/{}", rec1.encoded_hash());
+    let rec3 = repo.new_record(vec![("test", &b"3"[..]), (prev.as_str(), &b""[..])].into_iter(), false).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["log", "--format", "{hash}"]).expect_success().stdout).unwrap();
+    let expected = |a: &str, b: &str| format!("* {}\n|\\\n| * {}\n* {}\n", rec1.encoded_hash(), a, b);
+    assert!(output == expected(&rec2.encoded_hash(), &rec3.encoded_hash()) ||
+            output == expected(&rec3.encoded_hash(), &rec2.encoded_hash()), output);
+}
+
+/// Should render merging records
+#[test]
+fn log_merge() {
+    let dir = TestDir::new("sit", "log_merge");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), false).unwrap();
+    let rec2 = repo.new_record(vec![("test", &b"2"[..])].into_iter(), false).unwrap();
+    let rec3 = repo.new_record(vec![("test", &b"3"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["log", "--format", "{hash}"]).expect_success().stdout).unwrap();
+    let expected = |a: &str, b: &str| format!("* {}\n| * {}\n|/\n* {}\n", a, b, rec3.encoded_hash());
+    assert!(output == expected(&rec1.encoded_hash(), &rec2.encoded_hash()) ||
+            output == expected(&rec2.encoded_hash(), &rec1.encoded_hash()), output);
+}
+
+/// Should only show records descending from given roots
+#[test]
+fn log_root() {
+    let dir = TestDir::new("sit", "log_root");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), false).unwrap();
+    let rec2 = repo.new_record(vec![("test", &b"2"[..])].into_iter(), true).unwrap();
+    repo.new_record(vec![("test", &b"3"[..])].into_iter(), false).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["log", "--format", "{hash}", "--root", &rec1.encoded_hash()]).expect_success().stdout).unwrap();
+    assert_eq!(output, format!("* {}\n* {}\n", rec1.encoded_hash(), rec2.encoded_hash()));
+}
+
+/// Should filter records by type, author and timestamp
+#[test]
+fn log_filters() {
+    let dir = TestDir::new("sit", "log_filters");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![(".type/Comment", &b""[..]), (".authors", &b"John Doe <john@example.com>"[..]),
+                                    (".timestamp", &b"2018-06-01T12:00:00Z"[..])].into_iter(), false).unwrap();
+    let rec2 = repo.new_record(vec![(".type/Closed", &b""[..]), (".authors", &b"Jane Doe <jane@example.com>"[..]),
+                                    (".timestamp", &b"2018-07-01T12:00:00Z"[..])].into_iter(), true).unwrap();
+    let log = |args: &[&str]| {
+        let output = dir.cmd().arg("log").args(&["--format", "{hash}"]).args(args).expect_success().stdout;
+        String::from_utf8(output).unwrap()
+    };
+    assert_eq!(log(&["--type", "Comment"]), format!("* {}\n", rec1.encoded_hash()));
+    assert_eq!(log(&["--type", "Closed"]), format!("* {}\n", rec2.encoded_hash()));
+    assert_eq!(log(&["--author", "jane"]), format!("* {}\n", rec2.encoded_hash()));
+    assert_eq!(log(&["--since", "2018-06-15"]), format!("* {}\n", rec2.encoded_hash()));
+    assert_eq!(log(&["--until", "2018-06-15"]), format!("* {}\n", rec1.encoded_hash()));
+    assert_eq!(log(&["--since", "2018-06-15", "--type", "Comment"]), "");
+    dir.cmd().args(&["log", "--since", "yesterday"]).expect_failure();
+}
+
+/// Should render all placeholders
+#[test]
+fn log_format() {
+    let dir = TestDir::new("sit", "log_format");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), false).unwrap();
+    let rec2 = repo.new_record(vec![(".type/B", &b""[..]), (".type/A", &b""[..]), (".authors", &b"John"[..]),
+                                    (".timestamp", &b"2018-06-01T12:00:00Z"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["log", "--root", &rec2.encoded_hash(),
+                                                    "--format", "{hash} {parents} {types} {authors} {timestamp} {other}"])
+        .expect_success().stdout).unwrap();
+    assert_eq!(output, format!("* {} {} A, B John 2018-06-01T12:00:00Z {{other}}\n", rec2.encoded_hash(), rec1.encoded_hash()));
+}
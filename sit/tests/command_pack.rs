@@ -0,0 +1,73 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use sit_core::{Repository, record::RecordOwningContainer, Record, path::HasPath};
+
+use cli_test_dir::*;
+
+include!("includes/config.rs");
+
+/// Should do nothing if there are no records
+#[test]
+fn pack_nothing() {
+    let dir = TestDir::new("sit", "pack_nothing");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let output = String::from_utf8(dir.cmd().arg("pack").expect_success().stdout).unwrap();
+    assert_eq!(output, "");
+}
+
+/// Should move loose records into a pack, preserving them
+#[test]
+fn pack() {
+    let dir = TestDir::new("sit", "pack");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), true).unwrap();
+    let rec2 = repo.new_record(vec![("test", &b"2"[..])].into_iter(), true).unwrap();
+    let records = String::from_utf8(dir.cmd().arg("records").expect_success().stdout).unwrap();
+    let output = String::from_utf8(dir.cmd().arg("pack").expect_success().stdout).unwrap();
+    assert!(dir.path(output.trim()).is_file());
+    // loose records are gone
+    assert!(!rec1.path().exists());
+    assert!(!rec2.path().exists());
+    // but they are still there
+    dir.cmd().arg("integrity").expect_success();
+    assert_eq!(String::from_utf8(dir.cmd().arg("records").expect_success().stdout).unwrap(), records);
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    assert_eq!(repo.record(rec1.encoded_hash()).unwrap().hash(), rec1.hash());
+    // new records are linked to packed ones
+    let rec3 = repo.new_record(vec![("test", &b"3"[..])].into_iter(), true).unwrap();
+    assert!(rec3.file_iter().any(|(name, _)| name == format!(".prev/{}", rec2.encoded_hash())));
+    // the new record gets packed, too
+    let output = String::from_utf8(dir.cmd().arg("pack").expect_success().stdout).unwrap();
+    assert!(!output.is_empty());
+    // nothing else to pack
+    let output = String::from_utf8(dir.cmd().arg("pack").expect_success().stdout).unwrap();
+    assert_eq!(output, "");
+    dir.cmd().arg("integrity").expect_success();
+}
+
+/// Should reduce packed records
+#[test]
+fn pack_reduce() {
+    let dir = TestDir::new("sit", "pack_reduce");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        return Object.assign(state, {value: (state.value || "") + new TextDecoder('utf-8').decode(record.files.test)});
+    }
+    "#);
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![("test", &b"a"[..])].into_iter(), true).unwrap();
+    repo.new_record(vec![("test", &b"b"[..])].into_iter(), true).unwrap();
+    let before = String::from_utf8(dir.cmd().arg("reduce").expect_success().stdout).unwrap();
+    dir.cmd().arg("pack").expect_success();
+    let after = String::from_utf8(dir.cmd().arg("reduce").expect_success().stdout).unwrap();
+    assert_eq!(before, after);
+}
@@ -0,0 +1,165 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use cli_test_dir::*;
+use sit_core::{Repository, Record, record::RecordContainer};
+#[cfg(feature = "deprecated-items")]
+use sit_core::{Item, encoding::Encoding};
+
+/// Creates a chain of records, returning their hashes
+fn records(dir: &TestDir, count: usize) -> Vec<String> {
+    (0..count).map(|_| {
+        let record = String::from_utf8(dir.cmd()
+            .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+            .args(&["record", "--no-author", "-t", "Sometype"])
+            .expect_success().stdout).unwrap();
+        String::from(record.trim())
+    }).collect()
+}
+
+/// Should rebuild records in the flat namespace, preserving their order
+#[test]
+fn rebuild() {
+    let dir = TestDir::new("sit", "rebuild");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let hashes = records(&dir, 3);
+    dir.cmd()
+        .args(&["rebuild", ".sit", "rebuilt"])
+        .expect_success();
+    // nothing has changed, so neither have the hashes
+    let repo = Repository::open(dir.path("rebuilt")).unwrap();
+    let rebuilt: Vec<Vec<String>> = repo.record_iter().unwrap()
+        .map(|records| records.into_iter().map(|r| r.encoded_hash()).collect())
+        .collect();
+    assert_eq!(rebuilt, vec![vec![hashes[0].clone()], vec![hashes[1].clone()], vec![hashes[2].clone()]]);
+    dir.cmd().args(&["-r", "rebuilt", "integrity"]).expect_success();
+}
+
+/// Should run the hook on every record and link rewritten records together
+#[test]
+#[cfg(unix)]
+fn rebuild_on_record() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = TestDir::new("sit", "rebuild_on_record");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let hashes = records(&dir, 2);
+    dir.create_file("hook", "#!/bin/sh\necho rewritten > \"$1/rewritten\"\n");
+    ::std::fs::set_permissions(dir.path("hook"), ::std::fs::Permissions::from_mode(0o755)).unwrap();
+    dir.cmd()
+        .args(&["rebuild", ".sit", "rebuilt", "--on-record", dir.path("hook").to_str().unwrap(), "--mapping", "mapping"])
+        .expect_success();
+    let mapping = ::std::fs::read_to_string(dir.path("mapping")).unwrap();
+    let mapping: Vec<Vec<&str>> = mapping.lines().map(|l| l.split(' ').collect()).collect();
+    assert_eq!(mapping.iter().map(|m| m[0]).collect::<Vec<_>>(), hashes);
+
+    let repo = Repository::open(dir.path("rebuilt")).unwrap();
+    let records: Vec<_> = repo.record_iter().unwrap().flat_map(|r| r).collect();
+    assert_eq!(records.iter().map(|r| r.encoded_hash()).collect::<Vec<_>>(), vec![mapping[0][1], mapping[1][1]]);
+    assert!(records.iter().all(|r| r.file_iter().any(|(name, _)| name == "rewritten")));
+    assert!(records[0].encoded_hash() != hashes[0]);
+    assert_eq!(records[1].parents(), vec![String::from(mapping[0][1])]);
+    dir.cmd().args(&["-r", "rebuilt", "integrity"]).expect_success();
+}
+
+/// Should re-encode records, rewrite their links and write the mapping
+#[test]
+#[cfg(feature = "deprecated-items")]
+fn rebuild_encoding() {
+    let dir = TestDir::new("sit", "rebuild_encoding");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let id = String::from_utf8(dir.cmd().arg("item").expect_success().stdout).unwrap();
+    let mut hashes = vec![];
+    for _ in 0..2 {
+        let record = String::from_utf8(dir.cmd()
+            .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+            .args(&["record", id.trim(), "--no-author", "-t", "Sometype"])
+            .expect_success().stdout).unwrap();
+        hashes.push(String::from(record.trim()));
+    }
+    dir.cmd()
+        .args(&["rebuild", ".sit", "rebuilt", "--encoding", "hex", "--mapping", "mapping"])
+        .expect_success();
+    let mapping = ::std::fs::read_to_string(dir.path("mapping")).unwrap();
+    let mapping: Vec<Vec<&str>> = mapping.lines().map(|l| l.split(' ').collect()).collect();
+    assert_eq!(mapping.len(), 2);
+    assert_eq!(mapping[0][0], hashes[0]);
+    assert_eq!(mapping[1][0], hashes[1]);
+
+    let repo = Repository::open(dir.path("rebuilt")).unwrap();
+    assert_eq!(repo.config().encoding(), &Encoding::Hex);
+    let item = repo.item(id.trim()).unwrap();
+    let records: Vec<_> = item.record_iter().unwrap().flat_map(|r| r).collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].encoded_hash(), mapping[0][1]);
+    assert_eq!(records[1].encoded_hash(), mapping[1][1]);
+    // the link to the previous record is rewritten
+    let link = format!(".prev/{}", mapping[0][1]);
+    assert!(records[1].file_iter().any(|(name, _)| name == link));
+    dir.cmd().args(&["-r", "rebuilt", "integrity"]).expect_success();
+}
+
+/// Should drop, remove and replace according to rewrite rules, keeping descendants linked
+#[test]
+fn rebuild_rules() {
+    let dir = TestDir::new("sit", "rebuild_rules");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file("text", "password hunter2");
+    dir.create_file("big.zip", "zip");
+    let mut hashes = vec![];
+    for args in &[&["-t", "Note", "text", "big.zip"][..], &["-t", "Secret"][..], &["-t", "Note"][..]] {
+        let record = String::from_utf8(dir.cmd()
+            .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are no configs
+            .args(&["record", "--no-author"])
+            .args(*args)
+            .expect_success().stdout).unwrap();
+        hashes.push(String::from(record.trim()));
+    }
+    dir.create_file("rules.json", r#"{
+      "drop": "files.\".type/Secret\" != null",
+      "remove": ["*.zip"],
+      "replace": [{"files": "text", "from": "hunter2", "to": "[REDACTED]"}]
+    }"#);
+    dir.cmd()
+        .args(&["rebuild", ".sit", "rebuilt", "--rules", "rules.json", "--mapping", "mapping"])
+        .expect_success();
+    let mapping = ::std::fs::read_to_string(dir.path("mapping")).unwrap();
+    let mapping: Vec<Vec<&str>> = mapping.lines().map(|l| l.split(' ').collect()).collect();
+    assert_eq!(mapping.len(), 3);
+    assert_eq!(mapping[1], vec![hashes[1].as_str(), "-"]);
+
+    let repo = Repository::open(dir.path("rebuilt")).unwrap();
+    let records: Vec<_> = repo.record_iter().unwrap().flat_map(|r| r).collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].encoded_hash(), mapping[0][1]);
+    assert_eq!(records[1].encoded_hash(), mapping[2][1]);
+    assert!(!records[0].file_iter().any(|(name, _)| name == "big.zip"));
+    let mut text = String::new();
+    use std::io::Read;
+    records[0].file_iter().find(|(name, _)| name == "text").unwrap().1.read_to_string(&mut text).unwrap();
+    assert_eq!(text, "password [REDACTED]");
+    // the record that followed the dropped one is linked to its parent
+    assert_eq!(records[1].parents(), vec![String::from(mapping[0][1])]);
+    dir.cmd().args(&["-r", "rebuilt", "integrity"]).expect_success();
+}
+
+/// Should fail on invalid rules
+#[test]
+fn rebuild_invalid_rules() {
+    let dir = TestDir::new("sit", "rebuild_invalid_rules");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file("rules.json", r#"{"remove": ["[*"]}"#);
+    dir.cmd()
+        .args(&["rebuild", ".sit", "rebuilt", "--rules", "rules.json"])
+        .expect_failure();
+    assert!(!dir.path("rebuilt").exists());
+}
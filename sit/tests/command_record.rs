@@ -433,6 +433,29 @@ fn record_should_sign_if_instructed_cmdline() {
     assert!(record.file(".signature").is_some());
 }
 
+/// Should link records correctly when many of them are recorded concurrently
+#[test]
+fn record_concurrently() {
+    let dir = TestDir::new("sit", "record_concurrently");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let children: Vec<_> = (0..16).map(|i| {
+        dir.cmd()
+            .args(&["record", "--no-author", "-t", &format!("Type{}", i)])
+            .stdout(process::Stdio::null())
+            .spawn().unwrap()
+    }).collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let generations: Vec<_> = repo.record_iter().unwrap().collect();
+    // every record is linked to the previous one
+    assert_eq!(generations.len(), 16);
+    assert!(generations.iter().all(|records| records.len() == 1));
+}
+
 
 fn verify_authors<S: AsRef<str>>(dir: &TestDir, expected: S) {
     let repo = Repository::open(dir.path(".sit")).unwrap();
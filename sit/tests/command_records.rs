@@ -317,6 +317,61 @@ fn pgp_signature_wrong_data() {
 }
 
 
+/// Should verify PGP signature of records with redacted files
+#[test]
+fn pgp_signature_redacted() {
+    let dir = TestDir::new("sit", "pgpredacted");
+    no_user_config(&dir);
+
+    let gpg = which::which("gpg2").or_else(|_| which::which("gpg")).expect("should have gpg installed");
+
+    let mut genkey = process::Command::new(&gpg)
+        .args(&["--batch", "--gen-key","-"])
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .stdin(::std::process::Stdio::piped())
+        .stdout(::std::process::Stdio::null())
+        .stderr(::std::process::Stdio::null())
+        .spawn().unwrap();
+
+    {
+        use std::io::Write;
+        let stdin = genkey.stdin.as_mut().expect("Failed to open stdin");
+        stdin.write_all(r#"
+        Key-Type: default
+        Subkey-Type: default
+        Name-Real: Test
+        Name-Comment: Test
+        Name-Email: test@test.com
+        Expire-Date: 0
+        %no-protection
+        %commit
+        "#.as_bytes()).expect("Failed to write to stdin");
+    }
+    genkey.expect_success();
+
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+
+    dir.create_file("secret", "password");
+    let record = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap()) // to ensure there are right configs
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .args(&["record", "--sign",  "--signing-key", "test@test.com", "--no-author", "-t","Sometype", "secret"])
+        .expect_success().stdout).unwrap();
+
+    dir.cmd().args(&["redact", record.trim(), "secret"]).expect_success();
+
+    let filter = format!("hash == '{}'", record.trim());
+    let output = String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap())
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .env("GNUPGHOME", dir.path(".").to_str().unwrap())
+        .args(&["records", "-v", "-f", &filter, "-q", "verification.success"]).expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "true");
+}
+
 /// Should not verify PGP key if there is no signature
 #[test]
 fn pgp_no_signature() {
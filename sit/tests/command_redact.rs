@@ -0,0 +1,54 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+extern crate serde_json;
+
+use sit_core::{Repository, record::RecordOwningContainer, Record};
+
+use cli_test_dir::*;
+
+/// Should hide redacted files from records and reducers
+#[test]
+fn redact() {
+    let dir = TestDir::new("sit", "redact");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        var files = Object.keys(record.files).filter(function(f) { return f[0] != "."; });
+        return Object.assign(state, {files: (state.files || []).concat(files).sort()});
+    }
+    "#);
+    let record = Repository::open(dir.path(".sit")).unwrap()
+        .new_record(vec![("secret", &b"password"[..]), ("text", &b"hello"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "-q", "files"]).expect_success().stdout).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(output.trim()).unwrap(), serde_json::json!(["secret", "text"]));
+
+    let redaction = String::from_utf8(dir.cmd().args(&["redact", &record.encoded_hash(), "secret"]).expect_success().stdout).unwrap();
+
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "-q", "files"]).expect_success().stdout).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(output.trim()).unwrap(), serde_json::json!(["text"]));
+    let filter = format!("hash == '{}'", record.encoded_hash());
+    let output = String::from_utf8(dir.cmd().args(&["records", "-f", &filter, "-q", "keys(files)"]).expect_success().stdout).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(output.trim()).unwrap(), serde_json::json!(["text"]));
+
+    // the record itself is intact
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let record = repo.record(record.encoded_hash()).unwrap();
+    assert!(record.file_iter().any(|(name, _)| name == "secret"));
+    assert!(repo.record(redaction.trim()).unwrap().parents().contains(&record.encoded_hash()));
+    dir.cmd().arg("integrity").expect_success();
+}
+
+/// Should refuse to redact files the record doesn't have
+#[test]
+fn redact_missing_file() {
+    let dir = TestDir::new("sit", "redact_missing_file");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let record = Repository::open(dir.path(".sit")).unwrap()
+        .new_record(vec![("text", &b"hello"[..])].into_iter(), true).unwrap();
+    dir.cmd().args(&["redact", &record.encoded_hash(), "secret"]).expect_failure();
+    dir.cmd().args(&["redact", "missing", "text"]).expect_failure();
+}
@@ -221,6 +221,43 @@ fn reduce_repo_fixed_roots() {
     assert_eq!(serde_json::from_str::<serde_json::Value>(output.trim()).unwrap(), serde_json::Value::Object(expect));
 }
 
+/// Should reduce every set of fixed roots in a batch
+#[test]
+fn reduce_repo_batch() {
+    let dir = TestDir::new("sit", "reduce_repo_batch");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        var v = state.value || "";
+        v = v + new TextDecoder('utf-8').decode(record.files.test);
+        return Object.assign(state, {value: v});
+    }
+    "#);
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), false).unwrap();
+    let rec2 = repo.new_record(vec![("test", &b"2"[..])].into_iter(), false).unwrap();
+    let prev = format!(".prev/{}", rec1.encoded_hash());
+    let rec3 = repo.new_record(vec![("test", &b"3"[..]), (prev.as_str(), &b""[..])].into_iter(), false).unwrap();
+    let (hash1, hash2, hash3) = (rec1.encoded_hash(), rec2.encoded_hash(), rec3.encoded_hash());
+    let mut both = vec![hash2.clone(), hash3.clone()];
+    both.sort();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "-q", "value",
+                                                    "--batch", &hash1, "--batch", &hash2,
+                                                    "--batch", &format!("{},{}", hash3, hash2)])
+                                   .expect_success().stdout).unwrap();
+    let result: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+    let result = result.as_object().unwrap();
+    assert_eq!(result.len(), 3);
+    assert_eq!(result[&hash1], serde_json::Value::String("13".into()));
+    assert_eq!(result[&hash2], serde_json::Value::String("2".into()));
+    let value = result[&both.join(",")].as_str().unwrap();
+    assert!(value == "23" || value == "32");
+    // --batch and --root are mutually exclusive
+    dir.cmd().args(&["reduce", "--batch", &hash1, "--root", &hash2]).expect_failure();
+}
+
 /// Should reduce starting with a certain state
 #[test]
 fn reduce_repo_initial_state() {
@@ -244,3 +281,175 @@ fn reduce_repo_initial_state() {
     expect.insert("value".into(), serde_json::Value::String("01".into()));
     assert_eq!(serde_json::from_str::<serde_json::Value>(output.trim()).unwrap(), serde_json::Value::Object(expect));
 }
+
+/// Should reuse cached reduction and only reduce new records
+#[test]
+fn reduce_repo_cache() {
+    let dir = TestDir::new("sit", "reduce_repo_cache");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        var v = state.value || "";
+        v = v + new TextDecoder('utf-8').decode(record.files.test);
+        return Object.assign(state, {value: v});
+    }
+    "#);
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![("test", &b"1"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "-q", "value"]).expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "1");
+    assert!(dir.path(".sit/cache/reductions").is_dir());
+    repo.new_record(vec![("test", &b"2"[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "-q", "value"]).expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "12");
+    // changing reducers invalidates the cache
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        var v = state.value || "";
+        v = v + "-" + new TextDecoder('utf-8').decode(record.files.test);
+        return Object.assign(state, {value: v});
+    }
+    "#);
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "-q", "value"]).expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "-1-2");
+}
+
+/// Should cache states reduced with different error policies separately
+/// and never extend states of stopped reductions
+#[test]
+fn reduce_repo_cache_error_policy() {
+    let dir = TestDir::new("sit", "reduce_repo_cache_error_policy");
+    no_user_config(&dir);
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        var v = new TextDecoder('utf-8').decode(record.files.test);
+        if (v == "1") {
+            throw new Error("first");
+        }
+        return Object.assign(state, {value: (state.value || "") + v});
+    }
+    "#);
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![("test", &b"1"[..])].into_iter(), true).unwrap();
+    repo.new_record(vec![("test", &b"2"[..])].into_iter(), true).unwrap();
+    let reduce = || String::from_utf8(dir.cmd()
+        .env("HOME", dir.path(".").to_str().unwrap())
+        .env("USERPROFILE", dir.path(".").to_str().unwrap())
+        .args(&["reduce", "-q", "value"]).expect_success().stdout).unwrap();
+    user_config(&dir, r#"{"reducers": {"on_error": "skip-record"}}"#);
+    assert_eq!(reduce().trim(), "2");
+    user_config(&dir, r#"{"reducers": {"on_error": "stop"}}"#);
+    assert_eq!(reduce().trim(), "null");
+    // records added after the reduction has stopped are ignored, too
+    repo.new_record(vec![("test", &b"3"[..])].into_iter(), true).unwrap();
+    assert_eq!(reduce().trim(), "null");
+}
+
+/// Should not use cache if asked not to
+#[test]
+fn reduce_repo_no_cache() {
+    let dir = TestDir::new("sit", "reduce_repo_no_cache");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        return Object.assign(state, {value: "hello"});
+    }
+    "#);
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![("test", &b""[..])].into_iter(), true).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "--no-cache", "-q", "value"]).expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "hello");
+    assert!(!dir.path(".sit/cache").exists());
+}
+
+/// Should pick up WebAssembly reducers
+#[test]
+#[cfg(feature = "wasm-reducers")]
+fn reduce_repo_wasm() {
+    let dir = TestDir::new("sit", "reduce_repo_wasm");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    // a module that returns the record it was given as the new state
+    let module: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x0e, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+        0x60, 0x04, 0x7f, 0x7f, 0x7f, 0x7f, 0x01, 0x7e, 0x03, 0x03, 0x02, 0x00, 0x01, 0x05, 0x03, 0x01,
+        0x00, 0x01, 0x06, 0x07, 0x01, 0x7f, 0x01, 0x41, 0x80, 0x08, 0x0b, 0x07, 0x1b, 0x03, 0x06, 0x6d,
+        0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x05, 0x61, 0x6c, 0x6c, 0x6f, 0x63, 0x00, 0x00, 0x06,
+        0x72, 0x65, 0x64, 0x75, 0x63, 0x65, 0x00, 0x01, 0x0a, 0x1a, 0x02, 0x0b, 0x00, 0x23, 0x00, 0x23,
+        0x00, 0x20, 0x00, 0x6a, 0x24, 0x00, 0x0b, 0x0c, 0x00, 0x20, 0x02, 0xad, 0x42, 0x20, 0x86, 0x20,
+        0x03, 0xad, 0x84, 0x0b,
+    ];
+    ::std::fs::create_dir_all(dir.path(".sit/reducers")).unwrap();
+    ::std::fs::write(dir.path(".sit/reducers/test.wasm"), module).unwrap();
+    let record = Repository::open(dir.path(".sit")).unwrap().new_record(vec![("test", &b"hi"[..])].into_iter(), false).unwrap();
+    let output = String::from_utf8(dir.cmd().args(&["reduce", "--no-cache", "-q", "[hash, files.test]"]).expect_success().stdout).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(output.trim()).unwrap(),
+               serde_json::json!([record.encoded_hash(), [104, 105]]));
+}
+
+/// Should print reducers' console output to stderr as JSON lines
+/// when reducing with --debug
+#[test]
+fn reduce_repo_debug() {
+    let dir = TestDir::new("sit", "reduce_repo_debug");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        console.warn("reducing", record.hash);
+        return Object.assign(state, {value: "hello"});
+    }
+    "#);
+    let record = Repository::open(dir.path(".sit")).unwrap().new_record(vec![("test", &b""[..])].into_iter(), true).unwrap();
+    let output = dir.cmd().args(&["reduce", "--debug"]).expect_success();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(stdout.trim()).unwrap(), serde_json::json!({"value": "hello"}));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let entries: Vec<serde_json::Value> = stderr.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["level"], "warn");
+    assert_eq!(entries[0]["message"], format!("reducing {}", record.encoded_hash()));
+    assert_eq!(entries[0]["record"], record.encoded_hash());
+    assert!(entries[0]["file"].as_str().unwrap().ends_with("test.js"));
+}
+
+/// Should not load native reducers unless enabled
+#[test]
+#[cfg(feature = "native-reducers")]
+fn reduce_repo_native_opt_in() {
+    let dir = TestDir::new("sit", "reduce_repo_native_opt_in");
+    no_user_config(&dir);
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.create_file(".sit/reducers/test.js",r#"
+    module.exports = function(state, record) {
+        return Object.assign(state, {value: "hello"});
+    }
+    "#);
+    // not a library, so it fails if it is ever loaded
+    dir.create_file(&format!(".sit/reducers/native.{}", ::std::env::consts::DLL_EXTENSION), "");
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    repo.new_record(vec![("test", &b""[..])].into_iter(), true).unwrap();
+    let reduce = || {
+        let mut cmd = dir.cmd();
+        cmd.env("HOME", dir.path(".").to_str().unwrap())
+           .env("USERPROFILE", dir.path(".").to_str().unwrap())
+           .args(&["reduce", "--no-cache", "-q", "value"]);
+        cmd
+    };
+    let output = String::from_utf8(reduce().expect_success().stdout).unwrap();
+    assert_eq!(output.trim(), "hello");
+    user_config(&dir, r#"{"reducers": {"native": true}}"#);
+    let output = String::from_utf8(reduce().expect_failure().stderr).unwrap();
+    assert!(output.contains("can't load native reducers"));
+}
@@ -0,0 +1,128 @@
+extern crate cli_test_dir;
+
+use cli_test_dir::*;
+
+const REDUCER: &str = r#"
+module.exports = function(state, record) {
+    var parents = Object.keys(record.files).filter(function(name) { return name.indexOf(".prev/") == 0; });
+    var text = record.files.text ? new TextDecoder().decode(record.files.text) : null;
+    return Object.assign(state, {count: (state.count || 0) + 1, parents: (state.parents || 0) + parents.length, text: text});
+}
+"#;
+
+/// Should pass if reducers produce expected states
+#[test]
+fn reducer_test() {
+    let dir = TestDir::new("sit", "reducer_test");
+    dir.create_file("reducers/count.js", REDUCER);
+    dir.create_file("test.json", r#"{
+      "reducers": ["reducers"],
+      "tests": [
+        {"name": "empty", "expect": {}},
+        {"name": "records",
+         "records": [{"id": "a", "files": {"text": "first"}}, {"prev": ["a"], "files": {"text": "second"}}],
+         "expect": {"count": 2, "parents": 1, "text": "second"}},
+        {"name": "queries",
+         "state": {"count": 10},
+         "records": [{"files": {"text": "first"}}],
+         "queries": [{"query": "count", "expect": 11}, {"query": "text", "expect": "first"}]}
+      ]
+    }"#);
+    let output = String::from_utf8(dir.cmd().args(&["reducer", "test", "test.json"]).expect_success().stdout).unwrap();
+    assert!(output.contains("test.json: empty ... ok"));
+    assert!(output.contains("test.json: records ... ok"));
+    assert!(output.contains("test.json: queries ... ok"));
+    assert!(output.contains("3 passed, 0 failed"));
+}
+
+/// Should accept YAML fixtures
+#[test]
+fn reducer_test_yaml() {
+    let dir = TestDir::new("sit", "reducer_test_yaml");
+    dir.create_file("reducers/count.js", REDUCER);
+    dir.create_file("test.yaml", r#"
+reducers: [reducers]
+tests:
+  - name: records
+    records:
+      - id: a
+        files:
+          text: first
+      - id: b
+        prev: [a]
+        files:
+          text: second
+      - prev: [a, b]
+        files:
+          text: third
+    queries:
+      - query: parents
+        expect: 3
+      - query: text
+        expect: third
+"#);
+    dir.cmd().args(&["reducer", "test", "test.yaml"]).expect_success();
+}
+
+/// Should fail if reducers don't produce expected states
+#[test]
+fn reducer_test_failure() {
+    let dir = TestDir::new("sit", "reducer_test_failure");
+    dir.create_file("reducers/count.js", REDUCER);
+    dir.create_file("test.json", r#"{
+      "reducers": ["reducers"],
+      "tests": [
+        {"name": "state", "records": [{"files": {"text": "first"}}], "expect": {"count": 2}},
+        {"name": "query", "records": [{"files": {"text": "first"}}], "queries": [{"query": "count", "expect": 2}]},
+        {"name": "passing", "records": [{"files": {"text": "first"}}], "queries": [{"query": "count", "expect": 1}]}
+      ]
+    }"#);
+    let output = String::from_utf8(dir.cmd().args(&["reducer", "test", "test.json"]).expect_failure().stdout).unwrap();
+    assert!(output.contains("test.json: state ... FAILED"));
+    assert!(output.contains("test.json: query ... FAILED"));
+    assert!(output.contains("expected `count` to be 2, got 1"));
+    assert!(output.contains("test.json: passing ... ok"));
+    assert!(output.contains("1 passed, 2 failed"));
+}
+
+/// Should use reducers specified on the command line
+#[test]
+fn reducer_test_custom_reducers() {
+    let dir = TestDir::new("sit", "reducer_test_custom_reducers");
+    dir.create_file("count.js", REDUCER);
+    dir.create_file("test.json", r#"{
+      "tests": [{"name": "records", "records": [{"files": {"text": "first"}}], "queries": [{"query": "count", "expect": 1}]}]
+    }"#);
+    // no reducers specified
+    dir.cmd().args(&["reducer", "test", "test.json"]).expect_failure();
+    dir.cmd().args(&["reducer", "test", "-r", "count.js", "test.json"]).expect_success();
+}
+
+/// Should fail if a record refers to an undefined parent
+#[test]
+fn reducer_test_undefined_prev() {
+    let dir = TestDir::new("sit", "reducer_test_undefined_prev");
+    dir.create_file("reducers/count.js", REDUCER);
+    dir.create_file("test.json", r#"{
+      "reducers": ["reducers"],
+      "tests": [{"name": "records", "records": [{"prev": ["a"], "files": {"text": "first"}}]}]
+    }"#);
+    let output = String::from_utf8(dir.cmd().args(&["reducer", "test", "test.json"]).expect_failure().stdout).unwrap();
+    assert!(output.contains("test.json: records ... ERROR"));
+}
+
+/// Should report reducers that can't be loaded as errors
+#[test]
+fn reducer_test_syntax_error() {
+    let dir = TestDir::new("sit", "reducer_test_syntax_error");
+    dir.create_file("reducers/broken.js", "module.exports = function(state, record) {");
+    dir.create_file("test.json", r#"{
+      "reducers": ["reducers"],
+      "tests": [{"name": "records", "records": [{"files": {"text": "first"}}]}]
+    }"#);
+    let output = dir.cmd().args(&["reducer", "test", "test.json"]).expect_failure();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("test.json: records ... ERROR"));
+    assert!(stdout.contains("can't load reducers"));
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("panicked"));
+}
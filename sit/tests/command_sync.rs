@@ -0,0 +1,80 @@
+extern crate cli_test_dir;
+extern crate sit_core;
+
+use std::fs;
+use std::io::Write;
+
+use sit_core::{Repository, record::RecordOwningContainer, Record, path::HasPath};
+
+use cli_test_dir::*;
+
+include!("includes/config.rs");
+
+fn lines(output: Vec<u8>) -> Vec<String> {
+    let mut lines: Vec<_> = String::from_utf8(output).unwrap().lines().map(String::from).collect();
+    lines.sort();
+    lines
+}
+
+/// Should copy missing records in both directions
+#[test]
+fn sync() {
+    let dir = TestDir::new("sit", "sync");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let other = Repository::new(dir.path("other").join(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), true).unwrap();
+    let rec2 = other.new_record(vec![("test", &b"2"[..])].into_iter(), true).unwrap();
+    let mut expected = vec![rec1.encoded_hash(), rec2.encoded_hash()];
+    expected.sort();
+    assert_eq!(lines(dir.cmd().args(&["sync", "other"]).expect_success().stdout), expected);
+    assert!(repo.record(rec2.encoded_hash()).is_some());
+    assert!(other.record(rec1.encoded_hash()).is_some());
+    // nothing left to copy
+    assert!(lines(dir.cmd().args(&["sync", "other/.sit"]).expect_success().stdout).is_empty());
+}
+
+/// Should only copy records in one direction if asked to
+#[test]
+fn sync_pull_push() {
+    let dir = TestDir::new("sit", "sync_pull_push");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let other = Repository::new(dir.path("other").join(".sit")).unwrap();
+    let rec1 = repo.new_record(vec![("test", &b"1"[..])].into_iter(), true).unwrap();
+    let rec2 = other.new_record(vec![("test", &b"2"[..])].into_iter(), true).unwrap();
+    assert_eq!(lines(dir.cmd().args(&["sync", "--pull", "other"]).expect_success().stdout), vec![rec2.encoded_hash()]);
+    assert!(other.record(rec1.encoded_hash()).is_none());
+    assert_eq!(lines(dir.cmd().args(&["sync", "--push", "other"]).expect_success().stdout), vec![rec1.encoded_hash()]);
+    assert!(other.record(rec1.encoded_hash()).is_some());
+}
+
+/// Should report invalid records and not copy them
+#[test]
+fn sync_invalid() {
+    let dir = TestDir::new("sit", "sync_invalid");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    let repo = Repository::open(dir.path(".sit")).unwrap();
+    let other = Repository::new(dir.path("other").join(".sit")).unwrap();
+    let rec = other.new_record(vec![("test", &b"1"[..])].into_iter(), true).unwrap();
+    fs::File::create(rec.path().join("test")).unwrap().write_all(b"corrupted").unwrap();
+    let output = dir.cmd().args(&["sync", "--pull", "other"]).expect_failure();
+    assert!(String::from_utf8(output.stderr).unwrap().contains(&format!("Invalid record {}", rec.encoded_hash())));
+    assert!(repo.record(rec.encoded_hash()).is_none());
+}
+
+/// Should fail if the other repository can't be opened
+#[test]
+fn sync_no_repository() {
+    let dir = TestDir::new("sit", "sync_no_repository");
+    dir.cmd()
+        .arg("init")
+        .expect_success();
+    dir.cmd().args(&["sync", "missing"]).expect_failure();
+}